@@ -0,0 +1,67 @@
+// times gen-srf over a generated mod storage path. nimble has no library target, so this drives
+// the binary cargo builds for it. run with `cargo bench`, or `cargo bench -- <runs>`
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const DEFAULT_RUNS: usize = 5;
+
+// a mod with a few large files spanning many parts, lots of small ones and some pbos
+fn write_fixture(base_path: &Path) {
+    let mod_path = base_path.join("@bench");
+    let pbo = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test_files/@ace/addons/ace_advanced_ballistics.pbo");
+
+    std::fs::create_dir_all(mod_path.join("addons")).unwrap();
+    std::fs::create_dir_all(mod_path.join("data")).unwrap();
+
+    for i in 0..4 {
+        let contents: Vec<u8> = (0..32 * 1024 * 1024)
+            .map(|j| ((i + j) % 251) as u8)
+            .collect();
+        std::fs::write(mod_path.join(format!("data/large_{i}.bin")), contents).unwrap();
+    }
+
+    for i in 0..500 {
+        std::fs::write(mod_path.join(format!("data/small_{i}.txt")), i.to_string()).unwrap();
+    }
+
+    for i in 0..20 {
+        std::fs::copy(&pbo, mod_path.join(format!("addons/bench_{i}.pbo"))).unwrap();
+    }
+}
+
+fn main() {
+    // cargo bench passes --bench, anything else is the number of runs
+    let runs = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_RUNS);
+
+    let dir = tempfile::tempdir().unwrap();
+    write_fixture(dir.path());
+
+    let mut times = Vec::with_capacity(runs);
+
+    for _ in 0..runs {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_nimble"))
+            .args(["gen-srf", "--force", "--quiet", "--path"])
+            .arg(dir.path())
+            .status()
+            .unwrap();
+        times.push(start.elapsed());
+
+        assert!(status.success(), "gen-srf failed: {status}");
+    }
+
+    times.sort();
+    let total: Duration = times.iter().sum();
+
+    println!(
+        "gen-srf over {runs} runs: min {:?}, median {:?}, mean {:?}",
+        times[0],
+        times[times.len() / 2],
+        total / runs as u32
+    );
+}
@@ -0,0 +1,281 @@
+use hex::FromHexError;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+use snafu::{ensure, ResultExt, Snafu};
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{Read, Write};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("hex digest decode error: {}", source))]
+    HexDecode { source: FromHexError },
+    #[snafu(display("a checksum has 32 (md5) or 64 (sha256) hex digits, got {}", got))]
+    WrongLength { got: usize },
+}
+
+// what a repo's checksums are made with. swifty only knows md5, which stays the default so
+// existing repos work unchanged
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Algorithm {
+    #[default]
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    // digest length in bytes
+    fn len(self) -> u8 {
+        match self {
+            Self::Md5 => 16,
+            Self::Sha256 => 32,
+        }
+    }
+
+    pub fn is_md5(&self) -> bool {
+        *self == Self::Md5
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Md5 => Hasher::Md5(Md5::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn digest(self, data: impl AsRef<[u8]>) -> Checksum {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+
+        hasher.finalize()
+    }
+}
+
+// an Algorithm's hasher, usable as an io::Write sink
+#[derive(Clone)]
+pub enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        match self {
+            Self::Md5(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Checksum {
+        match self {
+            Self::Md5(hasher) => Checksum::from_bytes(&hasher.finalize()),
+            Self::Sha256(hasher) => Checksum::from_bytes(&hasher.finalize()),
+        }
+    }
+
+    // finalizes and starts over with the same algorithm
+    pub fn finalize_reset(&mut self) -> Checksum {
+        match self {
+            Self::Md5(hasher) => Checksum::from_bytes(&hasher.finalize_reset()),
+            Self::Sha256(hasher) => Checksum::from_bytes(&hasher.finalize_reset()),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// a digest made with any Algorithm, which one follows from its length. unused bytes stay zero, so
+// the derived comparisons only ever see the digest itself
+#[derive(Hash, PartialEq, Eq, Clone)]
+pub struct Checksum {
+    inner: [u8; 32],
+    // a u8 keeps errors carrying a couple of these small
+    len: u8,
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self {
+            inner: [0; 32],
+            len: Algorithm::Md5.len(),
+        }
+    }
+}
+
+impl Checksum {
+    pub fn new(digest: &str) -> Result<Self, Error> {
+        // legacy srfs sometimes carry trailing whitespace
+        let digest = digest.trim();
+        let got = digest.chars().count();
+        ensure!(
+            [Algorithm::Md5, Algorithm::Sha256]
+                .iter()
+                .any(|algorithm| got == usize::from(algorithm.len()) * 2),
+            WrongLengthSnafu { got }
+        );
+
+        let mut inner = [0; 32];
+        hex::decode_to_slice(digest, &mut inner[..got / 2]).context(HexDecodeSnafu)?;
+
+        Ok(Self {
+            inner,
+            len: (got / 2) as u8,
+        })
+    }
+
+    // panics if bytes is longer than any digest we know
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut inner = [0; 32];
+        inner[..bytes.len()].copy_from_slice(bytes);
+
+        Self {
+            inner,
+            len: bytes.len() as u8,
+        }
+    }
+
+    // digest of everything input yields, hashed as it streams in
+    pub fn from_reader<R: Read + ?Sized>(
+        algorithm: Algorithm,
+        input: &mut R,
+    ) -> std::io::Result<Self> {
+        let mut hasher = algorithm.hasher();
+        std::io::copy(input, &mut hasher)?;
+
+        Ok(hasher.finalize())
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        if self.len == Algorithm::Sha256.len() {
+            Algorithm::Sha256
+        } else {
+            Algorithm::Md5
+        }
+    }
+
+    // looks at every byte, wherever the first difference is, so how long it takes doesn't tell
+    // how much of the digest matched. == is fine for lookups, this is for checking things
+    pub fn verify(&self, other: &Checksum) -> bool {
+        let difference = self
+            .inner
+            .iter()
+            .zip(&other.inner)
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+
+        std::hint::black_box(difference) == 0 && self.len == other.len
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner[..usize::from(self.len)]
+    }
+
+    // how swifty writes digests, and how we show them
+    pub fn to_hex_upper(&self) -> String {
+        hex::encode_upper(self.as_bytes())
+    }
+
+    pub fn to_hex_lower(&self) -> String {
+        hex::encode(self.as_bytes())
+    }
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_hex_upper())
+    }
+}
+
+impl Serialize for Checksum {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_hex_upper())
+    }
+}
+
+impl<'de> Deserialize<'de> for Checksum {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let digest = String::deserialize(deserializer)?;
+
+        Self::new(&digest).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Debug for Checksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Checksum")
+            .field("inner", &self.to_hex_upper())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_test() {
+        let digest = Checksum::new("D41D8CD98F00B204E9800998ECF8427E").unwrap();
+
+        assert_eq!(digest.to_hex_upper(), "D41D8CD98F00B204E9800998ECF8427E");
+        assert_eq!(digest.to_hex_lower(), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(digest.to_string(), "D41D8CD98F00B204E9800998ECF8427E");
+        assert_eq!(
+            serde_json::to_string(&digest).unwrap(),
+            "\"D41D8CD98F00B204E9800998ECF8427E\""
+        );
+    }
+
+    #[test]
+    fn from_reader_test() {
+        let digest = Checksum::from_reader(Algorithm::Md5, &mut &b"hello"[..]).unwrap();
+
+        assert_eq!(digest.to_hex_upper(), "5D41402ABC4B2A76B9719D911017C592");
+        assert!(digest.verify(&Checksum::new("5D41402ABC4B2A76B9719D911017C592").unwrap()));
+        assert!(!digest.verify(&Checksum::default()));
+
+        let digest = Checksum::from_reader(Algorithm::Sha256, &mut &b"hello"[..]).unwrap();
+
+        assert_eq!(
+            digest.to_hex_lower(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(digest.algorithm(), Algorithm::Sha256);
+        assert_eq!(Checksum::new(&digest.to_hex_upper()).unwrap(), digest);
+        assert!(!digest.verify(&Algorithm::Md5.digest("hello")));
+    }
+
+    #[test]
+    fn parse_test() {
+        let digest = Checksum::new("D41D8CD98F00B204E9800998ECF8427E").unwrap();
+
+        assert_eq!(
+            Checksum::new(" d41d8cd98f00B204E9800998ECF8427E\r\n").unwrap(),
+            digest
+        );
+        assert!(matches!(
+            Checksum::new("D41D8CD98F00B204E9800998ECF8427"),
+            Err(Error::WrongLength { got: 31 })
+        ));
+        assert!(matches!(
+            Checksum::new("Z41D8CD98F00B204E9800998ECF8427E"),
+            Err(Error::HexDecode { .. })
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::commands::sync::{self, fetch_mirrored_srf};
+use crate::output::format_size;
+use crate::{http, repo_history, repository};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Failed to fetch repository info: {}", source))]
+    RepositoryFetch { source: repository::Error },
+    #[snafu(display("Failed to load repository snapshot: {}", source))]
+    History { source: repo_history::Error },
+    #[snafu(display("Failed to fetch mod.srf: {}", source))]
+    RemoteSrf { source: sync::Error },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change<'a> {
+    Added(&'a repository::Mod),
+    Updated(&'a repository::Mod),
+    Removed(&'a repository::Mod),
+}
+
+pub fn diff_repositories<'a>(
+    old: &'a repository::Repository,
+    new: &'a repository::Repository,
+) -> Vec<Change<'a>> {
+    // a mod moving between required and optional isn't a change to its contents
+    let mut old_mods: HashMap<_, _> = old
+        .required_mods
+        .iter()
+        .chain(&old.optional_mods)
+        .map(|r#mod| (r#mod.mod_name.to_lowercase(), r#mod))
+        .collect();
+
+    let mut changes = Vec::new();
+
+    for r#mod in new.required_mods.iter().chain(&new.optional_mods) {
+        match old_mods.remove(&r#mod.mod_name.to_lowercase()) {
+            Some(old_mod) if old_mod.checksum == r#mod.checksum => {}
+            Some(_) => changes.push(Change::Updated(r#mod)),
+            None => changes.push(Change::Added(r#mod)),
+        }
+    }
+
+    let mut removed: Vec<_> = old_mods.into_values().collect();
+    removed.sort_by(|a, b| a.mod_name.cmp(&b.mod_name));
+    changes.extend(removed.into_iter().map(Change::Removed));
+
+    changes
+}
+
+pub fn changelog(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    cache_path: &Path,
+    from: &str,
+) -> Result<(), Error> {
+    let old_repo =
+        repo_history::load(&repo_history::history_dir(cache_path), from).context(HistorySnafu)?;
+
+    let new_repo = repository::get_mirrored_repository_info(
+        client,
+        mirrors,
+        repository::DEFAULT_MAX_REPO_SIZE,
+    )
+    .context(RepositoryFetchSnafu)?;
+    let client = &new_repo.authorized_client(client);
+
+    println!("changes from {} to {}:", old_repo.version, new_repo.version);
+
+    for change in diff_repositories(&old_repo, &new_repo) {
+        let (kind, r#mod) = match change {
+            Change::Added(r#mod) => ("added", r#mod),
+            Change::Updated(r#mod) => ("updated", r#mod),
+            Change::Removed(r#mod) => {
+                println!("  {:<8} {}", "removed", r#mod.mod_name);
+                continue;
+            }
+        };
+
+        let srf = fetch_mirrored_srf(client, mirrors, &r#mod.mod_name).context(RemoteSrfSnafu)?;
+        let size = srf.files.iter().map(|file| file.length).sum();
+
+        println!("  {:<8} {} ({})", kind, r#mod.mod_name, format_size(size));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Checksum;
+
+    fn mods(mods: &[(&str, &str)]) -> Vec<repository::Mod> {
+        mods.iter()
+            .map(|(name, checksum)| repository::Mod {
+                mod_name: name.to_string(),
+                checksum: Checksum::new(checksum).unwrap(),
+                enabled: true,
+            })
+            .collect()
+    }
+
+    fn repo(required: &[(&str, &str)], optional: &[(&str, &str)]) -> repository::Repository {
+        repository::Repository {
+            repo_name: "test".to_string(),
+            checksum: String::new(),
+            required_mods: mods(required),
+            optional_mods: mods(optional),
+            client_parameters: String::new(),
+            repo_basic_authentication: None,
+            version: "1".to_string(),
+            servers: vec![],
+        }
+    }
+
+    #[test]
+    fn diff_repositories_test() {
+        let a = "00000000000000000000000000000000";
+        let b = "11111111111111111111111111111111";
+
+        let old = repo(
+            &[("@ace", a), ("@cba", a), ("@tfar", a)],
+            &[("@jsrs", a), ("@blastcore", a), ("@dui", a)],
+        );
+        let new = repo(
+            &[("@ace", a), ("@cba", b), ("@rhs", a), ("@dui", a)],
+            &[("@jsrs", b), ("@enhanced_movement", a)],
+        );
+
+        assert_eq!(
+            diff_repositories(&old, &new),
+            vec![
+                Change::Updated(&new.required_mods[1]),
+                Change::Added(&new.required_mods[2]),
+                Change::Updated(&new.optional_mods[0]),
+                Change::Added(&new.optional_mods[1]),
+                Change::Removed(&old.optional_mods[1]),
+                Change::Removed(&old.required_mods[2]),
+            ]
+        );
+    }
+}
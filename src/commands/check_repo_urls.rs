@@ -0,0 +1,89 @@
+use crate::commands::sync::{self, fetch_mirrored_srf};
+use crate::{http, repository};
+use log::info;
+use snafu::{ensure, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Failed to fetch repository info: {}", source))]
+    RepositoryFetch { source: repository::Error },
+    #[snafu(display("{} of the repository's urls are unreachable", count))]
+    Unreachable { count: usize },
+}
+
+fn check_url(client: &http::Client, mirrors: &http::Mirrors, path: &str) -> Result<(), String> {
+    let res = match mirrors.call(client, path, |url| client.head(url)) {
+        // not every static file host implements HEAD, fall back to a GET whose body we never read
+        Err(ureq::Error::Status(405 | 501, _)) => {
+            mirrors.call(client, path, |url| client.get_metadata(url))
+        }
+        res => res,
+    };
+
+    // ureq errors already mention the url
+    res.map(drop).map_err(|e| e.to_string())
+}
+
+fn check_mod(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    r#mod: &repository::Mod,
+    sample_files: bool,
+) -> Result<(), String> {
+    let srf_path = format!("{}/mod.srf", r#mod.mod_name);
+
+    check_url(client, mirrors, &srf_path)?;
+
+    if sample_files {
+        let srf = fetch_mirrored_srf(client, mirrors, &r#mod.mod_name)
+            .map_err(|e: sync::Error| format!("{}: {e}", mirrors.url(&srf_path)))?;
+
+        if let Some(file) = srf.files.first() {
+            check_url(
+                client,
+                mirrors,
+                &format!("{}/{}", r#mod.mod_name, file.path),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn check_repo_urls(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    sample_files: bool,
+) -> Result<(), Error> {
+    let repo = repository::get_mirrored_repository_info(
+        client,
+        mirrors,
+        repository::DEFAULT_MAX_REPO_SIZE,
+    )
+    .context(RepositoryFetchSnafu)?;
+    let client = &repo.authorized_client(client);
+
+    let mods: Vec<_> = repo
+        .required_mods
+        .iter()
+        .chain(&repo.optional_mods)
+        .collect();
+
+    let mut unreachable: usize = 0;
+
+    for r#mod in &mods {
+        match check_mod(client, mirrors, r#mod, sample_files) {
+            Ok(()) => info!("{}: ok", r#mod.mod_name),
+            Err(e) => {
+                eprintln!("{}: {}", r#mod.mod_name, e);
+                unreachable += 1;
+            }
+        }
+    }
+
+    info!("checked {} mods, {} unreachable", mods.len(), unreachable);
+
+    ensure!(unreachable == 0, UnreachableSnafu { count: unreachable });
+
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use crate::commands::ModDirs;
+use crate::mod_cache::ModCache;
+use crate::output::format_size;
+use crate::{mod_cache, partial_downloads};
+use snafu::{ResultExt, Snafu};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to open ModCache: {}", source))]
+    ModCacheOpen { source: mod_cache::Error },
+    #[snafu(display("failed to remove {}: {}", path.display(), source))]
+    Remove {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct CleanOptions {
+    // remove what was found instead of only listing it
+    pub yes: bool,
+    // remove the mod cache too, so the next command rebuilds it from disk
+    pub cache: bool,
+    pub mod_dirs: ModDirs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    OrphanedMod,
+    PartialDownloads,
+    TempFile,
+    Cache,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::OrphanedMod => "orphaned mod",
+            Self::PartialDownloads => "partial downloads",
+            Self::TempFile => "temp file",
+            Self::Cache => "cache",
+        };
+
+        f.pad(description)
+    }
+}
+
+#[derive(Debug)]
+struct Leftover {
+    kind: Kind,
+    path: PathBuf,
+    size: u64,
+}
+
+// tempfile names its files .tmp followed by 6 random characters. anything else is left alone, even
+// if it happens to start with .tmp
+fn is_temp_file(name: &str) -> bool {
+    name.strip_prefix(".tmp")
+        .is_some_and(|rest| rest.len() == 6 && rest.chars().all(|c| c.is_ascii_alphanumeric()))
+        || name.starts_with("nimble-case-probe-")
+}
+
+fn size_of(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn find_leftovers(
+    base_path: &Path,
+    cache_path: &Path,
+    mod_cache: &ModCache,
+    options: &CleanOptions,
+) -> Vec<Leftover> {
+    let cached = |name: &str| {
+        mod_cache
+            .mods
+            .values()
+            .any(|r#mod| r#mod.name.eq_ignore_ascii_case(name))
+    };
+    let leftover = |kind, path: PathBuf| Leftover {
+        kind,
+        size: size_of(&path),
+        path,
+    };
+
+    let mut leftovers: Vec<_> = options
+        .mod_dirs
+        .walk(base_path)
+        .filter(|e| !cached(&e.file_name().to_string_lossy()))
+        .map(|e| leftover(Kind::OrphanedMod, e.into_path()))
+        .collect();
+
+    for path in [
+        partial_downloads::data_dir(base_path),
+        partial_downloads::record_path(cache_path),
+    ] {
+        if path.exists() {
+            leftovers.push(leftover(Kind::PartialDownloads, path));
+        }
+    }
+
+    // temp files end up next to whatever they were going to replace, so only the mod storage path
+    // itself and the mods we manage need looking at
+    let temp_files = WalkDir::new(base_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() != 1 || !e.file_type().is_dir() || cached(&e.file_name().to_string_lossy())
+        })
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file() && is_temp_file(&e.file_name().to_string_lossy()));
+
+    for entry in temp_files {
+        leftovers.push(leftover(Kind::TempFile, entry.into_path()));
+    }
+
+    if options.cache && cache_path.exists() {
+        leftovers.push(leftover(Kind::Cache, cache_path.to_path_buf()));
+    }
+
+    leftovers
+}
+
+pub fn clean(base_path: &Path, cache_path: &Path, options: &CleanOptions) -> Result<(), Error> {
+    // without a cache every mod would look orphaned, so don't make one up
+    let mod_cache = ModCache::from_disk(cache_path).context(ModCacheOpenSnafu)?;
+
+    let leftovers = find_leftovers(base_path, cache_path, &mod_cache, options);
+
+    if leftovers.is_empty() {
+        println!("nothing to clean");
+        return Ok(());
+    }
+
+    for leftover in &leftovers {
+        println!(
+            "{:<17}  {} ({})",
+            leftover.kind,
+            leftover.path.display(),
+            format_size(leftover.size)
+        );
+    }
+
+    let total = leftovers.iter().map(|leftover| leftover.size).sum();
+
+    if !options.yes {
+        println!(
+            "{} can be reclaimed, pass --yes to remove these",
+            format_size(total)
+        );
+        return Ok(());
+    }
+
+    for leftover in &leftovers {
+        let path = &leftover.path;
+
+        if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        }
+        .context(RemoveSnafu { path })?;
+    }
+
+    println!("reclaimed {}", format_size(total));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::{Algorithm, Checksum};
+    use crate::srf;
+
+    #[test]
+    fn find_leftovers_test() {
+        let base = tempfile::tempdir().unwrap();
+        let base_path = base.path();
+        let cache_path = mod_cache::default_path(base_path);
+
+        for dir in ["@synced", "@orphan", "keys", ".nimble-partial"] {
+            std::fs::create_dir(base_path.join(dir)).unwrap();
+        }
+        std::fs::write(base_path.join("@orphan/a.pbo"), b"abc").unwrap();
+        std::fs::write(base_path.join("@synced/.tmpAb12Cd"), b"ab").unwrap();
+        std::fs::write(base_path.join("@synced/.tmp_notes.txt"), b"keep").unwrap();
+        std::fs::write(base_path.join("keys/.tmpAb12Cd"), b"keep").unwrap();
+        std::fs::write(base_path.join(".nimble-partial/0123"), b"a").unwrap();
+
+        let mod_cache = ModCache::from_mods([srf::Mod {
+            name: "@Synced".to_string(),
+            checksum: Checksum::default(),
+            algorithm: Algorithm::Md5,
+            files: vec![],
+        }]);
+        mod_cache.to_disk(&cache_path).unwrap();
+
+        let found = |cache| {
+            let options = CleanOptions {
+                cache,
+                ..CleanOptions::default()
+            };
+
+            find_leftovers(base_path, &cache_path, &mod_cache, &options)
+                .into_iter()
+                .map(|leftover| (leftover.kind, leftover.path, leftover.size))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            found(false),
+            [
+                (Kind::OrphanedMod, base_path.join("@orphan"), 3),
+                (Kind::PartialDownloads, base_path.join(".nimble-partial"), 1),
+                (Kind::TempFile, base_path.join("@synced/.tmpAb12Cd"), 2),
+            ]
+        );
+        assert_eq!(found(true).last().unwrap().0, Kind::Cache);
+    }
+}
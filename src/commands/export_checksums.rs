@@ -0,0 +1,162 @@
+use crate::checksum::Checksum;
+use crate::commands::{find_mod_dir, ModDirs};
+use crate::srf;
+use rayon::prelude::*;
+use serde::Serialize;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("no mod named {} found in {}", name, base_path.display()))]
+    UnknownMod { name: String, base_path: PathBuf },
+    #[snafu(display("failed to scan mod: {}", source))]
+    Scan { source: srf::Error },
+    #[snafu(display("io error: {}", source))]
+    Io { source: std::io::Error },
+    #[snafu(display("failed to serialize checksums: {}", source))]
+    Serialization { source: serde_json::Error },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+// one row per part, so external tools don't need to understand the srf nesting
+#[derive(Debug, Serialize)]
+struct PartRow<'a> {
+    #[serde(rename = "mod")]
+    mod_name: &'a str,
+    file: &'a str,
+    part: &'a str,
+    start: u64,
+    length: u64,
+    checksum: &'a Checksum,
+}
+
+fn rows(mods: &[srf::Mod]) -> impl Iterator<Item = PartRow<'_>> {
+    mods.iter().flat_map(|r#mod| {
+        r#mod.files.iter().flat_map(move |file| {
+            file.parts.iter().map(move |part| PartRow {
+                mod_name: &r#mod.name,
+                file: file.path.as_str(),
+                part: &part.path,
+                start: part.start,
+                length: part.length,
+                checksum: &part.checksum,
+            })
+        })
+    })
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn write_csv<W: Write>(mods: &[srf::Mod], output: &mut W) -> std::io::Result<()> {
+    writeln!(output, "mod,file,part,start,length,checksum")?;
+
+    for row in rows(mods) {
+        writeln!(
+            output,
+            "{},{},{},{},{},{}",
+            csv_field(row.mod_name),
+            csv_field(row.file),
+            csv_field(row.part),
+            row.start,
+            row.length,
+            row.checksum
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn export_checksums(
+    base_path: &Path,
+    mod_name: Option<&str>,
+    format: Format,
+    hashing: srf::PboHashing,
+    mod_dirs: &ModDirs,
+) -> Result<(), Error> {
+    let mod_paths = match mod_name {
+        Some(name) => {
+            vec![find_mod_dir(base_path, name).context(UnknownModSnafu { name, base_path })?]
+        }
+        None => mod_dirs.walk(base_path).map(|e| e.into_path()).collect(),
+    };
+
+    // scan instead of reading mod.srf, so the output always matches what is on disk
+    let mods: Vec<srf::Mod> = mod_paths
+        .par_iter()
+        .map(|path| srf::scan_mod_with(path, hashing))
+        .collect::<Result<_, _>>()
+        .context(ScanSnafu)?;
+
+    let mut stdout = std::io::stdout().lock();
+
+    match format {
+        Format::Json => {
+            let rows: Vec<_> = rows(&mods).collect();
+            serde_json::to_writer_pretty(&mut stdout, &rows).context(SerializationSnafu)?;
+            writeln!(stdout).context(IoSnafu)?;
+        }
+        Format::Csv => write_csv(&mods, &mut stdout).context(IoSnafu)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Algorithm;
+    use relative_path::RelativePathBuf;
+
+    #[test]
+    fn write_csv_test() {
+        let r#mod = srf::Mod {
+            name: "@test".to_string(),
+            checksum: Checksum::new("D41D8CD98F00B204E9800998ECF8427E").unwrap(),
+            algorithm: Algorithm::Md5,
+            files: vec![srf::File {
+                path: RelativePathBuf::from("addons/a, \"b\".txt"),
+                length: 6,
+                checksum: Checksum::default(),
+                r#type: srf::FileType::File,
+                extensions: None,
+                parts: vec![
+                    srf::Part {
+                        path: "a, \"b\".txt_0".to_string(),
+                        length: 3,
+                        start: 0,
+                        checksum: Checksum::from_bytes(&[0xAA; 16]),
+                    },
+                    srf::Part {
+                        path: "a, \"b\".txt_3".to_string(),
+                        length: 3,
+                        start: 3,
+                        checksum: Checksum::from_bytes(&[0xBB; 16]),
+                    },
+                ],
+            }],
+        };
+
+        let mut output = Vec::new();
+        write_csv(&[r#mod], &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "mod,file,part,start,length,checksum\n\
+             @test,\"addons/a, \"\"b\"\".txt\",\"a, \"\"b\"\".txt_0\",0,3,AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n\
+             @test,\"addons/a, \"\"b\"\".txt\",\"a, \"\"b\"\".txt_3\",3,3,BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB\n"
+        );
+    }
+}
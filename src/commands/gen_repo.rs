@@ -0,0 +1,181 @@
+use crate::checksum::Algorithm;
+use crate::commands::ModDirs;
+use crate::mod_source::{self, ModSource};
+use crate::repository::{self, BasicAuth, Repository, Server};
+use crate::srf;
+use rayon::prelude::*;
+use snafu::{ResultExt, Snafu};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    Scan { source: mod_source::Error },
+    #[snafu(display("failed to write {}: {}", path.display(), source))]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to serialize repo.json: {}", source))]
+    Serialization { source: serde_json::Error },
+}
+
+#[derive(Debug, Default)]
+pub struct GenRepoOptions {
+    pub repo_name: String,
+    pub version: String,
+    pub client_parameters: String,
+    // credentials clients should use for the mods
+    pub basic_auth: Option<BasicAuth>,
+    pub servers: Vec<Server>,
+    pub mod_dirs: ModDirs,
+}
+
+// parses the name,address,port[,password[,battleye]] given to --server
+pub fn parse_server(spec: &str) -> Result<Server, String> {
+    let mut fields = spec.split(',');
+    let mut next = |what: &str| {
+        fields
+            .next()
+            .filter(|field| !field.is_empty())
+            .ok_or(format!("missing the server's {what}"))
+    };
+
+    let name = next("name")?.to_string();
+    let address = next("address")?;
+    let address = address
+        .parse()
+        .map_err(|_| format!("{address} isn't an ip address"))?;
+    let port = next("port")?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("{port} isn't a port number"))?;
+    let password = next("password").unwrap_or_default().to_string();
+    let battle_eye = next("battleye").is_ok_and(|field| field.eq_ignore_ascii_case("battleye"));
+
+    Ok(Server {
+        name,
+        address,
+        port,
+        password,
+        battle_eye,
+    })
+}
+
+// nimble never reads the repo's checksum, so it's derived from the mods. that way it changes
+// whenever they do, which is all a client could use it for
+fn repo_checksum(mods: &[repository::Mod]) -> String {
+    let mut hasher = Algorithm::Md5.hasher();
+
+    for r#mod in mods {
+        hasher.update(r#mod.checksum.to_string());
+    }
+
+    hasher.finalize().to_hex_upper()
+}
+
+// mod directories, along with mods published as <name>.zip archives next to them
+fn mod_sources(base_path: &Path, mod_dirs: &ModDirs) -> Vec<ModSource> {
+    let mut sources: Vec<_> = mod_dirs
+        .walk(base_path)
+        .map(|e| ModSource::Directory(e.into_path()))
+        .collect();
+
+    let archives = WalkDir::new(base_path)
+        .min_depth(1)
+        .max_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .map(|e| ModSource::new(e.path()))
+        .filter(|source| matches!(source, ModSource::Archive(_)))
+        .filter(|source| mod_dirs.is_mod_dir(&source.name()));
+
+    sources.extend(archives);
+
+    sources
+}
+
+fn scan_mods(base_path: &Path, mod_dirs: &ModDirs) -> Result<Vec<repository::Mod>, Error> {
+    let sources = mod_sources(base_path, mod_dirs);
+
+    sources
+        .par_iter()
+        .map(|source| {
+            let (srf, _) = source
+                .scan(&srf::ScanOptions::default())
+                .context(ScanSnafu)?;
+
+            // scanning lowercases the name, the repo keeps the directory's own
+            Ok(repository::Mod {
+                mod_name: source.name(),
+                checksum: srf.checksum,
+                enabled: true,
+            })
+        })
+        .collect()
+}
+
+pub fn gen_repo(base_path: &Path, out: &Path, options: &GenRepoOptions) -> Result<(), Error> {
+    let required_mods = scan_mods(base_path, &options.mod_dirs)?;
+
+    let repo = Repository {
+        repo_name: options.repo_name.clone(),
+        checksum: repo_checksum(&required_mods),
+        required_mods,
+        optional_mods: vec![],
+        client_parameters: options.client_parameters.clone(),
+        repo_basic_authentication: options.basic_auth.clone(),
+        version: options.version.clone(),
+        servers: options.servers.clone(),
+    };
+
+    let file = File::create(out).context(WriteSnafu { path: out })?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, &repo).context(SerializationSnafu)?;
+    writer.flush().context(WriteSnafu { path: out })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gen_repo_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("@Test")).unwrap();
+        std::fs::write(dir.path().join("@Test/a.txt"), "a").unwrap();
+        std::fs::create_dir(dir.path().join("keys")).unwrap();
+
+        let out = dir.path().join("repo.json");
+        let options = GenRepoOptions {
+            repo_name: "test".to_string(),
+            servers: vec![parse_server("main,127.0.0.1,2302,hunter2,battleye").unwrap()],
+            ..GenRepoOptions::default()
+        };
+        gen_repo(dir.path(), &out, &options).unwrap();
+
+        let repo: Repository = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+        let srf = srf::scan_mod(&dir.path().join("@Test")).unwrap();
+
+        assert_eq!(
+            repo.required_mods,
+            [repository::Mod {
+                mod_name: "@Test".to_string(),
+                checksum: srf.checksum,
+                enabled: true,
+            }]
+        );
+        assert_eq!(repo.servers[0].port, 2302);
+        assert!(repo.servers[0].battle_eye);
+
+        assert!(parse_server("main,127.0.0.1").is_err());
+        assert!(!parse_server("main,127.0.0.1,2302").unwrap().battle_eye);
+    }
+}
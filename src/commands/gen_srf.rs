@@ -1,55 +1,329 @@
-use crate::md5_digest::Md5Digest;
+use crate::commands::{find_mod_dir, ModDirs};
 use crate::mod_cache::ModCache;
 use crate::{mod_cache, srf};
+use log::{info, warn};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
-use std::io::BufWriter;
-use std::path::Path;
-use walkdir::WalkDir;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-pub fn gen_srf_for_mod(mod_path: &Path) -> srf::Mod {
-    let generated_srf = srf::scan_mod(mod_path).unwrap();
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to scan {}: {}", path.display(), source))]
+    Scan { path: PathBuf, source: srf::Error },
+    #[snafu(display("failed to write {}: {}", path.display(), source))]
+    SrfWrite {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("serde failed to serialize: {}", source))]
+    SrfSerialization { source: serde_json::Error },
+    #[snafu(display("failed to build thread pool: {}", source))]
+    ThreadPool { source: rayon::ThreadPoolBuildError },
+    #[snafu(display("{}", source))]
+    ModCacheOpen { source: mod_cache::Error },
+    #[snafu(display("failed to write ModCache: {}", source))]
+    ModCacheWrite { source: mod_cache::Error },
+}
+
+#[derive(Debug, Default)]
+pub struct GenSrfOptions {
+    // write mod.srf in swifty's legacy format
+    pub legacy: bool,
+    // rescan every mod, even ones whose manifest says they didn't change
+    pub force: bool,
+    // threads shared by all mods and their files, defaults to the number of CPUs
+    pub jobs: Option<usize>,
+    pub mod_dirs: ModDirs,
+    // part size and how many unreadable files are left out of a mod instead of failing it
+    pub scan: srf::ScanOptions,
+}
+
+// size and modification time of every file in a mod as of its last scan. comparing these is
+// enough to tell that a mod doesn't need to be hashed again
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Manifest {
+    files: BTreeMap<String, (u64, SystemTime)>,
+}
+
+impl Manifest {
+    fn scan(mod_path: &Path) -> std::io::Result<Self> {
+        let mut files = BTreeMap::new();
+
+        for path in srf::mod_files(mod_path) {
+            let metadata = path.metadata()?;
+            let relative = path.strip_prefix(mod_path).unwrap_or(&path);
+
+            files.insert(
+                relative.to_string_lossy().replace('\\', "/"),
+                (metadata.len(), metadata.modified()?),
+            );
+        }
+
+        Ok(Self { files })
+    }
+}
+
+pub fn gen_srf_for_mod(mod_path: &Path, legacy: bool) -> Result<srf::Mod, Error> {
+    gen_srf_for_mod_with(mod_path, legacy, &srf::ScanOptions::default())
+}
+
+pub fn gen_srf_for_mod_with(
+    mod_path: &Path,
+    legacy: bool,
+    scan: &srf::ScanOptions,
+) -> Result<srf::Mod, Error> {
+    // taken before hashing, so anything touched mid scan gets rescanned next time
+    let manifest = Manifest::scan(mod_path);
+    let (generated_srf, warnings) =
+        srf::scan_mod_with_options(mod_path, scan).context(ScanSnafu { path: mod_path })?;
+
+    for warning in &warnings {
+        warn!("{}: {warning}", mod_path.display());
+    }
 
     let path = mod_path.join("mod.srf");
 
-    let writer = BufWriter::new(File::create(path).unwrap());
-    serde_json::to_writer(writer, &generated_srf).unwrap();
+    let mut writer = BufWriter::new(File::create(&path).context(SrfWriteSnafu { path: &path })?);
 
-    generated_srf
+    if legacy {
+        srf::serialize_legacy_srf(&generated_srf, &mut writer)
+            .context(SrfWriteSnafu { path: &path })?;
+    } else {
+        serde_json::to_writer(writer, &generated_srf).context(SrfSerializationSnafu)?;
+    }
+
+    let manifest_path = mod_path.join(srf::MANIFEST_FILE_NAME);
+
+    // a mod with skipped files has to be scanned again next time, whether or not they change
+    if !warnings.is_empty() {
+        let _ = std::fs::remove_file(&manifest_path);
+        return Ok(generated_srf);
+    }
+
+    let written = manifest.and_then(|manifest| {
+        std::fs::write(&manifest_path, serde_json::to_vec(&manifest)?)?;
+        Ok(())
+    });
+
+    if let Err(e) = written {
+        warn!("failed to write {}: {e}", manifest_path.display());
+    }
+
+    Ok(generated_srf)
 }
 
-pub fn open_cache_or_gen_srf(base_path: &Path) -> Result<ModCache, mod_cache::Error> {
-    match ModCache::from_disk(base_path) {
-        Ok(cache) => Ok(cache),
+// returns the existing mod.srf if no file in the mod changed size or modification time since it
+// was written
+fn read_current_srf(mod_path: &Path) -> Option<srf::Mod> {
+    let manifest_file = File::open(mod_path.join(srf::MANIFEST_FILE_NAME)).ok()?;
+    let manifest: Manifest = serde_json::from_reader(BufReader::new(manifest_file)).ok()?;
+
+    if Manifest::scan(mod_path).ok()? != manifest {
+        return None;
+    }
+
+    let mut reader = BufReader::new(File::open(mod_path.join("mod.srf")).ok()?);
+    srf::read_srf(&mut reader).ok()
+}
+
+pub fn open_cache_or_gen_srf(
+    base_path: &Path,
+    cache_path: &Path,
+    mod_dirs: &ModDirs,
+) -> Result<ModCache, Error> {
+    let options = GenSrfOptions {
+        mod_dirs: mod_dirs.clone(),
+        ..GenSrfOptions::default()
+    };
+
+    match ModCache::from_disk(cache_path) {
+        Ok(cache) => return Ok(cache),
         Err(mod_cache::Error::FileOpen { source })
             if source.kind() == std::io::ErrorKind::NotFound =>
         {
-            println!("nimble-cache.json not found, generating...");
-            gen_srf(base_path);
-            ModCache::from_disk_or_empty(base_path)
+            info!("{} not found, generating...", cache_path.display());
+        }
+        Err(mod_cache::Error::IntegrityMismatch) => {
+            warn!("{} is corrupt, regenerating...", cache_path.display());
         }
-        Err(e) => Err(e),
+        Err(e) => return Err(e).context(ModCacheOpenSnafu),
+    }
+
+    gen_srf(base_path, cache_path, &options)?;
+    ModCache::from_disk_or_empty(cache_path).context(ModCacheOpenSnafu)
+}
+
+// checks every cached mod against its mod.srf and the manifest next to it, rescanning the ones
+// that changed on disk since they were cached and forgetting the ones that are gone. mod.srf and
+// the manifest are only rewritten with write_srf. returns whether the cache changed
+pub fn revalidate_cache(
+    base_path: &Path,
+    mod_cache: &mut ModCache,
+    write_srf: bool,
+) -> Result<bool, Error> {
+    let drifted: Vec<_> = mod_cache
+        .mods
+        .par_iter()
+        .filter_map(|(checksum, cached)| {
+            let Some(path) = find_mod_dir(base_path, &cached.name) else {
+                return Some((cached.name.clone(), None));
+            };
+
+            match read_current_srf(&path) {
+                Some(srf) if srf.checksum == *checksum => None,
+                _ => Some((cached.name.clone(), Some(path))),
+            }
+        })
+        .collect();
+
+    for (name, path) in &drifted {
+        mod_cache.remove_by_name(name);
+
+        let Some(path) = path else {
+            warn!("{name} is cached but no longer on disk, forgetting it");
+            continue;
+        };
+
+        warn!("{} changed since it was cached, rescanning", path.display());
+
+        let srf = if write_srf {
+            gen_srf_for_mod(path, false)?
+        } else {
+            srf::scan_mod(path).context(ScanSnafu { path })?
+        };
+
+        mod_cache.insert(srf);
     }
+
+    Ok(!drifted.is_empty())
 }
 
-pub fn gen_srf(base_path: &Path) {
-    let mods: HashMap<Md5Digest, srf::Mod> = WalkDir::new(base_path)
-        .min_depth(1)
-        .max_depth(1)
-        .into_iter()
+fn scan_mods(base_path: &Path, options: &GenSrfOptions) -> Result<Vec<srf::Mod>, Error> {
+    options
+        .mod_dirs
+        .walk(base_path)
         .par_bridge()
-        .filter_map(Result::ok)
-        .filter(|e| e.file_type().is_dir() && e.file_name().to_string_lossy().starts_with('@'))
         .map(|entry| {
             let path = entry.path();
-            let srf = gen_srf_for_mod(path);
+            // a srf hashed with another algorithm has to be redone, even if nothing changed
+            let current_srf = if options.force {
+                None
+            } else {
+                read_current_srf(path).filter(|srf| srf.algorithm == options.scan.algorithm)
+            };
 
-            (srf.checksum.clone(), srf)
+            let srf = match current_srf {
+                Some(srf) => {
+                    info!("{} is unchanged, skipping", path.display());
+                    srf
+                }
+                None => gen_srf_for_mod_with(path, options.legacy, &options.scan)?,
+            };
+
+            Ok(srf)
         })
-        .collect();
+        .collect()
+}
+
+pub fn gen_srf(base_path: &Path, cache_path: &Path, options: &GenSrfOptions) -> Result<(), Error> {
+    // scan_mod is parallel on its own, so running it inside the pool bounds both levels at once.
+    // --jobs then caps how many files are read at the same time
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .context(ThreadPoolSnafu)?;
+
+    let mut mods = pool.install(|| scan_mods(base_path, options))?;
+    // scanned in parallel, sorted so which of two identical mods gets cached doesn't change
+    mods.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut cache = ModCache::from_mods(mods);
 
-    let cache = ModCache::new(mods);
+    // the optional mod selection and what the repo said about each mod can't be rebuilt from
+    // disk, carry them over. checksums of mods that are gone would only confuse the next sync
+    if let Ok(previous) = ModCache::from_disk(cache_path) {
+        let names: HashSet<_> = cache
+            .mods
+            .values()
+            .map(|r#mod| r#mod.name.to_lowercase())
+            .collect();
 
-    cache.to_disk(base_path).unwrap();
+        cache.optional = previous.optional;
+        cache.repo_checksums = previous
+            .repo_checksums
+            .into_iter()
+            .filter(|(name, _)| names.contains(name))
+            .collect();
+    }
+
+    cache.to_disk(cache_path).context(ModCacheWriteSnafu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Checksum;
+    use std::time::Duration;
+
+    #[test]
+    fn read_current_srf_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mod_path = dir.path().join("@test");
+        std::fs::create_dir(&mod_path).unwrap();
+        std::fs::write(mod_path.join("readme.txt"), "hello").unwrap();
+
+        assert_eq!(read_current_srf(&mod_path), None);
+
+        let generated = gen_srf_for_mod(&mod_path, false).unwrap();
+        assert_eq!(read_current_srf(&mod_path), Some(generated));
+
+        // make sure the change lands after the srf, even on filesystems with coarse timestamps
+        let file = File::options()
+            .write(true)
+            .open(mod_path.join("readme.txt"))
+            .unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(read_current_srf(&mod_path), None);
+
+        gen_srf_for_mod(&mod_path, false).unwrap();
+        std::fs::write(mod_path.join("new.txt"), "new").unwrap();
+
+        assert_eq!(read_current_srf(&mod_path), None);
+    }
+
+    #[test]
+    fn gen_srf_keeps_repo_checksums_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("nimble-cache.json");
+        std::fs::create_dir(dir.path().join("@test")).unwrap();
+        std::fs::write(dir.path().join("@test/readme.txt"), "hello").unwrap();
+
+        let checksum = Checksum::new("00000000000000000000000000000001").unwrap();
+        let mut previous = ModCache::new_empty();
+        previous.optional.insert("@test".to_string());
+        previous
+            .repo_checksums
+            .insert("@test".to_string(), checksum.clone());
+        previous
+            .repo_checksums
+            .insert("@gone".to_string(), checksum.clone());
+        previous.to_disk(&cache_path).unwrap();
+
+        gen_srf(dir.path(), &cache_path, &GenSrfOptions::default()).unwrap();
+
+        let cache = ModCache::from_disk(&cache_path).unwrap();
+        assert!(cache.mods.values().any(|r#mod| r#mod.name == "@test"));
+        assert!(cache.optional.contains("@test"));
+        assert_eq!(
+            cache.repo_checksums,
+            BTreeMap::from([("@test".to_string(), checksum)])
+        );
+    }
 }
@@ -0,0 +1,45 @@
+use crate::commands::find_mod_dir;
+use crate::commands::verify::{self, verify_mod};
+use crate::srf;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("no mod named {} found in {}", name, base_path.display()))]
+    UnknownMod { name: String, base_path: PathBuf },
+    #[snafu(display("failed to inspect mod: {}", source))]
+    Verify { source: verify::Error },
+}
+
+fn describe(file: Option<&srf::File>) -> String {
+    match file {
+        Some(file) => format!("{} bytes, {}", file.length, file.checksum),
+        None => "-".to_string(),
+    }
+}
+
+pub fn inspect(base_path: &Path, mod_name: &str) -> Result<(), Error> {
+    let mod_path = find_mod_dir(base_path, mod_name).context(UnknownModSnafu {
+        name: mod_name,
+        base_path,
+    })?;
+
+    let reports = verify_mod(&mod_path).context(VerifySnafu)?;
+
+    for report in &reports {
+        println!("{:<10} {}", report.status, report.path);
+        println!("    expected: {}", describe(report.expected.as_ref()));
+        println!("    on disk:  {}", describe(report.actual.as_ref()));
+    }
+
+    let problems = reports.iter().filter(|report| report.is_problem()).count();
+    println!(
+        "{}: {} files, {} with problems",
+        mod_path.display(),
+        reports.len(),
+        problems
+    );
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use crate::checksum::Algorithm;
+use crate::pbo::{self, Pbo};
+use crate::srf;
+use snafu::{ResultExt, Snafu};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to open {}: {}", path.display(), source))]
+    Open {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to read pbo header: {}", source))]
+    Header { source: pbo::Error },
+    #[snafu(display("invalid pbo layout: {}", source))]
+    Layout { source: pbo::Error },
+    #[snafu(display("failed to hash pbo: {}", source))]
+    Scan { source: srf::Error },
+}
+
+// prints a single pbo's header and the parts its checksum is made of, hashed the way sync and
+// gen-srf do, to find out why a checksum differs from swifty's
+pub fn inspect_pbo(path: &Path, algorithm: Algorithm) -> Result<(), Error> {
+    let file = File::open(path).context(OpenSnafu { path })?;
+    let pbo = Pbo::read(BufReader::new(file)).context(HeaderSnafu)?;
+
+    let mut extensions: Vec<_> = pbo.extensions.iter().collect();
+    extensions.sort();
+
+    println!("header: {} bytes", pbo.header_len);
+    println!("extensions:");
+    for (key, value) in extensions {
+        println!("    {key} = {value}");
+    }
+
+    // the first entry is the unnamed version header, not a file
+    println!("entries (type, data size, original size, offset, timestamp, name):");
+    for entry in pbo.entries.iter().skip(1) {
+        println!(
+            "    {:<6} {:>10} {:>10} {:>10} {:>10} {}",
+            format!("{:?}", entry.r#type),
+            entry.data_size,
+            entry.original_size,
+            entry.offset,
+            entry.timestamp,
+            entry.filename
+        );
+    }
+
+    // hashing would fail on it anyway, the entries above show what's wrong
+    pbo.check_offsets().context(LayoutSnafu)?;
+
+    let base_path = path.parent().unwrap_or(Path::new(""));
+    let scanned = srf::scan_pbo(path, base_path, algorithm).context(ScanSnafu)?;
+
+    println!("parts (start, length, checksum, name):");
+    for part in &scanned.parts {
+        println!(
+            "    {:>10} {:>10} {} {}",
+            part.start, part.length, part.checksum, part.path
+        );
+    }
+
+    println!(
+        "{}: {} bytes, {}",
+        scanned.path, scanned.length, scanned.checksum
+    );
+
+    Ok(())
+}
@@ -1,34 +1,241 @@
+use crate::commands::gen_srf;
 use crate::commands::gen_srf::open_cache_or_gen_srf;
-use crate::mod_cache;
+use crate::commands::ModDirs;
 use crate::mod_cache::ModCache;
-use snafu::{ResultExt, Snafu};
-use std::cfg;
+use crate::{http, repository};
+use log::{info, warn};
+use serde::{Deserialize, Deserializer};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::collections::HashSet;
+use std::fmt::Display;
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
 
-#[cfg(not(windows))]
-use snafu::OptionExt;
+// steam app ids of the games communities run repos like these for, by the names --app-id takes
+const APP_ID_PRESETS: [(&str, u32); 3] =
+    [("arma3", 107410), ("reforger", 1874880), ("dayz", 221100)];
+
+// the steam app launched through steam://run. arma 3 unless told otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppId(pub u32);
+
+impl Default for AppId {
+    fn default() -> Self {
+        Self(APP_ID_PRESETS[0].1)
+    }
+}
+
+impl FromStr for AppId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, id)) = APP_ID_PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(Self(*id));
+        }
+
+        s.parse().map(Self).map_err(|_| {
+            let names: Vec<_> = APP_ID_PRESETS.iter().map(|(name, _)| *name).collect();
+            format!("{s} isn't an app id or one of {}", names.join(", "))
+        })
+    }
+}
+
+impl Display for AppId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// nimble.toml may give either the number or a preset's name
+impl<'de> Deserialize<'de> for AppId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum IdOrName {
+            Id(u32),
+            Name(String),
+        }
+
+        match IdOrName::deserialize(deserializer)? {
+            IdOrName::Id(id) => Ok(Self(id)),
+            IdOrName::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
 
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("failed to open ModCache: {}", source))]
-    ModCacheOpen { source: mod_cache::Error },
+    ModCacheOpen { source: gen_srf::Error },
     #[snafu(display("failed to find drive_c"))]
     #[cfg(not(windows))]
     FailedToFindDriveC,
+    #[snafu(display("failed to find the steam library around {}", path.display()))]
+    #[cfg(not(windows))]
+    FailedToFindSteamLibrary { path: PathBuf },
+    #[snafu(display("failed to find arma3_x64.exe in any steam library, pass --executable"))]
+    ExecutableNotFound,
+    #[snafu(display("arma3_x64.exe only runs through proton here, pass --proton"))]
+    #[cfg(not(windows))]
+    ProtonRequired,
+    #[snafu(display("failed to open {}: {}", url, source))]
+    OpenUrl { url: String, source: std::io::Error },
+    #[snafu(display("failed to start {}: {}", program.display(), source))]
+    Spawn {
+        program: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to fetch the repository: {}", source))]
+    RepositoryFetch { source: repository::Error },
+    #[snafu(display("the repository has no server named {}", name))]
+    UnknownServer { name: String },
+    #[snafu(display("joining a server needs the repository url"))]
+    ConnectWithoutRepository,
+    #[snafu(display(
+        "app id {} only works through steam, --direct always starts arma 3",
+        app_id
+    ))]
+    DirectAppId { app_id: AppId },
+}
+
+#[derive(Debug, Default)]
+pub struct LaunchOptions {
+    // start the game executable instead of going through steam's url handler
+    pub direct: bool,
+    // the game executable, found in the steam libraries if not given
+    pub executable: Option<PathBuf>,
+    // proton script to run the executable with
+    #[cfg(not(windows))]
+    pub proton: Option<PathBuf>,
+    // name of a server from the repo to join right away
+    pub connect: Option<String>,
+    // the game steam starts
+    pub app_id: AppId,
+    // mods to leave out of -mod= even though they're installed
+    pub exclude_mods: Vec<String>,
+    // which directories count as mods when the cache has to be generated from scratch
+    pub mod_dirs: ModDirs,
+}
+
+// what the repo adds to the command line
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RepoArgs {
+    args: Vec<String>,
+    battle_eye: bool,
+    // lowercased names of mods the repo disabled or the user excluded, which stay out of -mod= even
+    // if still on disk
+    disabled: HashSet<String>,
+}
+
+fn repo_args(repo: &repository::Repository, connect: Option<&str>) -> Result<RepoArgs, Error> {
+    let mut args: Vec<_> = repo
+        .client_parameters
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect();
+
+    let Some(name) = connect else {
+        return Ok(RepoArgs {
+            args,
+            battle_eye: false,
+            disabled: repo.disabled_mod_names(),
+        });
+    };
+
+    let server = repo
+        .servers
+        .iter()
+        .find(|server| server.name.eq_ignore_ascii_case(name))
+        .context(UnknownServerSnafu { name })?;
+
+    args.push(format!("-connect={}", server.address));
+    args.push(format!("-port={}", server.port));
+
+    if !server.password.is_empty() {
+        args.push(format!("-password={}", server.password));
+    }
+
+    Ok(RepoArgs {
+        args,
+        battle_eye: server.battle_eye,
+        disabled: repo.disabled_mod_names(),
+    })
 }
 
-fn generate_mod_args(base_path: &Path, mod_cache: &ModCache) -> String {
-    mod_cache
+// the repo is only needed for its extra parameters, so launching still works offline unless a
+// server has to be looked up
+fn fetch_repo_args(
+    client: &http::Client,
+    mirrors: Option<&http::Mirrors>,
+    connect: Option<&str>,
+) -> Result<RepoArgs, Error> {
+    let Some(mirrors) = mirrors else {
+        ensure!(connect.is_none(), ConnectWithoutRepositorySnafu);
+        return Ok(RepoArgs::default());
+    };
+
+    let repo = repository::get_mirrored_repository_info(
+        client,
+        mirrors,
+        repository::DEFAULT_MAX_REPO_SIZE,
+    );
+
+    match repo {
+        Ok(repo) => repo_args(&repo, connect),
+        Err(source) if connect.is_some() => Err(Error::RepositoryFetch { source }),
+        Err(e) => {
+            warn!("failed to fetch the repository, launching without its parameters: {e}");
+            Ok(RepoArgs::default())
+        }
+    }
+}
+
+impl LaunchOptions {
+    fn is_direct(&self) -> bool {
+        #[cfg(not(windows))]
+        if self.proton.is_some() {
+            return true;
+        }
+
+        self.direct || self.executable.is_some()
+    }
+}
+
+fn generate_mod_args(
+    base_path: &Path,
+    mod_cache: &ModCache,
+    disabled: &HashSet<String>,
+) -> Vec<String> {
+    // the cache doesn't keep any order, sorting keeps the load order the same from one launch to
+    // the next
+    let mut enabled: Vec<_> = mod_cache
         .mods
         .values()
-        .fold(String::from("-noLauncher -mod="), |acc, r#mod| {
-            let mod_name = &r#mod.name;
-            let full_path = base_path
-                .join(Path::new(mod_name))
-                .to_string_lossy()
-                .to_string();
-            format!("{acc}{full_path};")
-        })
+        .filter(|r#mod| !disabled.contains(&r#mod.name.to_lowercase()))
+        .collect();
+    enabled.sort_by_cached_key(|r#mod| (r#mod.name.to_lowercase(), r#mod.name.clone()));
+
+    let mods = enabled.into_iter().fold(String::new(), |acc, r#mod| {
+        let mod_name = &r#mod.name;
+        let full_path = base_path
+            .join(Path::new(mod_name))
+            .to_string_lossy()
+            .to_string();
+
+        // the game splits -mod= at semicolons and has no way to escape them
+        if full_path.contains(';') {
+            warn!("leaving {full_path} out of -mod=, the game can't load paths containing ;");
+            return acc;
+        }
+
+        format!("{acc}{full_path};")
+    });
+
+    vec!["-noLauncher".to_string(), format!("-mod={mods}")]
 }
 
 // if we're on windows we don't have to do anything
@@ -39,11 +246,16 @@ fn convert_host_base_path_to_proton_base_path(host_base_path: &Path) -> Result<P
 
 // if we're not on windows, try to find a "drive_c" dir in the ancestors of base_path
 #[cfg(not(windows))]
-fn convert_host_base_path_to_proton_base_path(host_base_path: &Path) -> Result<PathBuf, Error> {
-    let drive_c_path = host_base_path
+fn find_drive_c(host_base_path: &Path) -> Result<&Path, Error> {
+    host_base_path
         .ancestors()
         .find(|&x| x.ends_with("drive_c"))
-        .context(FailedToFindDriveCSnafu)?;
+        .context(FailedToFindDriveCSnafu)
+}
+
+#[cfg(not(windows))]
+fn convert_host_base_path_to_proton_base_path(host_base_path: &Path) -> Result<PathBuf, Error> {
+    let drive_c_path = find_drive_c(host_base_path)?;
 
     let relative = host_base_path
         .strip_prefix(drive_c_path)
@@ -52,27 +264,249 @@ fn convert_host_base_path_to_proton_base_path(host_base_path: &Path) -> Result<P
     Ok(Path::new("c:/").join(relative))
 }
 
-pub fn launch(base_path: &Path) -> Result<(), Error> {
-    let mod_cache = open_cache_or_gen_srf(base_path).context(ModCacheOpenSnafu)?;
+// proton wants to know which prefix to use and where steam is. the mods live in drive_c of the
+// prefix, which is <steam>/steamapps/compatdata/107410/pfx/drive_c
+#[cfg(not(windows))]
+fn proton_environment(host_base_path: &Path) -> Result<[(&'static str, PathBuf); 2], Error> {
+    let compat_data = find_drive_c(host_base_path)?
+        .parent()
+        .and_then(Path::parent)
+        .context(FailedToFindSteamLibrarySnafu {
+            path: host_base_path,
+        })?;
+
+    let steam_root = compat_data
+        .ancestors()
+        .find(|&x| x.ends_with("steamapps"))
+        .and_then(Path::parent)
+        .context(FailedToFindSteamLibrarySnafu {
+            path: host_base_path,
+        })?;
+
+    Ok([
+        ("STEAM_COMPAT_DATA_PATH", compat_data.to_owned()),
+        ("STEAM_COMPAT_CLIENT_INSTALL_PATH", steam_root.to_owned()),
+    ])
+}
+
+fn steam_roots() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        vec![PathBuf::from(r"C:\Program Files (x86)\Steam")]
+    } else {
+        let Some(home) = std::env::var_os("HOME").map(PathBuf::from) else {
+            return vec![];
+        };
+
+        vec![home.join(".steam/steam"), home.join(".local/share/Steam")]
+    }
+}
+
+// steam keeps the extra library folders in a vdf file, with lines like "path"  "D:\\Games"
+fn parse_library_folders(vdf: &str) -> Vec<PathBuf> {
+    vdf.lines()
+        .filter_map(|line| line.trim().strip_prefix("\"path\""))
+        .filter_map(|value| {
+            let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+            Some(PathBuf::from(value.replace("\\\\", "\\")))
+        })
+        .collect()
+}
+
+fn find_arma_executable() -> Option<PathBuf> {
+    steam_roots()
+        .into_iter()
+        .flat_map(|root| {
+            let vdf = std::fs::read_to_string(root.join("steamapps/libraryfolders.vdf"));
+            let mut libraries = vdf
+                .map(|vdf| parse_library_folders(&vdf))
+                .unwrap_or_default();
+            libraries.insert(0, root);
+            libraries
+        })
+        .map(|library| library.join("steamapps/common/Arma 3/arma3_x64.exe"))
+        .find(|executable| executable.is_file())
+}
 
+// steam starts the game through its own launcher, which decides about battleye on its own
+fn launch_steam(
+    base_path: &Path,
+    mod_cache: &ModCache,
+    app_id: AppId,
+    repo_args: &RepoArgs,
+) -> Result<(), Error> {
     let proton_base_path = convert_host_base_path_to_proton_base_path(base_path)?;
 
-    let binding = generate_mod_args(&proton_base_path, &mod_cache);
-    let cmdline =
-        percent_encoding::utf8_percent_encode(&binding, percent_encoding::NON_ALPHANUMERIC);
+    let mut args = generate_mod_args(&proton_base_path, mod_cache, &repo_args.disabled);
+    args.extend_from_slice(&repo_args.args);
+
+    let steam_url = |args: &[String]| {
+        let binding = steam_cmdline(args);
+        let cmdline =
+            percent_encoding::utf8_percent_encode(&binding, percent_encoding::NON_ALPHANUMERIC);
+
+        format!("steam://run/{app_id}//{cmdline}/")
+    };
+
+    let redacted: Vec<_> = args.iter().map(|arg| redact_password(arg)).collect();
+    let shown_url = steam_url(&redacted);
+
+    info!("launching {shown_url}");
+
+    open::that(steam_url(&args)).context(OpenUrlSnafu { url: shown_url })
+}
+
+// the server password ends up in the arguments, which shouldn't be shown to whoever reads the log
+fn redact_password(arg: &str) -> String {
+    match arg.get(..10) {
+        Some(flag) if flag.eq_ignore_ascii_case("-password=") => format!("{flag}<redacted>"),
+        _ => arg.to_string(),
+    }
+}
+
+// steam splits what it passes to the game like a windows command line: at spaces, unless they're
+// inside double quotes, with \" standing for a literal quote. a direct launch hands over the
+// arguments as they are instead
+fn steam_cmdline(args: &[String]) -> String {
+    let quoted: Vec<_> = args.iter().map(|arg| quote_arg(arg)).collect();
+
+    quoted.join(" ")
+}
 
-    let steam_url = format!("steam://run/107410//{cmdline}/");
+fn quote_arg(arg: &str) -> String {
+    if !arg.contains(|c: char| c.is_whitespace() || c == '"') {
+        return arg.to_string();
+    }
 
-    dbg!(&steam_url);
+    // backslashes are only special right before a quote, where they escape each other and one
+    // more escapes the quote itself
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
 
-    open::that(steam_url).unwrap();
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            c => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+
+    // trailing ones would escape the closing quote
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+
+    quoted
+}
+
+fn launch_direct(
+    base_path: &Path,
+    mod_cache: &ModCache,
+    options: &LaunchOptions,
+    repo_args: &RepoArgs,
+) -> Result<(), Error> {
+    // the executable, its battleye launcher and the arguments are all arma 3's
+    ensure!(
+        options.app_id == AppId::default(),
+        DirectAppIdSnafu {
+            app_id: options.app_id
+        }
+    );
+
+    // the game is windows only, spawning it natively can't work
+    #[cfg(not(windows))]
+    let proton = options.proton.as_ref().context(ProtonRequiredSnafu)?;
+
+    // servers running battleye need the game started through its launcher, which takes the real
+    // executable as an argument
+    let (executable, prefix_args) = match &options.executable {
+        Some(executable) => (executable.clone(), vec![]),
+        None => {
+            let executable = find_arma_executable().context(ExecutableNotFoundSnafu)?;
+
+            if repo_args.battle_eye {
+                let args = ["2", "1", "1", "-exe", "arma3_x64.exe"].map(str::to_owned);
+                (
+                    executable.with_file_name("arma3battleye.exe"),
+                    args.to_vec(),
+                )
+            } else {
+                (executable, vec![])
+            }
+        }
+    };
+
+    let game_args = |base_path: &Path| {
+        let mut args = prefix_args.clone();
+        args.extend(generate_mod_args(base_path, mod_cache, &repo_args.disabled));
+        args.extend_from_slice(&repo_args.args);
+        args
+    };
+
+    let mut command = Command::new(&executable);
+    command.args(game_args(base_path));
+
+    // proton runs the windows executable, which then sees the mods through the prefix's drive_c
+    #[cfg(not(windows))]
+    {
+        let proton_base_path = convert_host_base_path_to_proton_base_path(base_path)?;
+
+        command = Command::new(proton);
+        command
+            .arg("run")
+            .arg(&executable)
+            .args(game_args(&proton_base_path))
+            .envs(proton_environment(base_path)?);
+    }
+
+    let shown: Vec<_> = command
+        .get_args()
+        .map(|arg| redact_password(&arg.to_string_lossy()))
+        .collect();
+    info!(
+        "launching {} {}",
+        Path::new(command.get_program()).display(),
+        shown.join(" ")
+    );
+
+    let program = PathBuf::from(command.get_program());
+    command.spawn().context(SpawnSnafu { program })?;
 
     Ok(())
 }
 
+pub fn launch(
+    client: &http::Client,
+    mirrors: Option<&http::Mirrors>,
+    base_path: &Path,
+    cache_path: &Path,
+    options: &LaunchOptions,
+) -> Result<(), Error> {
+    let mod_cache = open_cache_or_gen_srf(base_path, cache_path, &options.mod_dirs)
+        .context(ModCacheOpenSnafu)?;
+    let mut repo_args = fetch_repo_args(client, mirrors, options.connect.as_deref())?;
+    repo_args
+        .disabled
+        .extend(options.exclude_mods.iter().map(|name| name.to_lowercase()));
+
+    if options.is_direct() {
+        launch_direct(base_path, &mod_cache, options, &repo_args)
+    } else {
+        launch_steam(base_path, &mod_cache, options.app_id, &repo_args)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::checksum::Checksum;
+    use crate::mod_cache;
 
     #[test]
     #[cfg(windows)]
@@ -84,6 +518,282 @@ mod tests {
         assert_eq!(original_path, converted);
     }
 
+    #[test]
+    fn redact_password_test() {
+        assert_eq!(redact_password("-password=hunter2"), "-password=<redacted>");
+        assert_eq!(redact_password("-PASSWORD=hunter2"), "-PASSWORD=<redacted>");
+        assert_eq!(redact_password("-port=2302"), "-port=2302");
+        assert_eq!(redact_password("-mod=@ünïcode;"), "-mod=@ünïcode;");
+    }
+
+    #[test]
+    fn repo_args_test() {
+        let repo: repository::Repository = serde_json::from_str(
+            r#"{
+                "repoName": "test",
+                "checksum": "",
+                "requiredMods": [
+                    { "modName": "@ace", "checkSum": "00000000000000000000000000000001", "enabled": true },
+                    { "modName": "@CBA", "checkSum": "00000000000000000000000000000002", "enabled": false }
+                ],
+                "optionalMods": [],
+                "clientParameters": "-noSplash  -skipIntro",
+                "repoBasicAuthentication": null,
+                "version": "1",
+                "servers": [
+                    {
+                        "name": "Main",
+                        "address": "192.0.2.1",
+                        "port": "2302",
+                        "password": "hunter2",
+                        "battleEye": true
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            repo_args(&repo, None).unwrap(),
+            RepoArgs {
+                args: vec!["-noSplash".to_string(), "-skipIntro".to_string()],
+                battle_eye: false,
+                disabled: HashSet::from(["@cba".to_string()]),
+            }
+        );
+        assert_eq!(
+            repo_args(&repo, Some("main")).unwrap(),
+            RepoArgs {
+                args: [
+                    "-noSplash",
+                    "-skipIntro",
+                    "-connect=192.0.2.1",
+                    "-port=2302",
+                    "-password=hunter2"
+                ]
+                .map(str::to_owned)
+                .to_vec(),
+                battle_eye: true,
+                disabled: HashSet::from(["@cba".to_string()]),
+            }
+        );
+        assert!(matches!(
+            repo_args(&repo, Some("other")),
+            Err(Error::UnknownServer { .. })
+        ));
+    }
+
+    #[test]
+    fn generate_mod_args_test() {
+        let mut mod_cache = ModCache::new_empty();
+        for (checksum, name) in [
+            ("00000000000000000000000000000001", "@ace"),
+            ("00000000000000000000000000000002", "@cba"),
+        ] {
+            mod_cache.mods.insert(
+                Checksum::new(checksum).unwrap(),
+                mod_cache::Mod {
+                    name: name.to_string(),
+                    files: None,
+                },
+            );
+        }
+
+        let base_path = Path::new("mods");
+        assert_eq!(
+            generate_mod_args(base_path, &mod_cache, &HashSet::from(["@cba".to_string()])),
+            vec![
+                "-noLauncher".to_string(),
+                format!("-mod={};", base_path.join("@ace").display())
+            ]
+        );
+
+        // inserted out of order, into a cache that doesn't keep any
+        for (checksum, name) in [
+            ("00000000000000000000000000000003", "@rhs"),
+            ("00000000000000000000000000000004", "@Acre"),
+        ] {
+            mod_cache.mods.insert(
+                Checksum::new(checksum).unwrap(),
+                mod_cache::Mod {
+                    name: name.to_string(),
+                    files: None,
+                },
+            );
+        }
+
+        let args = generate_mod_args(base_path, &mod_cache, &HashSet::new());
+        assert_eq!(
+            args,
+            generate_mod_args(base_path, &mod_cache, &HashSet::new())
+        );
+        assert_eq!(
+            args[1],
+            format!(
+                "-mod={};{};{};{};",
+                base_path.join("@ace").display(),
+                base_path.join("@Acre").display(),
+                base_path.join("@cba").display(),
+                base_path.join("@rhs").display()
+            )
+        );
+    }
+
+    #[test]
+    fn steam_cmdline_test() {
+        let mut mod_cache = ModCache::new_empty();
+        for (checksum, name) in [
+            ("00000000000000000000000000000001", "@ace"),
+            ("00000000000000000000000000000002", "@task force radio"),
+            ("00000000000000000000000000000003", "@a;b"),
+        ] {
+            mod_cache.mods.insert(
+                Checksum::new(checksum).unwrap(),
+                mod_cache::Mod {
+                    name: name.to_string(),
+                    files: None,
+                },
+            );
+        }
+
+        let base_path = Path::new("c:/Program Files/mods");
+        let mut args = generate_mod_args(base_path, &mod_cache, &HashSet::new());
+        args.push("-skipIntro".to_string());
+        let cmdline = steam_cmdline(&args);
+
+        // split the way steam does, at spaces outside of double quotes
+        let mut parsed = vec![String::new()];
+        let mut quoted = false;
+        for c in cmdline.chars() {
+            match c {
+                '"' => quoted = !quoted,
+                ' ' if !quoted => parsed.push(String::new()),
+                c => parsed.last_mut().unwrap().push(c),
+            }
+        }
+
+        assert_eq!(parsed, args);
+
+        let mods = parsed[1].strip_prefix("-mod=").unwrap();
+        assert_eq!(
+            mods.split_terminator(';').collect::<Vec<_>>(),
+            [
+                base_path.join("@ace").to_string_lossy(),
+                base_path.join("@task force radio").to_string_lossy()
+            ]
+        );
+
+        assert_eq!(
+            steam_cmdline(&[r#"-name=a "b""#.to_string(), r"c:\a b\".to_string()]),
+            r#""-name=a \"b\"" "c:\a b\\""#
+        );
+        assert_eq!(steam_cmdline(&[r"c:\a\b".to_string()]), r"c:\a\b");
+    }
+
+    #[test]
+    fn app_id_test() {
+        assert_eq!("reforger".parse(), Ok(AppId(1874880)));
+        assert_eq!("221100".parse(), Ok(AppId(221100)));
+        assert!("arma2".parse::<AppId>().is_err());
+        assert_eq!(AppId::default(), AppId(107410));
+
+        #[derive(Deserialize)]
+        struct Config {
+            app_id: AppId,
+        }
+
+        for (toml, expected) in [("app_id = 221100", 221100), ("app_id = \"Arma3\"", 107410)] {
+            let config: Config = toml::from_str(toml).unwrap();
+            assert_eq!(config.app_id, AppId(expected));
+        }
+    }
+
+    #[test]
+    fn parse_library_folders_test() {
+        let vdf = r#"
+"libraryfolders"
+{
+	"0"
+	{
+		"path"		"/home/user/.local/share/Steam"
+		"apps"
+		{
+			"107410"		"45000000000"
+		}
+	}
+	"1"
+	{
+		"path"		"D:\\SteamLibrary"
+	}
+}
+"#;
+
+        assert_eq!(
+            parse_library_folders(vdf),
+            vec![
+                PathBuf::from("/home/user/.local/share/Steam"),
+                PathBuf::from(r"D:\SteamLibrary")
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn proton_environment_test() {
+        let steam = PathBuf::from("/home/user/.local/share/Steam");
+        let compat_data = steam.join("steamapps/compatdata/107410");
+        let base_path = compat_data.join("pfx/drive_c/arma_mods");
+
+        assert_eq!(
+            proton_environment(&base_path).unwrap(),
+            [
+                ("STEAM_COMPAT_DATA_PATH", compat_data),
+                ("STEAM_COMPAT_CLIENT_INSTALL_PATH", steam)
+            ]
+        );
+        assert!(proton_environment(Path::new("/mods/drive_c/arma_mods")).is_err());
+    }
+
+    #[test]
+    fn direct_app_id_test() {
+        let options = LaunchOptions {
+            direct: true,
+            executable: Some(PathBuf::from("/games/dayz/DayZ_x64.exe")),
+            app_id: AppId(221100),
+            ..LaunchOptions::default()
+        };
+
+        assert!(matches!(
+            launch_direct(
+                Path::new("/mods"),
+                &ModCache::new_empty(),
+                &options,
+                &RepoArgs::default()
+            ),
+            Err(Error::DirectAppId { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn direct_without_proton_test() {
+        let options = LaunchOptions {
+            direct: true,
+            executable: Some(PathBuf::from("/games/arma3/arma3_x64.exe")),
+            ..LaunchOptions::default()
+        };
+
+        assert!(matches!(
+            launch_direct(
+                Path::new("/mods"),
+                &ModCache::new_empty(),
+                &options,
+                &RepoArgs::default()
+            ),
+            Err(Error::ProtonRequired)
+        ));
+    }
+
     #[test]
     #[cfg(not(windows))]
     fn test_proton_path_conversion() {
@@ -1,3 +1,193 @@
+use crate::{mod_source, partial_downloads, repo_history};
+use snafu::{ensure, ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+pub mod changelog;
+pub mod check_repo_urls;
+pub mod clean;
+pub mod export_checksums;
+pub mod gen_repo;
 pub mod gen_srf;
+pub mod inspect;
+pub mod inspect_pbo;
 pub mod launch;
+pub mod status;
 pub mod sync;
+pub mod update_cache;
+pub mod verify;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{} {}", path.display(), reason))]
+    InvalidLocalPath { path: PathBuf, reason: &'static str },
+    #[snafu(display("failed to create {}: {}", path.display(), source))]
+    LocalPathCreation {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+// makes sure the mod storage path is a directory before a command walks or writes into it, so a
+// typo fails right away instead of somewhere deep inside the command. with create, a missing one
+// is created instead
+pub fn check_local_path(path: &Path, create: bool) -> Result<(), Error> {
+    if !path.exists() {
+        ensure!(
+            create,
+            InvalidLocalPathSnafu {
+                path,
+                reason: "doesn't exist, pass --create to create it"
+            }
+        );
+
+        return std::fs::create_dir_all(path).context(LocalPathCreationSnafu { path });
+    }
+
+    ensure!(
+        path.is_dir(),
+        InvalidLocalPathSnafu {
+            path,
+            reason: "is not a directory"
+        }
+    );
+
+    Ok(())
+}
+
+// which top-level directories of the mod storage path are mods. by default, those whose name
+// starts with @, like swifty expects
+#[derive(Debug, Default, Clone)]
+pub struct ModDirs {
+    // further directories to treat as mods, whatever their name
+    pub include: Vec<String>,
+    // treat every top-level directory as a mod
+    pub all: bool,
+}
+
+// directories nimble keeps its own state in, and the keys directory the game reads. these are
+// never mods, even when every directory is
+fn is_reserved_dir(name: &str) -> bool {
+    let path = Path::new(name);
+
+    path == partial_downloads::data_dir(Path::new(""))
+        || path == repo_history::history_dir(Path::new(""))
+        || name.starts_with(mod_source::EXTRACT_PREFIX)
+        || name.eq_ignore_ascii_case("keys")
+}
+
+impl ModDirs {
+    pub fn is_mod_dir(&self, name: &str) -> bool {
+        if is_reserved_dir(name) {
+            return false;
+        }
+
+        self.all
+            || name.starts_with('@')
+            || self
+                .include
+                .iter()
+                .any(|dir| dir.eq_ignore_ascii_case(name))
+    }
+
+    // the mod directories directly inside base_path, in name order
+    pub fn walk<'a>(&'a self, base_path: &Path) -> impl Iterator<Item = DirEntry> + 'a {
+        WalkDir::new(base_path)
+            .min_depth(1)
+            .max_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_dir() && self.is_mod_dir(&e.file_name().to_string_lossy()))
+    }
+}
+
+// resolves a user supplied mod name to its directory in base_path. the leading @ is optional and
+// the comparison ignores case, since that's how the game treats mod names anyway
+pub fn find_mod_dir(base_path: &Path, name: &str) -> Option<PathBuf> {
+    let name = name.trim_start_matches('@');
+
+    std::fs::read_dir(base_path)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .trim_start_matches('@')
+                .eq_ignore_ascii_case(name)
+        })
+        .map(|entry| entry.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_mod_dir_test() {
+        let test_files: PathBuf = [env!("CARGO_MANIFEST_DIR"), "test_files"].iter().collect();
+
+        assert_eq!(
+            find_mod_dir(&test_files, "ACE"),
+            Some(test_files.join("@ace"))
+        );
+        assert_eq!(
+            find_mod_dir(&test_files, "@ace"),
+            Some(test_files.join("@ace"))
+        );
+        assert_eq!(find_mod_dir(&test_files, "cba"), None);
+    }
+
+    #[test]
+    fn check_local_path_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let missing = dir.path().join("a/b");
+
+        assert!(check_local_path(dir.path(), false).is_ok());
+        assert!(matches!(
+            check_local_path(&file, true),
+            Err(Error::InvalidLocalPath { .. })
+        ));
+        assert!(matches!(
+            check_local_path(&missing, false),
+            Err(Error::InvalidLocalPath { .. })
+        ));
+        assert!(check_local_path(&missing, true).is_ok());
+        assert!(missing.is_dir());
+    }
+
+    #[test]
+    fn is_mod_dir_test() {
+        let default = ModDirs::default();
+        assert!(default.is_mod_dir("@ace"));
+        assert!(!default.is_mod_dir("addons"));
+
+        let include = ModDirs {
+            include: vec!["Addons".to_string()],
+            all: false,
+        };
+        assert!(include.is_mod_dir("addons"));
+        assert!(!include.is_mod_dir("keys"));
+
+        let all = ModDirs {
+            all: true,
+            ..ModDirs::default()
+        };
+        assert!(all.is_mod_dir("addons"));
+        assert!(!all.is_mod_dir("keys"));
+        assert!(!all.is_mod_dir("Keys"));
+        assert!(!all.is_mod_dir(".nimble-partial"));
+        assert!(!all.is_mod_dir("nimble-history"));
+        assert!(!all.is_mod_dir(".nimble-extractAbc123"));
+
+        let include_keys = ModDirs {
+            include: vec!["keys".to_string()],
+            all: false,
+        };
+        assert!(!include_keys.is_mod_dir("keys"));
+    }
+}
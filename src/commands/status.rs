@@ -0,0 +1,168 @@
+use crate::commands::gen_srf;
+use crate::commands::gen_srf::open_cache_or_gen_srf;
+use crate::commands::sync::diff_repo;
+use crate::commands::ModDirs;
+use crate::mod_cache::ModCache;
+use crate::{http, repository};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Failed to fetch repository info: {}", source))]
+    RepositoryFetch { source: repository::Error },
+    #[snafu(display("Failed to open ModCache: {}", source))]
+    ModCacheOpen { source: gen_srf::Error },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Row<'a> {
+    name: &'a str,
+    optional: bool,
+    installed: bool,
+    up_to_date: bool,
+}
+
+fn status_rows<'a>(mod_cache: &ModCache, repo: &'a repository::Repository) -> Vec<Row<'a>> {
+    let mods: Vec<_> = repo
+        .required_mods
+        .iter()
+        .chain(&repo.optional_mods)
+        .filter(|r#mod| r#mod.enabled)
+        .collect();
+
+    // a renamed mod is on disk under its old name, but sync still has to move it
+    let (outdated, renames) = diff_repo(mod_cache, &mods);
+    let outdated: HashSet<_> = outdated
+        .into_iter()
+        .chain(renames.iter().map(|rename| rename.to))
+        .map(|r#mod| r#mod.mod_name.to_lowercase())
+        .collect();
+    let renamed: HashSet<_> = renames
+        .iter()
+        .map(|rename| rename.to.mod_name.to_lowercase())
+        .collect();
+
+    mods.iter()
+        .map(|r#mod| {
+            let name = r#mod.mod_name.to_lowercase();
+            let cached = mod_cache
+                .mods
+                .values()
+                .any(|cached| cached.name.eq_ignore_ascii_case(&name));
+
+            Row {
+                name: &r#mod.mod_name,
+                optional: !repo.required_mods.iter().any(|required| required == *r#mod),
+                installed: cached || renamed.contains(&name),
+                up_to_date: !outdated.contains(&name),
+            }
+        })
+        .collect()
+}
+
+pub fn status(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    base_path: &Path,
+    cache_path: &Path,
+    mod_dirs: &ModDirs,
+) -> Result<(), Error> {
+    let repo = repository::get_mirrored_repository_info(
+        client,
+        mirrors,
+        repository::DEFAULT_MAX_REPO_SIZE,
+    )
+    .context(RepositoryFetchSnafu)?;
+
+    let mod_cache =
+        open_cache_or_gen_srf(base_path, cache_path, mod_dirs).context(ModCacheOpenSnafu)?;
+
+    let rows = status_rows(&mod_cache, &repo);
+    let width = rows
+        .iter()
+        .map(|row| row.name.len())
+        .chain(["mod".len()])
+        .max()
+        .unwrap_or_default();
+    let yes_no = |value| if value { "yes" } else { "no" };
+
+    println!(
+        "{:<width$}  {:<8}  {:<9}  up to date",
+        "mod", "type", "installed"
+    );
+
+    for row in rows {
+        println!(
+            "{:<width$}  {:<8}  {:<9}  {}",
+            row.name,
+            if row.optional { "optional" } else { "required" },
+            yes_no(row.installed),
+            yes_no(row.up_to_date)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::{Algorithm, Checksum};
+    use crate::srf;
+
+    #[test]
+    fn status_rows_test() {
+        let digest = |n: u8| Checksum::new(&format!("{n:032X}")).unwrap();
+        let repo_mod = |name: &str, checksum| repository::Mod {
+            mod_name: name.to_string(),
+            checksum: digest(checksum),
+            enabled: true,
+        };
+        let cached_mod = |name: &str, checksum| srf::Mod {
+            name: name.to_string(),
+            checksum: digest(checksum),
+            algorithm: Algorithm::Md5,
+            files: vec![],
+        };
+
+        let mod_cache = ModCache::from_mods([
+            cached_mod("@ace", 1),
+            cached_mod("@cba", 2),
+            cached_mod("@old_name", 3),
+        ]);
+
+        let repo = repository::Repository {
+            repo_name: "test".to_string(),
+            checksum: String::new(),
+            required_mods: vec![
+                repo_mod("@ACE", 1),
+                repo_mod("@cba", 4),
+                repo_mod("@new_name", 3),
+            ],
+            optional_mods: vec![repo_mod("@jsrs", 5)],
+            client_parameters: String::new(),
+            repo_basic_authentication: None,
+            version: "1".to_string(),
+            servers: vec![],
+        };
+
+        let row = |name, optional, installed, up_to_date| Row {
+            name,
+            optional,
+            installed,
+            up_to_date,
+        };
+
+        assert_eq!(
+            status_rows(&mod_cache, &repo),
+            vec![
+                row("@ACE", false, true, true),
+                row("@cba", false, true, false),
+                row("@new_name", false, true, false),
+                row("@jsrs", true, false, false),
+            ]
+        );
+    }
+}
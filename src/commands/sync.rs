@@ -1,29 +1,147 @@
-use crate::commands::gen_srf::{gen_srf_for_mod, open_cache_or_gen_srf};
+use crate::checksum::{Algorithm, Checksum, Hasher};
+use crate::commands::gen_srf;
+use crate::commands::gen_srf::{
+    gen_srf_for_mod, gen_srf_for_mod_with, open_cache_or_gen_srf, revalidate_cache,
+};
+use crate::commands::{find_mod_dir, ModDirs};
 use crate::mod_cache::ModCache;
-use crate::{repository, srf};
-use indicatif::{ProgressBar, ProgressState, ProgressStyle};
-use snafu::{ResultExt, Snafu};
-use std::collections::HashMap;
+use crate::mod_source;
+use crate::nimbleignore::NimbleIgnore;
+use crate::output;
+use crate::partial_downloads::{self, PartialDownload, PartialDownloads};
+use crate::pbo::Pbo;
+use crate::{dedup, http, keys, repo_history, repository, signature, srf, sync_journal};
+use log::{debug, error, info, trace, warn};
+use rayon::prelude::*;
+use relative_path::RelativePathBuf;
+use snafu::{ensure, ResultExt, Snafu};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom};
-use std::path::Path;
-use tempfile::tempfile;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
 
 #[derive(Debug)]
 struct DownloadCommand {
     file: String,
 
-    // These are currently unused. TODO: implement file diffing.
-    #[allow(dead_code)]
+    // byte range to fetch. anything short of the whole file is spliced into the existing local copy
     begin: u64,
-    #[allow(dead_code)]
     end: u64,
+    // length of the whole remote file
+    length: u64,
+
+    // parts of the remote file, used by --verify-parts
+    parts: Vec<srf::Part>,
+    // checksum of the remote file, so partial downloads are only resumed into the same version
+    checksum: Checksum,
+}
+
+impl DownloadCommand {
+    fn is_pbo(&self) -> bool {
+        self.file.to_lowercase().ends_with(".pbo")
+    }
+
+    fn is_whole_file(&self) -> bool {
+        self.begin == 0 && self.end == self.length
+    }
+}
+
+// byte ranges of remote that don't match local, merging neighbouring parts. parts are compared by
+// position as well as checksum, since we splice the new bytes in at the remote offsets
+fn changed_ranges(remote: &srf::File, local: &srf::File) -> Vec<(u64, u64)> {
+    if remote.r#type != local.r#type {
+        return vec![(0, remote.length)];
+    }
+
+    let local_parts: HashSet<_> = local
+        .parts
+        .iter()
+        .map(|part| (part.start, part.length, &part.checksum))
+        .collect();
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+
+    for part in &remote.parts {
+        let key = (part.start, part.length, &part.checksum);
+
+        if part.length == 0 || local_parts.contains(&key) {
+            continue;
+        }
+
+        let end = part.start + part.length;
+
+        match ranges.last_mut() {
+            Some(last) if last.1 == part.start => last.1 = end,
+            _ => ranges.push((part.start, end)),
+        }
+    }
+
+    // the file differs but every part matches, so the parts can't be trusted
+    if ranges.is_empty() {
+        return vec![(0, remote.length)];
+    }
+
+    ranges
+}
+
+#[derive(Debug, Default)]
+struct SyncDiff {
+    downloads: Vec<DownloadCommand>,
+    // local files that the remote mods no longer have
+    leftovers: Vec<PathBuf>,
+    // mod directories left half-written by an interrupted sync, wiped before downloading
+    stale_dirs: Vec<PathBuf>,
+    // mods the repo ships as <name>.zip instead of a directory, with the archive's size if known
+    archives: Vec<(String, u64)>,
+    // whole mods that have to go, with the reason why
+    removed_mods: Vec<(&'static str, PathBuf)>,
+}
+
+#[derive(Debug)]
+pub struct SyncOptions {
+    pub dry_run: bool,
+    // maximum size of repo.json, in bytes
+    pub max_repo_size: u64,
+    // check every part of a downloaded file against the remote srf, refetching corrupt ones
+    pub verify_parts: bool,
+    // size of the buffer used to copy downloads to disk
+    pub download_buffer: usize,
+    // number of concurrent downloads, defaults to the number of CPUs
+    pub jobs: Option<usize>,
+    // set mtimes of downloaded files from pbo timestamps or the server's Last-Modified
+    pub preserve_mtimes: bool,
+    // hardlink downloaded files to identical ones already on disk
+    pub dedup: bool,
+    // directory of .bikey files to check downloaded pbos against
+    pub check_signatures: Option<PathBuf>,
+    // the game's keys directory, to copy the installed mods' .bikey files into
+    pub keys_dir: Option<PathBuf>,
+    // optional mods to opt into or out of, on top of the ones selected by previous syncs
+    pub with_optional: Vec<String>,
+    pub without_optional: Vec<String>,
+    pub all_optional: bool,
+    // mods never to download, whether required or optional. local copies are removed unless
+    // keep_excluded is set
+    pub exclude_mods: Vec<String>,
+    pub keep_excluded: bool,
+    // which directories count as mods when the cache has to be generated from scratch
+    pub mod_dirs: ModDirs,
+    // check cached mods against their mod.srf and files on disk before diffing
+    pub revalidate: bool,
+    // ask the server about every file before downloading anything
+    pub validate_remote: bool,
+    // with json, events go to stdout as they happen
+    pub format: output::Format,
 }
 
 #[derive(Snafu, Debug)]
 pub enum Error {
     #[snafu(display("io error: {}", source))]
     Io { source: std::io::Error },
+    #[snafu(display("failed to build thread pool: {}", source))]
+    ThreadPool { source: rayon::ThreadPoolBuildError },
     #[snafu(display("Error while requesting repository data: {}", source))]
     Http {
         url: String,
@@ -40,249 +158,3354 @@ pub enum Error {
     #[snafu(display("Failed to generate SRF: {}", source))]
     SrfGeneration { source: srf::Error },
     #[snafu(display("Failed to open ModCache: {}", source))]
-    ModCacheOpen { source: crate::mod_cache::Error },
+    ModCacheOpen { source: gen_srf::Error },
+    #[snafu(display("Failed to write ModCache: {}", source))]
+    ModCacheWrite { source: crate::mod_cache::Error },
+    #[snafu(display("Failed to keep the sync journal: {}", source))]
+    Journal { source: sync_journal::Error },
+    #[snafu(display("Failed to rescan mod: {}", source))]
+    ModRescan { source: gen_srf::Error },
+    #[snafu(display("Failed to extract mod: {}", source))]
+    Archive { source: mod_source::Error },
+    #[snafu(display("Failed to install keys: {}", source))]
+    Keys { source: keys::Error },
+    #[snafu(display(
+        "{} contains {} and {}, which only differ in case and can't both exist on this filesystem",
+        mod_name,
+        first,
+        second
+    ))]
+    CaseCollision {
+        mod_name: String,
+        first: String,
+        second: String,
+    },
+    #[snafu(display(
+        "part {} of {} is corrupt: expected checksum {}, got {}",
+        part,
+        file,
+        expected,
+        actual
+    ))]
+    PartChecksumMismatch {
+        file: String,
+        part: String,
+        expected: Checksum,
+        actual: Checksum,
+    },
+    #[snafu(display("downloaded {} has checksum {}, expected {}", file, actual, expected))]
+    ChecksumMismatch {
+        file: String,
+        expected: Checksum,
+        actual: Checksum,
+    },
+    #[snafu(display("{} files in mod.srf can't be downloaded from the repository", count))]
+    UnavailableFiles { count: usize },
+    #[snafu(display("failed to remove {} files that are no longer needed", count))]
+    Removals { count: usize },
+    #[snafu(display("interrupted"))]
+    Interrupted,
 }
 
-fn diff_repo<'a>(
-    mod_cache: &ModCache,
+// how sync reports what it is doing and learns that it should stop, so it can run without a
+// terminal. downloads run in parallel, so hooks may be called from several threads at once
+pub trait SyncObserver: Sync {
+    // the downloads are known and about to start. a patched file takes one per changed range
+    fn on_diff_complete(&self, _downloads: usize, _bytes: u64) {}
+    // a whole file download (re)started, with downloaded of its size bytes already on disk
+    fn on_file_start(&self, _file: &str, _size: u64, _downloaded: u64) {}
+    fn on_bytes(&self, _file: &str, _delta: u64) {}
+    // a download finished, failed or was cancelled. size is what it set out to fetch
+    fn on_file_done(&self, _file: &str, _size: u64, _error: Option<&Error>) {}
+    fn on_downloads_finished(&self) {}
+    // runs log, which may write to the terminal, without mixing it up with what the observer draws
+    fn suspend(&self, log: &mut dyn FnMut()) {
+        log()
+    }
+    // polled between and during downloads. a cancelled sync keeps what it finished and fails with
+    // Error::Interrupted
+    fn should_cancel(&self) -> bool {
+        false
+    }
+}
+
+// a cached mod that the repo now lists under a different name, with identical content
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rename<'a> {
+    pub from: String,
+    pub to: &'a repository::Mod,
+}
+
+// applies this run's optional mod flags to the selection stored in the cache, returning the names
+// of the mods that were deselected
+fn select_optional_mods(
+    selection: &mut BTreeSet<String>,
+    remote_repo: &repository::Repository,
+    options: &SyncOptions,
+) -> Vec<String> {
+    let optional_names: HashSet<_> = remote_repo
+        .optional_mods
+        .iter()
+        .map(|r#mod| r#mod.mod_name.to_lowercase())
+        .collect();
+
+    if options.all_optional {
+        selection.extend(optional_names.iter().cloned());
+    }
+
+    for name in &options.with_optional {
+        let name = name.to_lowercase();
+
+        if optional_names.contains(&name) {
+            selection.insert(name);
+        } else {
+            warn!("{name} is not an optional mod of this repo, ignoring it");
+        }
+    }
+
+    options
+        .without_optional
+        .iter()
+        .map(|name| name.to_lowercase())
+        .filter(|name| selection.remove(name))
+        .collect()
+}
+
+// lowercased names of the mods the user asked not to install
+fn excluded_mods(remote_repo: &repository::Repository, options: &SyncOptions) -> BTreeSet<String> {
+    let listed: HashSet<_> = remote_repo
+        .required_mods
+        .iter()
+        .chain(&remote_repo.optional_mods)
+        .map(|r#mod| r#mod.mod_name.to_lowercase())
+        .collect();
+
+    options
+        .exclude_mods
+        .iter()
+        .map(|name| name.to_lowercase())
+        .filter(|name| {
+            let is_listed = listed.contains(name);
+            if !is_listed {
+                warn!("{name} is not a mod of this repo, ignoring it");
+            }
+            is_listed
+        })
+        .collect()
+}
+
+// the required mods plus the selected optional ones, leaving out whatever the repo disabled
+fn wanted_mods<'a>(
     remote_repo: &'a repository::Repository,
+    selection: &BTreeSet<String>,
 ) -> Vec<&'a repository::Mod> {
+    let optional = remote_repo
+        .optional_mods
+        .iter()
+        .filter(|r#mod| selection.contains(&r#mod.mod_name.to_lowercase()));
+
+    remote_repo
+        .required_mods
+        .iter()
+        .chain(optional)
+        .filter(|r#mod| r#mod.enabled)
+        .collect()
+}
+
+pub fn diff_repo<'a>(
+    mod_cache: &ModCache,
+    wanted: &[&'a repository::Mod],
+) -> (Vec<&'a repository::Mod>, Vec<Rename<'a>>) {
     let mut downloads = Vec::new();
+    let mut renames = Vec::new();
 
     // repo checksums use the repo generation timestamp in the checksum calculation, so we can't really
     // generate them for comparison. they aren't that useful anyway
 
-    for r#mod in &remote_repo.required_mods {
-        if !mod_cache.mods.contains_key(&r#mod.checksum) {
-            downloads.push(r#mod);
+    let cached_names: HashSet<_> = mod_cache
+        .mods
+        .values()
+        .map(|cached| cached.name.to_lowercase())
+        .collect();
+
+    let wanted_names: HashSet<_> = wanted
+        .iter()
+        .map(|r#mod| r#mod.mod_name.to_lowercase())
+        .collect();
+
+    for &r#mod in wanted {
+        // listed with the same checksum as when it was last synced, even if that's not the one
+        // its mod.srf has
+        let name = r#mod.mod_name.to_lowercase();
+        if cached_names.contains(&name)
+            && mod_cache.repo_checksums.get(&name) == Some(&r#mod.checksum)
+        {
+            continue;
+        }
+
+        match mod_cache.mods.get(&r#mod.checksum) {
+            Some(cached) if cached.name.eq_ignore_ascii_case(&r#mod.mod_name) => {}
+            // mod checksums don't include the name, so the same checksum under a name the repo
+            // dropped means the mod was renamed and we can just move the directory
+            Some(cached) if !wanted_names.contains(&cached.name.to_lowercase()) => {
+                renames.push(Rename {
+                    from: cached.name.clone(),
+                    to: r#mod,
+                });
+            }
+            _ => downloads.push(r#mod),
         }
     }
 
-    downloads
+    (downloads, renames)
 }
 
-fn diff_mod(
-    agent: &ureq::Agent,
-    repo_base_path: &str,
-    local_base_path: &Path,
-    remote_mod: &repository::Mod,
-) -> Result<Vec<DownloadCommand>, Error> {
-    // HACK HACK: this REALLY should be parsed through streaming rather than through buffering the whole thing
-    let remote_srf_url = format!("{}{}/mod.srf", repo_base_path, remote_mod.mod_name);
-    let mut remote_srf = agent
-        .get(&remote_srf_url)
-        .call()
+// moves renamed mods into place, returning the ones that couldn't be moved so they get synced
+// normally instead
+fn apply_renames<'a>(
+    base_path: &Path,
+    mod_cache: &mut ModCache,
+    renames: Vec<Rename<'a>>,
+) -> Result<Vec<&'a repository::Mod>, Error> {
+    let mut failed = Vec::new();
+
+    for rename in renames {
+        let from = find_mod_dir(base_path, &rename.from);
+        let to = base_path.join(&rename.to.mod_name);
+
+        match from {
+            Some(from) if !to.exists() => {
+                info!("{} was renamed to {}", rename.from, rename.to.mod_name);
+
+                std::fs::rename(&from, &to).context(IoSnafu)?;
+
+                if let Some(cached) = mod_cache.mods.get_mut(&rename.to.checksum) {
+                    cached.name = rename.to.mod_name.to_lowercase();
+                }
+
+                // the old name is gone, it mustn't be dropped later. the new one is recorded once
+                // a sync checks it
+                mod_cache.repo_checksums.remove(&rename.from.to_lowercase());
+            }
+            _ => failed.push(rename.to),
+        }
+    }
+
+    Ok(failed)
+}
+
+// returns the first pair of files whose paths only differ in case
+fn find_case_collision(files: &[srf::File]) -> Option<(&RelativePathBuf, &RelativePathBuf)> {
+    let mut seen = HashMap::new();
+
+    for file in files {
+        if let Some(other) = seen.insert(file.path.as_str().to_lowercase(), &file.path) {
+            return Some((other, &file.path));
+        }
+    }
+
+    None
+}
+
+// pairs every remote file with its local copy, if any, and returns the local files left over.
+// windows authored repos often disagree with the local copy about case, so paths that don't match
+// exactly are matched ignoring case. exact matches go first, so files that really only differ in
+// case stay apart
+fn match_files<'a>(
+    remote: &'a [srf::File],
+    local: &'a [srf::File],
+) -> (
+    Vec<(&'a srf::File, Option<&'a srf::File>)>,
+    Vec<&'a srf::File>,
+) {
+    let mut local_files: HashMap<_, _> = local.iter().map(|file| (&file.path, file)).collect();
+    let mut pairs = Vec::with_capacity(remote.len());
+    let mut unmatched = vec![];
+
+    for file in remote {
+        match local_files.remove(&file.path) {
+            Some(local_file) => pairs.push((file, Some(local_file))),
+            None => unmatched.push(file),
+        }
+    }
+
+    for file in unmatched {
+        let lowercase = file.path.as_str().to_lowercase();
+        let key = local_files
+            .keys()
+            .find(|path| path.as_str().to_lowercase() == lowercase)
+            .copied();
+
+        pairs.push((file, key.and_then(|key| local_files.remove(key))));
+    }
+
+    let mut leftovers: Vec<_> = local_files.into_values().collect();
+    leftovers.sort_by(|a, b| a.path.cmp(&b.path));
+
+    (pairs, leftovers)
+}
+
+// checks whether dir lives on a case-insensitive filesystem by creating a file and looking it up
+// with a different case
+fn is_case_insensitive(dir: &Path) -> Result<bool, std::io::Error> {
+    let probe = tempfile::Builder::new()
+        .prefix("nimble-case-probe-")
+        .tempfile_in(dir)?;
+
+    let name = probe
+        .path()
+        .file_name()
+        .expect("temp file has no name")
+        .to_string_lossy()
+        .to_uppercase();
+
+    Ok(dir.join(name).exists())
+}
+
+// remote srfs can be legacy or json, and may start with a utf-8 bom, which is bad, not very useful
+// and not supported by serde. http responses can't seek, so this peeks at the first bytes to tell,
+// then chains them back in front of the rest of the stream
+fn read_remote_srf(mut input: impl Read) -> Result<srf::Mod, Error> {
+    const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+    let mut head = Vec::new();
+    (&mut input)
+        .take((BOM.len() + "ADDON".len()) as u64)
+        .read_to_end(&mut head)
+        .context(IoSnafu)?;
+
+    let bom_len = if head.starts_with(BOM) { BOM.len() } else { 0 };
+    let is_legacy = head[bom_len..].starts_with(b"ADDON");
+
+    let mut head = Cursor::new(head);
+    head.set_position(bom_len as u64);
+    let mut reader = BufReader::new(head.chain(input));
+
+    if is_legacy {
+        srf::deserialize_legacy_srf(&mut reader).context(LegacySrfDeserializationSnafu)
+    } else {
+        serde_json::from_reader(reader).context(SrfDeserializationSnafu)
+    }
+}
+
+pub fn fetch_mirrored_srf(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    mod_name: &str,
+) -> Result<srf::Mod, Error> {
+    let path = format!("{mod_name}/mod.srf");
+    let (response, _) = mirrors
+        .call(client, &path, |url| client.get_metadata(url))
         .context(HttpSnafu {
-            url: remote_srf_url,
-        })?
-        .into_reader();
+            url: mirrors.url(&path),
+        })?;
 
-    let mut buf = String::new();
-    let _len = remote_srf.read_to_string(&mut buf).context(IoSnafu)?;
+    read_remote_srf(http::decoded_reader(response))
+}
 
-    // yeet utf-8 bom, which is bad, not very useful and not supported by serde
-    let bomless = buf.trim_start_matches('\u{feff}');
+// reads a mod.srf from disk, returning None if there is none
+fn read_local_srf(srf_path: &Path) -> Result<Option<srf::Mod>, Error> {
+    let file = match File::open(srf_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Io { source: e }),
+    };
 
-    let remote_is_legacy = srf::is_legacy_srf(&mut Cursor::new(bomless)).context(IoSnafu)?;
+    let mut reader = BufReader::new(file);
 
-    let remote_srf: srf::Mod = if remote_is_legacy {
-        srf::deserialize_legacy_srf(&mut BufReader::new(Cursor::new(bomless)))
-            .context(LegacySrfDeserializationSnafu)?
+    let srf = if srf::is_legacy_srf(&mut reader).context(IoSnafu)? {
+        srf::deserialize_legacy_srf(&mut reader).context(LegacySrfDeserializationSnafu)?
     } else {
-        serde_json::from_str(bomless).context(SrfDeserializationSnafu)?
+        serde_json::from_reader(&mut reader).context(SrfDeserializationSnafu)?
+    };
+
+    Ok(Some(srf))
+}
+
+// what the cache knew about a mod before it was taken out to be checked again
+struct CachedMod {
+    // whether the cache had the mod at all
+    present: bool,
+    srf: Option<srf::Mod>,
+    // the checksum repo.json listed for the mod when it was last synced
+    repo_checksum: Option<Checksum>,
+}
+
+fn diff_mod(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    local_base_path: &Path,
+    remote_mod: &repository::Mod,
+    cached: CachedMod,
+    diff: &mut SyncDiff,
+) -> Result<(), Error> {
+    let CachedMod {
+        present: cached,
+        srf: cached_srf,
+        repo_checksum,
+    } = cached;
+
+    let remote_srf = match fetch_mirrored_srf(client, mirrors, &remote_mod.mod_name) {
+        Ok(srf) => srf,
+        Err(Error::Http { url, source }) if matches!(*source, ureq::Error::Status(404, _)) => {
+            return match find_archive(client, mirrors, &remote_mod.mod_name) {
+                Some(size) => {
+                    diff_archive(
+                        local_base_path,
+                        remote_mod,
+                        cached_srf,
+                        repo_checksum,
+                        size,
+                        diff,
+                    );
+                    Ok(())
+                }
+                None => Err(Error::Http { url, source }),
+            };
+        }
+        Err(e) => return Err(e),
     };
 
+    // writing both files would silently clobber one with the other, leaving a broken mod behind
+    if let Some((first, second)) = find_case_collision(&remote_srf.files) {
+        if is_case_insensitive(local_base_path).context(IoSnafu)? {
+            return Err(Error::CaseCollision {
+                mod_name: remote_srf.name.clone(),
+                first: first.to_string(),
+                second: second.to_string(),
+            });
+        }
+    }
+
     let local_path = local_base_path.join(Path::new(&format!("{}/", remote_mod.mod_name)));
     let srf_path = local_path.join(Path::new("mod.srf"));
+    // local checksums only compare to remote ones made with the same algorithm
+    let scan_options = srf::ScanOptions {
+        algorithm: remote_srf.algorithm,
+        ..srf::ScanOptions::default()
+    };
 
     let local_srf = {
         if local_path.exists() {
-            let file = File::open(srf_path);
-
-            match file {
-                Ok(file) => {
-                    let mut reader = BufReader::new(file);
-
-                    if srf::is_legacy_srf(&mut reader).context(IoSnafu)? {
-                        srf::deserialize_legacy_srf(&mut reader)
-                            .context(LegacySrfDeserializationSnafu)?
-                    } else {
-                        serde_json::from_reader(&mut reader).context(SrfDeserializationSnafu)?
-                    }
+            match cached_srf.map_or_else(|| read_local_srf(&srf_path), |srf| Ok(Some(srf))) {
+                Ok(Some(srf)) => srf,
+                // a directory without a valid srf that we never cached is what an interrupted
+                // sync leaves behind. whatever is in there can't be trusted, so start over
+                Ok(None) | Err(_) if !cached => {
+                    info!(
+                        "{} looks like a partially downloaded mod, starting over",
+                        local_path.display()
+                    );
+
+                    diff.stale_dirs.push(local_path.clone());
+
+                    srf::Mod::generate_invalid(&remote_srf)
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    srf::scan_mod(&local_path).context(SrfGenerationSnafu)?
+                Ok(None) => {
+                    srf::scan_mod_with_options(&local_path, &scan_options)
+                        .context(SrfGenerationSnafu)?
+                        .0
                 }
-                Err(e) => return Err(Error::Io { source: e }),
+                Err(e) => return Err(e),
             }
         } else {
             srf::Mod::generate_invalid(&remote_srf)
         }
     };
 
-    if local_srf.checksum == remote_srf.checksum {
-        return Ok(vec![]);
-    }
-
-    let mut local_files = HashMap::new();
-
-    for file in &local_srf.files {
-        local_files.insert(&file.path, file);
-    }
-
-    let mut remote_files = HashMap::new();
+    let local_srf = if local_srf.algorithm == remote_srf.algorithm {
+        local_srf
+    } else {
+        srf::scan_mod_with_options(&local_path, &scan_options)
+            .context(SrfGenerationSnafu)?
+            .0
+    };
 
-    for file in &remote_srf.files {
-        remote_files.insert(&file.path, file);
+    if local_srf.checksum == remote_srf.checksum {
+        return Ok(());
     }
 
-    let mut download_list = Vec::new();
+    let (pairs, leftovers) = match_files(&remote_srf.files, &local_srf.files);
+    let mod_path = local_base_path.join(Path::new(&remote_srf.name));
 
-    for (path, file) in remote_files.drain() {
-        let local_file = local_files.remove(path);
+    for (file, local_file) in pairs {
+        let path = &file.path;
 
-        if let Some(local_file) = local_file {
-            if file.checksum != local_file.checksum {
-                // TODO: implement file diffing. for now, just download everything
+        match local_file {
+            Some(local_file) if file.checksum == local_file.checksum => {}
+            // patching writes into the local file, so it has to be the one the remote path names
+            Some(local_file) if local_file.path == file.path => {
+                for (begin, end) in changed_ranges(file, local_file) {
+                    diff.downloads.push(DownloadCommand {
+                        file: format!("{}/{}", remote_srf.name, path),
+                        begin,
+                        end,
+                        length: file.length,
+                        parts: file
+                            .parts
+                            .iter()
+                            .filter(|part| part.start >= begin && part.start + part.length <= end)
+                            .cloned()
+                            .collect(),
+                        checksum: file.checksum.clone(),
+                    });
+                }
+            }
+            _ => {
+                // a changed file that only matched ignoring case is replaced under the remote's name
+                if let Some(local_file) = local_file {
+                    diff.leftovers.push(local_file.path.to_path(&mod_path));
+                }
 
-                download_list.push(DownloadCommand {
+                diff.downloads.push(DownloadCommand {
                     file: format!("{}/{}", remote_srf.name, path),
                     begin: 0,
                     end: file.length,
+                    length: file.length,
+                    parts: file.parts.clone(),
+                    checksum: file.checksum.clone(),
                 });
             }
-        } else {
-            download_list.push(DownloadCommand {
-                file: format!("{}/{}", remote_srf.name, path),
-                begin: 0,
-                end: file.length,
-            });
         }
     }
 
-    // remove any local files that remain here
-    remove_leftover_files(local_base_path, &remote_srf, local_files.into_values())
-        .context(IoSnafu)?;
+    // any local files that remain here are leftovers
+    diff.leftovers.extend(
+        leftovers
+            .into_iter()
+            .map(|file| file.path.to_path(&mod_path)),
+    );
+
+    Ok(())
+}
 
-    Ok(download_list)
+// archive based repos publish <mod>.zip in place of the mod's directory. returns the archive's size,
+// or 0 if the server doesn't say, when there is one
+fn find_archive(client: &http::Client, mirrors: &http::Mirrors, mod_name: &str) -> Option<u64> {
+    let path = format!("{mod_name}.zip");
+    let (response, _) = mirrors.call(client, &path, |url| client.head(url)).ok()?;
+
+    let size = response
+        .header("Content-Length")
+        .filter(|_| response.header("Content-Encoding").is_none())
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+
+    Some(size)
 }
 
-// remove files that are present in the local disk but not in the remote repo
-fn remove_leftover_files<'a>(
+// an archive can only be fetched as a whole, so the mod is replaced entirely unless the local copy
+// is the one extracted from the archive repo.json lists now. archives packed by other tools aren't
+// hashed the way nimble hashes the extracted files, so that's mostly told by the checksum repo.json
+// listed when the mod was last synced
+fn diff_archive(
     local_base_path: &Path,
-    r#mod: &srf::Mod,
-    files: impl Iterator<Item = &'a srf::File>,
-) -> Result<(), std::io::Error> {
-    for file in files {
-        let path = file
-            .path
-            .to_path(local_base_path.join(Path::new(&r#mod.name)));
+    remote_mod: &repository::Mod,
+    cached_srf: Option<srf::Mod>,
+    repo_checksum: Option<Checksum>,
+    size: u64,
+    diff: &mut SyncDiff,
+) {
+    let local_path = local_base_path.join(&remote_mod.mod_name);
+    if local_path.exists() && repo_checksum.as_ref() == Some(&remote_mod.checksum) {
+        return;
+    }
 
-        println!("removing leftover file {}", &path.display());
+    let local_srf = match cached_srf {
+        Some(srf) => Some(srf),
+        None if local_path.exists() => read_local_srf(&local_path.join("mod.srf")).ok().flatten(),
+        None => None,
+    };
 
-        std::fs::remove_file(&path)?;
+    if local_srf.is_some_and(|srf| srf.checksum == remote_mod.checksum) {
+        return;
     }
 
-    Ok(())
+    diff.archives.push((remote_mod.mod_name.clone(), size));
 }
 
-fn execute_command_list(
-    agent: &mut ureq::Agent,
-    remote_base: &str,
-    local_base: &Path,
-    commands: &[DownloadCommand],
-) -> Result<(), Error> {
-    for (i, command) in commands.iter().enumerate() {
-        println!("downloading {} of {} - {}", i, commands.len(), command.file);
+// how much a set of downloads would fetch, overall and per mod
+#[derive(Debug, Default, PartialEq, Eq)]
+struct DownloadSummary {
+    files: usize,
+    bytes: u64,
+    // mod name -> (files, bytes)
+    mods: BTreeMap<String, (usize, u64)>,
+}
 
-        // download into temp file first in case we have a failure. this avoids us writing garbage data
-        // which will later make us crash in gen_srf
-        let mut temp_download_file = tempfile().context(IoSnafu)?;
+fn summarize_downloads(
+    downloads: &[DownloadCommand],
+    archives: &[(String, u64)],
+) -> DownloadSummary {
+    let mut summary = DownloadSummary::default();
+    // changed files can take several ranges, but they're still one file
+    let mut seen = HashSet::new();
 
-        let remote_url = format!("{}{}", remote_base, command.file);
+    for command in downloads {
+        let mod_name = command
+            .file
+            .split_once('/')
+            .map_or(command.file.as_str(), |(mod_name, _)| mod_name);
+        let new_file = seen.insert(command.file.as_str());
+        let bytes = command.end - command.begin;
 
-        let response = agent.get(&remote_url).call().context(HttpSnafu {
-            url: remote_url.clone(),
-        })?;
+        let (files, mod_bytes) = summary.mods.entry(mod_name.to_string()).or_default();
+        *files += usize::from(new_file);
+        *mod_bytes += bytes;
 
-        let pb = response
-            .header("Content-Length")
-            .and_then(|len| len.parse().ok())
-            .map_or_else(ProgressBar::new_spinner, ProgressBar::new);
+        summary.files += usize::from(new_file);
+        summary.bytes += bytes;
+    }
 
-        pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn std::fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-            .progress_chars("#>-"));
+    for (mod_name, size) in archives {
+        summary.mods.insert(mod_name.clone(), (1, *size));
+        summary.files += 1;
+        summary.bytes += size;
+    }
 
-        let reader = response.into_reader();
+    summary
+}
 
-        std::io::copy(&mut pb.wrap_read(reader), &mut temp_download_file).context(IoSnafu)?;
+// a lexical check, so a path with .. in it never counts as inside
+fn is_inside(base_path: &Path, path: &Path) -> bool {
+    path.starts_with(base_path)
+        && path != base_path
+        && !path
+            .components()
+            .any(|component| component == std::path::Component::ParentDir)
+}
 
-        // copy from temp to permanent file
-        let file_path = local_base.join(Path::new(&command.file));
-        std::fs::create_dir_all(file_path.parent().expect("file_path did not have a parent"))
-            .context(IoSnafu)?;
-        let mut local_file = File::create(&file_path).context(IoSnafu)?;
+// removes the directories above path that are empty now, stopping at the mod directory
+fn prune_empty_dirs(base_path: &Path, path: &Path) {
+    for dir in path.ancestors().skip(1) {
+        // base_path/@mod itself stays, even when it is empty
+        if !is_inside(base_path, dir) || dir.parent() == Some(base_path) {
+            break;
+        }
 
-        temp_download_file
-            .seek(SeekFrom::Start(0))
-            .context(IoSnafu)?;
-        std::io::copy(&mut temp_download_file, &mut local_file).context(IoSnafu)?;
+        // fails on directories that still have something in them, which is where we stop
+        if std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+
+        debug!("removed empty directory {}", dir.display());
+    }
+}
+
+// rescans the mods an unfinished sync was changing, so the cache describes what it left on disk
+fn resume_journal(
+    base_path: &Path,
+    mod_cache: &mut ModCache,
+    journal: &sync_journal::Journal,
+) -> Result<(), gen_srf::Error> {
+    for name in &journal.mods {
+        mod_cache.remove_by_name(name);
+
+        if let Some(dir) = find_mod_dir(base_path, name) {
+            mod_cache.insert(gen_srf_for_mod(&dir, false)?);
+        }
     }
 
     Ok(())
 }
 
-pub fn sync(
-    agent: &mut ureq::Agent,
-    repo_url: &str,
-    base_path: &Path,
-    dry_run: bool,
-) -> Result<(), Error> {
-    let remote_repo = repository::get_repository_info(agent, &format!("{repo_url}/repo.json"))
-        .context(RepositoryFetchSnafu)?;
+// deletes what diff_mod decided is no longer needed. kept separate from diffing so dry runs can
+// list removals without performing them. anything already gone counts as removed, and one failure
+// doesn't stop the rest from being removed. returns the paths that couldn't be
+fn apply_removals(base_path: &Path, diff: &SyncDiff) -> Vec<PathBuf> {
+    let mut failed = vec![];
+    let mut check = |path: &Path, res: std::io::Result<()>| match res {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("failed to remove {}: {e}", path.display());
+            failed.push(path.to_path_buf());
+        }
+        _ => {}
+    };
 
-    let mut mod_cache = open_cache_or_gen_srf(base_path).context(ModCacheOpenSnafu)?;
+    for (reason, dir) in &diff.removed_mods {
+        if !is_inside(base_path, dir) {
+            warn!(
+                "refusing to remove {} outside of the mod path",
+                dir.display()
+            );
+            continue;
+        }
 
-    let check = diff_repo(&mod_cache, &remote_repo);
+        info!("removing {reason} mod {}", dir.display());
 
-    println!("mods to check: {check:#?}");
+        check(dir, std::fs::remove_dir_all(dir));
+    }
 
-    // remove all mods to check from cache, we'll read them later
-    for r#mod in &check {
-        mod_cache.remove(&r#mod.checksum);
+    for dir in &diff.stale_dirs {
+        if !is_inside(base_path, dir) {
+            warn!(
+                "refusing to remove {} outside of the mod path",
+                dir.display()
+            );
+            continue;
+        }
+
+        info!("removing partially downloaded mod {}", dir.display());
+
+        check(dir, std::fs::remove_dir_all(dir));
     }
 
-    let mut download_commands = vec![];
+    for path in &diff.leftovers {
+        if !is_inside(base_path, path) {
+            warn!(
+                "refusing to remove {} outside of the mod path",
+                path.display()
+            );
+            continue;
+        }
+
+        info!("removing leftover file {}", path.display());
 
-    for r#mod in &check {
-        download_commands.extend(diff_mod(agent, repo_url, base_path, r#mod).unwrap());
+        check(path, std::fs::remove_file(path));
+        prune_empty_dirs(base_path, path);
     }
 
-    println!("download commands: {download_commands:#?}");
+    failed
+}
 
-    if dry_run {
-        return Ok(());
+// mods an earlier sync installed from the repo that it doesn't list at all anymore, neither as
+// required nor as optional. the cache also holds every other mod found in the mod path, and a
+// freshly generated one hasn't synced anything yet, so only the recorded repo checksums say what
+// came from the repo
+fn dropped_mods(mod_cache: &ModCache, remote_repo: &repository::Repository) -> Vec<String> {
+    let listed: HashSet<_> = remote_repo
+        .required_mods
+        .iter()
+        .chain(&remote_repo.optional_mods)
+        .map(|r#mod| r#mod.mod_name.to_lowercase())
+        .collect();
+
+    mod_cache
+        .repo_checksums
+        .keys()
+        .filter(|name| !listed.contains(*name))
+        .cloned()
+        .collect()
+}
+
+// how many times we try to pick a dropped transfer back up before giving up on a file
+const MAX_RESUME_ATTEMPTS: u32 = 3;
+
+pub const DEFAULT_DOWNLOAD_BUFFER: usize = 256 * 1024;
+
+// like std::io::copy, but with a caller sized buffer. the default 8 KiB one can't keep up with
+// gigabit connections
+fn copy_buffered<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    buf: &mut [u8],
+    observer: &dyn SyncObserver,
+    file: &str,
+) -> std::io::Result<u64> {
+    let mut written = 0;
+
+    loop {
+        // stop mid transfer, the partial download can be resumed next time
+        if observer.should_cancel() {
+            return Err(std::io::Error::other("interrupted"));
+        }
+
+        let len = match reader.read(buf) {
+            Ok(0) => return Ok(written),
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        writer.write_all(&buf[..len])?;
+        written += len as u64;
+        observer.on_bytes(file, len as u64);
     }
+}
+
+// picks the validator sent back in If-Range when resuming. weak etags aren't allowed there
+fn resume_validator(response: &ureq::Response) -> Option<String> {
+    response
+        .header("ETag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| response.header("Last-Modified"))
+        .map(str::to_owned)
+}
 
-    let res = execute_command_list(agent, repo_url, base_path, &download_commands);
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
-    if let Err(e) = res {
-        println!("an error occured while downloading: {e}");
-        println!("you should retry this command");
+// some mirrors serve pbos compressed, either with a Content-Encoding header or as plain .gz files.
+// a real pbo always starts with a NUL byte (the product entry has no name), so the magic can't be
+// mistaken for one. arma's own Cprs compression lives inside the pbo and is left alone
+fn detect_encoding(
+    reader: &mut impl BufRead,
+    content_encoding: Option<http::Encoding>,
+    is_pbo: bool,
+) -> Result<Option<http::Encoding>, std::io::Error> {
+    if content_encoding.is_some() {
+        return Ok(content_encoding);
     }
 
-    // gen_srf for the mods we downloaded
-    for r#mod in &check {
-        let srf = gen_srf_for_mod(&base_path.join(Path::new(&r#mod.mod_name)));
+    let gzipped = is_pbo && reader.fill_buf()?.starts_with(&GZIP_MAGIC);
 
-        mod_cache.insert(srf);
+    Ok(gzipped.then_some(http::Encoding::Gzip))
+}
+
+// hashes a file part by part as it is being written, so corrupt parts are known as soon as the
+// download finishes without reading everything back
+struct PartVerifier<'a> {
+    parts: &'a [srf::Part],
+    current: usize,
+    position: u64,
+    hasher: Hasher,
+    corrupt: Vec<&'a srf::Part>,
+}
+
+impl<'a> PartVerifier<'a> {
+    // only works if the parts tile the file from start to end, which is what both scan_file and
+    // scan_pbo produce
+    fn new(parts: &'a [srf::Part]) -> Option<Self> {
+        let mut expected_start = 0;
+
+        for part in parts {
+            if part.start != expected_start {
+                return None;
+            }
+
+            expected_start += part.length;
+        }
+
+        let mut verifier = Self {
+            parts,
+            current: 0,
+            position: 0,
+            hasher: parts
+                .first()
+                .map_or(Algorithm::Md5, |part| part.checksum.algorithm())
+                .hasher(),
+            corrupt: Vec::new(),
+        };
+        verifier.finish_empty_parts();
+
+        Some(verifier)
     }
 
-    // reserialize the cache
-    let writer = BufWriter::new(File::create(base_path.join("nimble-cache.json")).unwrap());
-    serde_json::to_writer(writer, &mod_cache).unwrap();
+    fn reset(&mut self) {
+        self.current = 0;
+        self.position = 0;
+        // throws away whatever was hashed so far
+        self.hasher.finalize_reset();
+        self.corrupt.clear();
+        self.finish_empty_parts();
+    }
 
-    Ok(())
+    fn finish_part(&mut self) {
+        let part = &self.parts[self.current];
+        let hash = self.hasher.finalize_reset();
+
+        if !hash.verify(&part.checksum) {
+            self.corrupt.push(part);
+        }
+
+        self.current += 1;
+    }
+
+    fn finish_empty_parts(&mut self) {
+        while self.current < self.parts.len() {
+            let part = &self.parts[self.current];
+
+            if self.position < part.start + part.length {
+                break;
+            }
+
+            self.finish_part();
+        }
+    }
+
+    fn update(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() && self.current < self.parts.len() {
+            let part = &self.parts[self.current];
+            let remaining = part.start + part.length - self.position;
+            let len = buf
+                .len()
+                .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+
+            self.hasher.update(&buf[..len]);
+            self.position += len as u64;
+            buf = &buf[len..];
+
+            self.finish_empty_parts();
+        }
+    }
+
+    // parts that didn't match, plus any the download never reached
+    fn corrupt_parts(&self) -> Vec<&'a srf::Part> {
+        let mut corrupt = self.corrupt.clone();
+        corrupt.extend(&self.parts[self.current..]);
+        corrupt
+    }
+}
+
+struct VerifyingWriter<'a, 'b> {
+    output: &'a mut File,
+    verifier: Option<&'a mut PartVerifier<'b>>,
+}
+
+impl Write for VerifyingWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.output.write(buf)?;
+
+        if let Some(verifier) = &mut self.verifier {
+            verifier.update(&buf[..written]);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// fetches bytes [begin, end) of file into the same offsets of output, returning their checksum
+fn fetch_range(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    file: &str,
+    begin: u64,
+    end: u64,
+    output: &mut File,
+    algorithm: Algorithm,
+) -> Result<Checksum, Error> {
+    let (response, _) = mirrors
+        .call(client, file, |url| {
+            let request = client.get_file(url);
+
+            if end > begin {
+                request.set("Range", &format!("bytes={}-{}", begin, end - 1))
+            } else {
+                request
+            }
+        })
+        .context(HttpSnafu {
+            url: mirrors.url(file),
+        })?;
+    let status = response.status();
+    let mut reader = response.into_reader();
+
+    // a server ignoring the range sends the whole file, skip ahead to the part we want
+    if status != 206 {
+        std::io::copy(&mut (&mut reader).take(begin), &mut std::io::sink()).context(IoSnafu)?;
+    }
+
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0; 64 * 1024];
+    let mut reader = reader.take(end - begin);
+
+    output.seek(SeekFrom::Start(begin)).context(IoSnafu)?;
+
+    loop {
+        let len = reader.read(&mut buf).context(IoSnafu)?;
+
+        if len == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..len]);
+        output.write_all(&buf[..len]).context(IoSnafu)?;
+    }
+
+    Ok(hasher.finalize())
+}
+
+// refetches a single part with a range request and writes it over the corrupt bytes
+fn refetch_part(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    file: &str,
+    part: &srf::Part,
+    output: &mut File,
+) -> Result<(), Error> {
+    let actual = fetch_range(
+        client,
+        mirrors,
+        file,
+        part.start,
+        part.start + part.length,
+        output,
+        part.checksum.algorithm(),
+    )?;
+
+    ensure!(
+        actual.verify(&part.checksum),
+        PartChecksumMismatchSnafu {
+            file,
+            part: &part.path,
+            expected: part.checksum.clone(),
+            actual,
+        }
+    );
+
+    Ok(())
+}
+
+// state shared by all downloads of a sync
+struct DownloadContext<'a> {
+    client: &'a http::Client,
+    mirrors: &'a http::Mirrors,
+    local_base: &'a Path,
+    options: &'a SyncOptions,
+    observer: &'a dyn SyncObserver,
+    partials: Mutex<PartialDownloads>,
+    partials_path: PathBuf,
+}
+
+impl DownloadContext<'_> {
+    // resuming is best effort, so failing to keep track of it only costs a full download later
+    fn update_partials(&self, update: impl FnOnce(&mut PartialDownloads)) {
+        let mut partials = self.partials.lock().unwrap();
+        update(&mut partials);
+
+        if let Err(e) = partials.to_disk(&self.partials_path) {
+            self.observer
+                .suspend(&mut || warn!("failed to record partial downloads: {e}"));
+        }
+    }
+}
+
+// downloads a file into output, resuming with a range request if the transfer drops midway. the
+// If-Range header makes the server send the whole file again if it changed between attempts, so
+// we never end up splicing two different versions of a file together. output may already hold
+// the start of the file from an earlier run, in which case validator is what it was fetched with.
+// returns the server's Last-Modified time, if it sent one
+fn download_file(
+    ctx: &DownloadContext,
+    command: &DownloadCommand,
+    output: &mut File,
+    mut verifier: Option<&mut PartVerifier>,
+    buf: &mut [u8],
+    mut validator: Option<String>,
+) -> Result<Option<SystemTime>, Error> {
+    let mut attempts = 0;
+
+    loop {
+        let downloaded = output.stream_position().context(IoSnafu)?;
+
+        // mirrors hand out different validators, so resuming from another one just starts over
+        let (response, mirror) = ctx
+            .mirrors
+            .call(ctx.client, &command.file, |url| {
+                let request = ctx.client.get_file(url);
+
+                match &validator {
+                    Some(validator) if downloaded > 0 => request
+                        .set("Range", &format!("bytes={downloaded}-"))
+                        .set("If-Range", validator),
+                    _ => request,
+                }
+            })
+            .context(HttpSnafu {
+                url: ctx.mirrors.url(&command.file),
+            })?;
+
+        ctx.observer
+            .suspend(&mut || debug!("fetching {} from {mirror}", command.file));
+
+        // anything but a 206 means either a fresh download or a remote file that changed under us,
+        // so start from scratch
+        let resumed = response.status() == 206;
+        let last_modified = response
+            .header("Last-Modified")
+            .and_then(|date| httpdate::parse_http_date(date).ok());
+
+        if !resumed {
+            output.set_len(0).context(IoSnafu)?;
+            output.seek(SeekFrom::Start(0)).context(IoSnafu)?;
+            validator = resume_validator(&response);
+
+            if let Some(verifier) = verifier.as_deref_mut() {
+                verifier.reset();
+            }
+        }
+
+        let already_downloaded = if resumed { downloaded } else { 0 };
+        ctx.observer
+            .on_file_start(&command.file, command.length, already_downloaded);
+        let content_encoding = http::content_encoding(&response);
+
+        let mut reader = BufReader::with_capacity(buf.len(), response.into_reader());
+        let mut writer = VerifyingWriter {
+            output,
+            verifier: verifier.as_deref_mut(),
+        };
+
+        let encoding = if resumed {
+            None
+        } else {
+            detect_encoding(&mut reader, content_encoding, command.is_pbo()).context(IoSnafu)?
+        };
+
+        if encoding.is_some() {
+            // ranges would refer to the compressed bytes, so these can't be resumed
+            validator = None;
+        }
+
+        if !resumed {
+            ctx.update_partials(|partials| {
+                partials.insert(
+                    &command.file,
+                    PartialDownload {
+                        checksum: command.checksum.clone(),
+                        validator: validator.clone(),
+                    },
+                )
+            });
+        }
+
+        // counts what ends up in the file, so compressed responses add up to its size as well
+        let copy_result = copy_buffered(
+            &mut http::decode(reader, encoding),
+            &mut writer,
+            buf,
+            ctx.observer,
+            &command.file,
+        );
+
+        match copy_result {
+            Ok(_) => return Ok(last_modified),
+            Err(_) if ctx.observer.should_cancel() => return InterruptedSnafu.fail(),
+            Err(e) if validator.is_some() && attempts < MAX_RESUME_ATTEMPTS => {
+                attempts += 1;
+                ctx.observer.suspend(&mut || {
+                    warn!("transfer of {} interrupted ({e}), resuming", command.file)
+                });
+            }
+            Err(e) => return Err(Error::Io { source: e }),
+        }
+    }
+}
+
+// downloads <mod>.zip and extracts it over the mod's directory. archives are small enough compared
+// to whole mods that they're simply fetched again if interrupted
+fn fetch_archive(
+    ctx: &DownloadContext,
+    mod_name: &str,
+    size: u64,
+    buf: &mut [u8],
+) -> Result<(), Error> {
+    let file = format!("{mod_name}.zip");
+
+    let (response, mirror) = ctx
+        .mirrors
+        .call(ctx.client, &file, |url| ctx.client.get_file(url))
+        .context(HttpSnafu {
+            url: ctx.mirrors.url(&file),
+        })?;
+
+    ctx.observer
+        .suspend(&mut || debug!("fetching {file} from {mirror}"));
+    ctx.observer.on_file_start(&file, size, 0);
+
+    std::fs::create_dir_all(partial_downloads::data_dir(ctx.local_base)).context(IoSnafu)?;
+    let archive_path = partial_downloads::data_path(ctx.local_base, &file);
+    let mut output = File::create(&archive_path).context(IoSnafu)?;
+
+    let encoding = http::content_encoding(&response);
+    let copy_result = copy_buffered(
+        &mut http::decode(response.into_reader(), encoding),
+        &mut output,
+        buf,
+        ctx.observer,
+        &file,
+    );
+
+    match copy_result {
+        Ok(_) => {}
+        Err(_) if ctx.observer.should_cancel() => return InterruptedSnafu.fail(),
+        Err(e) => return Err(Error::Io { source: e }),
+    }
+
+    drop(output);
+
+    mod_source::extract_archive(&archive_path, &ctx.local_base.join(mod_name))
+        .context(ArchiveSnafu)?;
+    std::fs::remove_file(&archive_path).context(IoSnafu)?;
+
+    Ok(())
+}
+
+// splices a changed range into the existing local file
+fn patch_file(ctx: &DownloadContext, command: &DownloadCommand) -> Result<(), Error> {
+    // the mod.srf stops describing the mod as soon as we start writing. without it, a sync that
+    // gets interrupted here rescans the mod next time instead of trusting stale parts
+    let mod_name = command.file.split('/').next().unwrap_or_default();
+    match std::fs::remove_file(ctx.local_base.join(mod_name).join("mod.srf")) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(Error::Io { source: e });
+        }
+        _ => {}
+    }
+
+    let mut output = File::options()
+        .read(true)
+        .write(true)
+        .open(ctx.local_base.join(Path::new(&command.file)))
+        .context(IoSnafu)?;
+
+    // every range of the file sets the same length, so the order they run in doesn't matter
+    output.set_len(command.length).context(IoSnafu)?;
+
+    fetch_range(
+        ctx.client,
+        ctx.mirrors,
+        &command.file,
+        command.begin,
+        command.end,
+        &mut output,
+        command.checksum.algorithm(),
+    )?;
+
+    // other ranges of the file may still be in flight, so check the parts we wrote rather than the
+    // whole file
+    for part in &command.parts {
+        let actual = hash_range(
+            &mut output,
+            part.start,
+            part.length,
+            part.checksum.algorithm(),
+        )
+        .context(IoSnafu)?;
+
+        ensure!(
+            actual.verify(&part.checksum),
+            PartChecksumMismatchSnafu {
+                file: &command.file,
+                part: &part.path,
+                expected: part.checksum.clone(),
+                actual,
+            }
+        );
+    }
+
+    Ok(())
+}
+
+fn hash_range(
+    file: &mut File,
+    start: u64,
+    length: u64,
+    algorithm: Algorithm,
+) -> std::io::Result<Checksum> {
+    file.seek(SeekFrom::Start(start))?;
+
+    Checksum::from_reader(algorithm, &mut file.take(length))
+}
+
+fn execute_command(
+    ctx: &DownloadContext,
+    command: &DownloadCommand,
+    buf: &mut [u8],
+) -> Result<(), Error> {
+    ensure!(!ctx.observer.should_cancel(), InterruptedSnafu);
+
+    if !command.is_whole_file() {
+        return patch_file(ctx, command);
+    }
+
+    // download into a partial file first in case we have a failure. this avoids us writing garbage
+    // data which will later make us crash in gen_srf. it lives next to the mods rather than inside
+    // one so a crash can't leave it behind in a mod directory, while still being on the same
+    // filesystem so moving it into place is atomic
+    let partial_path = partial_downloads::data_path(ctx.local_base, &command.file);
+    std::fs::create_dir_all(partial_downloads::data_dir(ctx.local_base)).context(IoSnafu)?;
+
+    let mut partial_file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&partial_path)
+        .context(IoSnafu)?;
+
+    // only continue a partial download of the exact version we want now
+    let validator = ctx
+        .partials
+        .lock()
+        .unwrap()
+        .get(&command.file)
+        .filter(|partial| partial.checksum == command.checksum)
+        .and_then(|partial| partial.validator.clone());
+
+    let mut verifier = if ctx.options.verify_parts {
+        let verifier = PartVerifier::new(&command.parts);
+
+        if verifier.is_none() {
+            ctx.observer.suspend(&mut || {
+                warn!(
+                    "parts of {} don't cover the whole file, can't verify them",
+                    command.file
+                )
+            });
+        }
+
+        verifier
+    } else {
+        None
+    };
+
+    if validator.is_some() {
+        // the verifier has to see every byte, including the ones from the earlier run
+        if let Some(verifier) = verifier.as_mut() {
+            loop {
+                let len = partial_file.read(buf).context(IoSnafu)?;
+
+                if len == 0 {
+                    break;
+                }
+
+                verifier.update(&buf[..len]);
+            }
+        }
+
+        let downloaded = partial_file.seek(SeekFrom::End(0)).context(IoSnafu)?;
+
+        if downloaded > 0 {
+            ctx.observer
+                .suspend(&mut || info!("resuming {} from byte {}", command.file, downloaded));
+        }
+    } else {
+        partial_file.set_len(0).context(IoSnafu)?;
+    }
+
+    let last_modified = download_file(
+        ctx,
+        command,
+        &mut partial_file,
+        verifier.as_mut(),
+        buf,
+        validator,
+    )?;
+
+    if let Some(verifier) = verifier {
+        for part in verifier.corrupt_parts() {
+            ctx.observer.suspend(&mut || {
+                warn!(
+                    "part {} of {} is corrupt, fetching it again",
+                    part.path, command.file
+                )
+            });
+
+            refetch_part(
+                ctx.client,
+                ctx.mirrors,
+                &command.file,
+                part,
+                &mut partial_file,
+            )?;
+        }
+    }
+
+    // the pbo's own timestamps are the same on every mirror, prefer those
+    let mtime = if ctx.options.preserve_mtimes {
+        let pbo_timestamp = if command.is_pbo() {
+            partial_file.seek(SeekFrom::Start(0)).context(IoSnafu)?;
+            Pbo::read(BufReader::new(&mut partial_file))
+                .ok()
+                .and_then(|pbo| pbo.newest_timestamp())
+        } else {
+            None
+        };
+
+        pbo_timestamp.or(last_modified)
+    } else {
+        None
+    };
+
+    drop(partial_file);
+
+    // hash the download the same way gen_srf will, so a truncated or corrupt response never makes
+    // it into the mod
+    let data_dir = partial_downloads::data_dir(ctx.local_base);
+    let scanned = if command.is_pbo() {
+        srf::scan_pbo(&partial_path, &data_dir, command.checksum.algorithm())
+    } else {
+        srf::scan_file(&partial_path, &data_dir, command.checksum.algorithm())
+    }
+    .context(SrfGenerationSnafu)?;
+
+    if !scanned.checksum.verify(&command.checksum) {
+        // the bytes are useless, don't resume from them next time
+        std::fs::remove_file(&partial_path).context(IoSnafu)?;
+        ctx.update_partials(|partials| partials.remove(&command.file));
+
+        return ChecksumMismatchSnafu {
+            file: &command.file,
+            expected: command.checksum.clone(),
+            actual: scanned.checksum,
+        }
+        .fail();
+    }
+
+    // move the finished download into place
+    let file_path = ctx.local_base.join(Path::new(&command.file));
+    std::fs::create_dir_all(file_path.parent().expect("file_path did not have a parent"))
+        .context(IoSnafu)?;
+    std::fs::rename(&partial_path, &file_path).context(IoSnafu)?;
+
+    ctx.update_partials(|partials| partials.remove(&command.file));
+
+    if let Some(mtime) = mtime {
+        File::options()
+            .write(true)
+            .open(&file_path)
+            .and_then(|file| file.set_modified(mtime))
+            .context(IoSnafu)?;
+    }
+
+    Ok(())
+}
+
+fn execute_command_list(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    local_base: &Path,
+    cache_path: &Path,
+    diff: &SyncDiff,
+    options: &SyncOptions,
+    observer: &dyn SyncObserver,
+) -> Result<(), Error> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .context(ThreadPoolSnafu)?;
+
+    let partials_path = partial_downloads::record_path(cache_path);
+    let partials = PartialDownloads::from_disk_or_empty(&partials_path).unwrap_or_else(|e| {
+        warn!("failed to read partial downloads, starting them over: {e}");
+        PartialDownloads::default()
+    });
+
+    let ctx = DownloadContext {
+        client,
+        mirrors,
+        local_base,
+        options,
+        observer,
+        partials: Mutex::new(partials),
+        partials_path,
+    };
+
+    let res = pool.install(|| {
+        diff.downloads.par_iter().try_for_each_init(
+            || vec![0; options.download_buffer],
+            |buf, command| {
+                let size = command.end - command.begin;
+
+                output::emit(
+                    options.format,
+                    &output::Event::DownloadStarted {
+                        file: &command.file,
+                        size,
+                    },
+                );
+
+                let res = execute_command(&ctx, command, buf);
+
+                match &res {
+                    Ok(()) => output::emit(
+                        options.format,
+                        &output::Event::DownloadFinished {
+                            file: &command.file,
+                            size,
+                        },
+                    ),
+                    Err(Error::Interrupted) => {}
+                    Err(e) => output::emit(
+                        options.format,
+                        &output::Event::DownloadFailed {
+                            file: &command.file,
+                            error: e.to_string(),
+                        },
+                    ),
+                }
+
+                observer.on_file_done(&command.file, size, res.as_ref().err());
+
+                res
+            },
+        )
+    });
+
+    // archives replace whole mods, so they only go once everything else made it
+    let res = res.and_then(|()| {
+        let mut buf = vec![0; options.download_buffer];
+
+        diff.archives.iter().try_for_each(|(mod_name, size)| {
+            let file = format!("{mod_name}.zip");
+
+            output::emit(
+                options.format,
+                &output::Event::DownloadStarted {
+                    file: &file,
+                    size: *size,
+                },
+            );
+
+            let res = fetch_archive(&ctx, mod_name, *size, &mut buf);
+
+            match &res {
+                Ok(()) => output::emit(
+                    options.format,
+                    &output::Event::DownloadFinished {
+                        file: &file,
+                        size: *size,
+                    },
+                ),
+                Err(Error::Interrupted) => {}
+                Err(e) => output::emit(
+                    options.format,
+                    &output::Event::DownloadFailed {
+                        file: &file,
+                        error: e.to_string(),
+                    },
+                ),
+            }
+
+            observer.on_file_done(&file, *size, res.as_ref().err());
+
+            res
+        })
+    });
+
+    observer.on_downloads_finished();
+    res?;
+
+    // everything landed, so whatever is still in here belongs to files we no longer want. failing
+    // to clean up only wastes some space
+    let _ = std::fs::remove_dir_all(partial_downloads::data_dir(local_base));
+    let _ = std::fs::remove_file(&ctx.partials_path);
+
+    Ok(())
+}
+
+pub fn sync(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    base_path: &Path,
+    cache_path: &Path,
+    options: &SyncOptions,
+    observer: &dyn SyncObserver,
+) -> Result<(), Error> {
+    let remote_repo =
+        repository::get_mirrored_repository_info(client, mirrors, options.max_repo_size)
+            .context(RepositoryFetchSnafu)?;
+
+    let client = &remote_repo.authorized_client(client);
+
+    // freshly created repos have nothing in them yet, there's no point in touching the cache
+    if remote_repo.required_mods.is_empty() && remote_repo.optional_mods.is_empty() {
+        info!("repo has no mods, nothing to sync");
+        return Ok(());
+    }
+
+    let mut mod_cache = open_cache_or_gen_srf(base_path, cache_path, &options.mod_dirs)
+        .context(ModCacheOpenSnafu)?;
+
+    // a mod changed behind our back would otherwise be diffed with checksums it no longer has
+    if options.revalidate
+        && revalidate_cache(base_path, &mut mod_cache, !options.dry_run).context(ModRescanSnafu)?
+        && !options.dry_run
+    {
+        mod_cache.to_disk(cache_path).context(ModCacheWriteSnafu)?;
+    }
+
+    let journal_path = sync_journal::path(cache_path);
+
+    // a sync that died halfway left its mods somewhere between the cache and the repo, carry on
+    // from what is actually on disk
+    if let Some(journal) = sync_journal::Journal::from_disk(&journal_path).context(JournalSnafu)? {
+        warn!("the previous sync didn't finish, rescanning the mods it was changing");
+
+        if !options.dry_run {
+            resume_journal(base_path, &mut mod_cache, &journal).context(ModRescanSnafu)?;
+            mod_cache.to_disk(cache_path).context(ModCacheWriteSnafu)?;
+            sync_journal::remove(&journal_path).context(JournalSnafu)?;
+        }
+    }
+
+    let previous_selection = mod_cache.optional.clone();
+    let deselected = select_optional_mods(&mut mod_cache.optional, &remote_repo, options);
+
+    let excluded = excluded_mods(&remote_repo, options);
+    let wanted: Vec<_> = wanted_mods(&remote_repo, &mod_cache.optional)
+        .into_iter()
+        .filter(|r#mod| !excluded.contains(&r#mod.mod_name.to_lowercase()))
+        .collect();
+    let wanted_names: HashSet<_> = wanted
+        .iter()
+        .map(|r#mod| r#mod.mod_name.to_lowercase())
+        .collect();
+
+    // the same name may be both disabled and wanted if the repo lists it twice, wanted wins
+    let mut disabled: Vec<_> = remote_repo
+        .disabled_mod_names()
+        .into_iter()
+        .filter(|name| !wanted_names.contains(name))
+        .collect();
+    disabled.sort();
+
+    let (mut check, renames) = diff_repo(&mod_cache, &wanted);
+
+    // a rename moves the old directory, so it mustn't be removed as dropped first
+    let renamed: HashSet<_> = renames
+        .iter()
+        .map(|rename| rename.from.to_lowercase())
+        .collect();
+    let dropped: Vec<_> = dropped_mods(&mod_cache, &remote_repo)
+        .into_iter()
+        .filter(|name| !renamed.contains(&name.to_lowercase()))
+        .collect();
+
+    let excluded: Vec<_> = if options.keep_excluded {
+        vec![]
+    } else {
+        excluded.into_iter().collect()
+    };
+
+    let removals = deselected
+        .iter()
+        .map(|name| ("deselected optional", name))
+        .chain(disabled.iter().map(|name| ("disabled", name)))
+        .chain(excluded.iter().map(|name| ("excluded", name)))
+        .chain(dropped.iter().map(|name| ("dropped", name)));
+
+    let mut removed_any = false;
+    let mut mod_removals = SyncDiff::default();
+    let mut removed_names = vec![];
+
+    for (reason, name) in removals {
+        // find_mod_dir only ever returns entries of base_path itself
+        let Some(dir) = find_mod_dir(base_path, name) else {
+            if !options.dry_run
+                && (mod_cache.repo_checksums.contains_key(&name.to_lowercase())
+                    || mod_cache
+                        .mods
+                        .values()
+                        .any(|r#mod| r#mod.name.eq_ignore_ascii_case(name)))
+            {
+                mod_cache.remove_by_name(name);
+                removed_any = true;
+            }
+            continue;
+        };
+
+        if options.dry_run {
+            info!("would remove {reason} mod {}", dir.display());
+        } else {
+            mod_removals.removed_mods.push((reason, dir.clone()));
+            removed_names.push((name, dir));
+        }
+    }
+
+    // a mod that couldn't be removed entirely stays cached, so the next sync tries again
+    let failed_removals = apply_removals(base_path, &mod_removals);
+    for (name, dir) in removed_names {
+        if !failed_removals.contains(&dir) {
+            mod_cache.remove_by_name(name);
+            removed_any = true;
+        }
+    }
+
+    // the selection and removals have to stick even if there turns out to be nothing to download
+    if !options.dry_run && (removed_any || mod_cache.optional != previous_selection) {
+        mod_cache.to_disk(cache_path).context(ModCacheWriteSnafu)?;
+    }
+
+    ensure!(
+        failed_removals.is_empty(),
+        RemovalsSnafu {
+            count: failed_removals.len()
+        }
+    );
+
+    if options.dry_run {
+        for rename in &renames {
+            info!("would rename {} to {}", rename.from, rename.to.mod_name);
+        }
+    } else {
+        check.extend(apply_renames(base_path, &mut mod_cache, renames)?);
+    }
+
+    if check.is_empty() {
+        info!("all mods are up to date");
+        output::emit(
+            options.format,
+            &output::Event::Summary {
+                dry_run: options.dry_run,
+                files: 0,
+                bytes: 0,
+                success: true,
+            },
+        );
+        install_keys(base_path, cache_path, &mod_cache, options)?;
+        record_snapshot(cache_path, &remote_repo);
+        return Ok(());
+    }
+
+    debug!("mods to check: {check:#?}");
+    output::emit(
+        options.format,
+        &output::Event::ModsToCheck {
+            mods: check.iter().map(|r#mod| r#mod.mod_name.as_str()).collect(),
+        },
+    );
+
+    let cached: Vec<CachedMod> = check
+        .iter()
+        .map(|r#mod| {
+            let entry = mod_cache
+                .mods
+                .iter()
+                .find(|(_, cached)| cached.name.eq_ignore_ascii_case(&r#mod.mod_name));
+
+            CachedMod {
+                present: entry.is_some(),
+                srf: entry.and_then(|(checksum, cached)| cached.to_srf(checksum)),
+                repo_checksum: mod_cache
+                    .repo_checksums
+                    .get(&r#mod.mod_name.to_lowercase())
+                    .cloned(),
+            }
+        })
+        .collect();
+
+    // remove all mods to check from cache, we'll read them later
+    for r#mod in &check {
+        mod_cache.remove_by_name(&r#mod.mod_name);
+    }
+
+    let mut diff = SyncDiff::default();
+
+    for (r#mod, cached) in check.iter().zip(cached) {
+        // nothing was written yet, so there's nothing to save either
+        ensure!(!observer.should_cancel(), InterruptedSnafu);
+
+        diff_mod(client, mirrors, base_path, r#mod, cached, &mut diff)?;
+    }
+
+    // ignored paths belong to the user, even where the repo ships a file of the same name
+    let ignore = NimbleIgnore::load(base_path);
+    diff.downloads.retain(|command| {
+        let ignored = ignore.is_ignored(&base_path.join(&command.file), false);
+        if ignored {
+            debug!("not downloading {}, it is ignored", command.file);
+        }
+        !ignored
+    });
+    diff.leftovers
+        .retain(|path| !ignore.is_ignored(path, false));
+
+    trace!("download commands: {:#?}", diff.downloads);
+
+    if options.validate_remote {
+        validate_remote(client, mirrors, &diff.downloads)?;
+    }
+
+    let summary = summarize_downloads(&diff.downloads, &diff.archives);
+
+    if options.dry_run {
+        for dir in &diff.stale_dirs {
+            info!("would remove partially downloaded mod {}", dir.display());
+        }
+
+        for path in &diff.leftovers {
+            info!("would remove leftover file {}", path.display());
+        }
+
+        for (mod_name, (files, bytes)) in &summary.mods {
+            info!(
+                "  {mod_name}: {files} files, {}",
+                output::format_size(*bytes)
+            );
+        }
+
+        info!(
+            "would download {} files, {}",
+            summary.files,
+            output::format_size(summary.bytes)
+        );
+
+        output::emit(
+            options.format,
+            &output::Event::Summary {
+                dry_run: true,
+                files: summary.files,
+                bytes: summary.bytes,
+                success: true,
+            },
+        );
+
+        return Ok(());
+    }
+
+    sync_journal::Journal {
+        mods: check.iter().map(|r#mod| r#mod.mod_name.clone()).collect(),
+    }
+    .to_disk(&journal_path)
+    .context(JournalSnafu)?;
+
+    // files that couldn't be removed only fail the sync once everything else is done. their mods
+    // are rescanned with them, so the next sync tries again
+    let failed_removals = apply_removals(base_path, &diff);
+
+    // patches write into files in place, which must not reach other mods sharing the file
+    let patched: BTreeSet<_> = diff
+        .downloads
+        .iter()
+        .filter(|command| !command.is_whole_file())
+        .map(|command| command.file.as_str())
+        .collect();
+
+    for file in patched {
+        dedup::break_hardlink(&base_path.join(Path::new(file))).context(IoSnafu)?;
+    }
+
+    observer.on_diff_complete(diff.downloads.len() + diff.archives.len(), summary.bytes);
+
+    let started = Instant::now();
+    let res = execute_command_list(
+        client, mirrors, base_path, cache_path, &diff, options, observer,
+    );
+
+    let downloaded = res.is_ok();
+    output::emit(
+        options.format,
+        &output::Event::Summary {
+            dry_run: false,
+            files: summary.files,
+            bytes: summary.bytes,
+            success: downloaded,
+        },
+    );
+
+    let download_error = match res {
+        Ok(()) => {
+            info!(
+                "synced {} mods, {} files, {} in {:.1}s",
+                summary.mods.len(),
+                summary.files,
+                output::format_size(summary.bytes),
+                started.elapsed().as_secs_f64()
+            );
+
+            if options.dedup {
+                link_duplicates(base_path, &mod_cache, &diff.downloads);
+            }
+
+            if let Some(keys_dir) = &options.check_signatures {
+                check_signatures(base_path, keys_dir, &diff.downloads);
+            }
+
+            None
+        }
+        Err(Error::Interrupted) => None,
+        Err(e) => {
+            // the summary already said so
+            if options.format != output::Format::Json {
+                error!("an error occurred while downloading: {e}");
+                error!("you should retry this command");
+            }
+
+            Some(e)
+        }
+    };
+
+    // gen_srf for the mods we downloaded. when interrupted, keep the ones already done and leave
+    // the rest out of the cache, so the next sync checks them again
+    for r#mod in &check {
+        if observer.should_cancel() {
+            break;
+        }
+
+        // a new mod whose downloads all failed never got a directory
+        let Some(mod_path) = find_mod_dir(base_path, &r#mod.mod_name) else {
+            mod_cache.remove_by_name(&r#mod.mod_name);
+            continue;
+        };
+
+        // hashed like the repo does, so the cache matches repo.json next time
+        let scan_options = srf::ScanOptions {
+            algorithm: r#mod.checksum.algorithm(),
+            ..srf::ScanOptions::default()
+        };
+        let srf = match gen_srf_for_mod_with(&mod_path, false, &scan_options) {
+            Ok(srf) => srf,
+            // the download error is what the user needs to see, the mod gets checked next time
+            Err(e) if download_error.is_some() => {
+                warn!("failed to rescan {}: {e}", r#mod.mod_name);
+                mod_cache.remove_by_name(&r#mod.mod_name);
+                continue;
+            }
+            Err(source) => return Err(Error::ModRescan { source }),
+        };
+
+        // an archive can't be checked before it's extracted, and would be fetched again every time
+        if srf.checksum != r#mod.checksum
+            && diff
+                .archives
+                .iter()
+                .any(|(name, _)| *name == r#mod.mod_name)
+        {
+            warn!(
+                "{} doesn't match its checksum in repo.json after extracting it",
+                r#mod.mod_name
+            );
+        }
+
+        mod_cache.insert(srf);
+
+        // after a failed download the mod may still be half done, and one with files that couldn't
+        // be removed still has them. either has to be checked again
+        if downloaded
+            && !failed_removals
+                .iter()
+                .any(|path| path.starts_with(&mod_path))
+        {
+            mod_cache
+                .repo_checksums
+                .insert(r#mod.mod_name.to_lowercase(), r#mod.checksum.clone());
+        }
+    }
+
+    // reserialize the cache
+    mod_cache.to_disk(cache_path).context(ModCacheWriteSnafu)?;
+    sync_journal::remove(&journal_path).context(JournalSnafu)?;
+
+    ensure!(!observer.should_cancel(), InterruptedSnafu);
+
+    // the mods that finished are cached, but the repo wasn't fully applied
+    if let Some(e) = download_error {
+        return Err(e);
+    }
+
+    install_keys(base_path, cache_path, &mod_cache, options)?;
+    ensure!(
+        failed_removals.is_empty(),
+        RemovalsSnafu {
+            count: failed_removals.len()
+        }
+    );
+
+    record_snapshot(cache_path, &remote_repo);
+
+    Ok(())
+}
+
+// copies the keys of every installed mod into the keys directory, if there is one
+fn install_keys(
+    base_path: &Path,
+    cache_path: &Path,
+    mod_cache: &ModCache,
+    options: &SyncOptions,
+) -> Result<(), Error> {
+    let Some(keys_dir) = options.keys_dir.as_deref().filter(|_| !options.dry_run) else {
+        return Ok(());
+    };
+
+    let mod_names = mod_cache.mods.values().map(|r#mod| r#mod.name.as_str());
+
+    keys::install_keys(
+        base_path,
+        mod_names,
+        keys_dir,
+        &keys::record_path(cache_path),
+    )
+    .context(KeysSnafu)
+}
+
+// sends a HEAD for every file about to be downloaded, so a mod.srf listing files the server doesn't
+// have, or has in a different size, fails the sync before it downloads anything
+fn validate_remote(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    downloads: &[DownloadCommand],
+) -> Result<(), Error> {
+    let files: BTreeMap<_, _> = downloads
+        .iter()
+        .map(|command| (command.file.as_str(), command.length))
+        .collect();
+
+    let unavailable = files
+        .par_iter()
+        .filter(|(file, length)| {
+            let response = match mirrors.call(client, file, |url| client.head(url)) {
+                Ok((response, _)) => response,
+                // the server doesn't do HEAD, only the download itself can tell
+                Err(ureq::Error::Status(405 | 501, _)) => return false,
+                Err(e) => {
+                    warn!("{file}: {e}");
+                    return true;
+                }
+            };
+
+            // an encoded response only gives the length of what goes over the wire
+            let remote_length = response
+                .header("Content-Length")
+                .filter(|_| response.header("Content-Encoding").is_none())
+                .and_then(|len| len.parse::<u64>().ok());
+
+            match remote_length {
+                Some(remote_length) if remote_length != **length => {
+                    warn!(
+                        "{file} is {remote_length} bytes on the server, but mod.srf says {length}"
+                    );
+                    true
+                }
+                _ => false,
+            }
+        })
+        .count();
+
+    ensure!(
+        unavailable == 0,
+        UnavailableFilesSnafu { count: unavailable }
+    );
+
+    Ok(())
+}
+
+// replaces freshly downloaded files with hardlinks to identical ones elsewhere in base_path,
+// either from mods that were already synced or from earlier downloads of this sync
+fn link_duplicates(base_path: &Path, mod_cache: &ModCache, downloads: &[DownloadCommand]) {
+    let mut index = dedup::Index::from_cache(base_path, mod_cache);
+    let mut linked = 0;
+    let mut saved = 0;
+
+    for command in downloads.iter().filter(|command| command.is_whole_file()) {
+        let path = base_path.join(Path::new(&command.file));
+
+        match index.get(&command.checksum, command.length) {
+            Some(original) => match dedup::link_duplicate(original, &path) {
+                Ok(true) => {
+                    linked += 1;
+                    saved += command.length;
+                }
+                Ok(false) => {}
+                Err(e) => warn!("failed to link {}: {e}", path.display()),
+            },
+            None => index.insert(&command.checksum, command.length, path),
+        }
+    }
+
+    if linked > 0 {
+        info!(
+            "linked {linked} duplicate files, saving {}",
+            output::format_size(saved)
+        );
+    }
+}
+
+// warns about downloaded pbos that no key in keys_dir signed, including pbos whose signature was
+// the only thing that changed
+fn check_signatures(base_path: &Path, keys_dir: &Path, downloads: &[DownloadCommand]) {
+    let keys = match signature::load_keys(keys_dir) {
+        Ok(keys) => keys,
+        Err(e) => {
+            warn!("can't check signatures: {e}");
+            return;
+        }
+    };
+
+    let pbos: BTreeSet<_> = downloads
+        .iter()
+        .filter_map(|command| {
+            let lowercase = command.file.to_lowercase();
+
+            if lowercase.ends_with(".pbo") {
+                Some(command.file.clone())
+            } else if lowercase.ends_with(".bisign") {
+                let end = lowercase.rfind(".pbo.")? + ".pbo".len();
+                Some(command.file[..end].to_string())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    for pbo in pbos {
+        match signature::check_pbo(&base_path.join(Path::new(&pbo)), &keys) {
+            Ok(()) => debug!("{pbo} is signed"),
+            Err(e) => warn!("{pbo} failed signature check: {e}"),
+        }
+    }
+}
+
+// keep a copy of what we just synced so the changelog command has something to compare against
+fn record_snapshot(cache_path: &Path, repo: &repository::Repository) {
+    if let Err(e) = repo_history::record(&repo_history::history_dir(cache_path), repo) {
+        warn!("failed to record repository snapshot: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reports nothing and never cancels
+    impl SyncObserver for () {}
+    use crate::test_server;
+    use relative_path::RelativePathBuf;
+    use std::time::Duration;
+
+    fn test_repository(mods: &[&srf::Mod]) -> repository::Repository {
+        repository::Repository {
+            repo_name: "test".to_string(),
+            checksum: String::new(),
+            required_mods: mods
+                .iter()
+                .map(|r#mod| repository::Mod {
+                    mod_name: r#mod.name.clone(),
+                    checksum: r#mod.checksum.clone(),
+                    enabled: true,
+                })
+                .collect(),
+            optional_mods: vec![],
+            client_parameters: String::new(),
+            repo_basic_authentication: None,
+            version: "1".to_string(),
+            servers: vec![],
+        }
+    }
+
+    fn test_options() -> SyncOptions {
+        SyncOptions {
+            dry_run: false,
+            max_repo_size: repository::DEFAULT_MAX_REPO_SIZE,
+            verify_parts: false,
+            download_buffer: DEFAULT_DOWNLOAD_BUFFER,
+            preserve_mtimes: false,
+            dedup: false,
+            check_signatures: None,
+            jobs: None,
+            with_optional: vec![],
+            without_optional: vec![],
+            all_optional: false,
+            exclude_mods: vec![],
+            keep_excluded: false,
+            keys_dir: None,
+            mod_dirs: ModDirs::default(),
+            revalidate: false,
+            validate_remote: false,
+            format: output::Format::Human,
+        }
+    }
+
+    fn test_client() -> http::Client {
+        http::Client::new(
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+        )
+    }
+
+    // serves the mod at remote_dir like a repo would, with repo.json listing only it and its
+    // mod.srf. its files aren't served from remote_dir but from extra_files, so they can differ
+    // from what mod.srf says
+    fn serve_mod(remote_dir: &Path, extra_files: &[(&str, &[u8])]) -> (String, srf::Mod) {
+        let remote_srf = srf::scan_mod(remote_dir).unwrap();
+
+        let mut files = HashMap::from([
+            (
+                "repo.json".to_string(),
+                serde_json::to_vec(&test_repository(&[&remote_srf])).unwrap(),
+            ),
+            (
+                format!("{}/mod.srf", remote_srf.name),
+                serde_json::to_vec(&remote_srf).unwrap(),
+            ),
+        ]);
+        files.extend(
+            extra_files
+                .iter()
+                .map(|(path, contents)| (path.to_string(), contents.to_vec())),
+        );
+
+        (test_server::serve(files), remote_srf)
+    }
+
+    // syncs base_path from the repo at url, keeping the cache in its default place
+    fn run_sync(url: &str, base_path: &Path, options: &SyncOptions) -> Result<(), Error> {
+        sync(
+            &test_client(),
+            &http::Mirrors::new([url.to_string()]),
+            base_path,
+            &base_path.join("nimble-cache.json"),
+            options,
+            &(),
+        )
+    }
+
+    #[test]
+    fn diff_repo_rename_test() {
+        let digest = |s: &str| Checksum::new(s).unwrap();
+        let cached_mod = |name: &str, checksum: &str| srf::Mod {
+            name: name.to_string(),
+            checksum: digest(checksum),
+            algorithm: Algorithm::Md5,
+            files: vec![],
+        };
+
+        let mod_cache = ModCache::from_mods([
+            cached_mod("@ace", "00000000000000000000000000000001"),
+            cached_mod("@cba", "00000000000000000000000000000002"),
+        ]);
+
+        let mut remote = test_repository(&[]);
+        remote.required_mods = vec![
+            repository::Mod {
+                mod_name: "@ace_compat".to_string(),
+                checksum: digest("00000000000000000000000000000001"),
+                enabled: true,
+            },
+            // still required under its old name, so this is a copy and not a rename
+            repository::Mod {
+                mod_name: "@cba".to_string(),
+                checksum: digest("00000000000000000000000000000003"),
+                enabled: true,
+            },
+            repository::Mod {
+                mod_name: "@cba_copy".to_string(),
+                checksum: digest("00000000000000000000000000000002"),
+                enabled: true,
+            },
+        ];
+
+        let wanted: Vec<_> = remote.required_mods.iter().collect();
+        let (check, renames) = diff_repo(&mod_cache, &wanted);
+
+        assert_eq!(
+            check,
+            vec![&remote.required_mods[1], &remote.required_mods[2]]
+        );
+
+        // synced before with the checksum the repo still lists, even though it isn't @cba's own
+        let mut synced_cache = ModCache::from_mods([
+            cached_mod("@ace", "00000000000000000000000000000001"),
+            cached_mod("@cba", "00000000000000000000000000000002"),
+        ]);
+        synced_cache.repo_checksums.insert(
+            "@cba".to_string(),
+            digest("00000000000000000000000000000003"),
+        );
+        let (check, _) = diff_repo(&synced_cache, &wanted);
+        assert_eq!(check, vec![&remote.required_mods[2]]);
+        assert_eq!(
+            renames,
+            vec![Rename {
+                from: "@ace".to_string(),
+                to: &remote.required_mods[0],
+            }]
+        );
+    }
+
+    #[test]
+    fn select_optional_mods_test() {
+        let optional_mod = |name: &str| repository::Mod {
+            mod_name: name.to_string(),
+            checksum: Checksum::default(),
+            enabled: true,
+        };
+
+        let mut remote = test_repository(&[]);
+        remote.required_mods = vec![optional_mod("@ace")];
+        remote.optional_mods = vec![optional_mod("@JSRS"), optional_mod("@blastcore")];
+
+        let mut options = test_options();
+        options.with_optional = vec!["@jsrs".to_string(), "@unknown".to_string()];
+
+        let mut selection = BTreeSet::new();
+        assert!(select_optional_mods(&mut selection, &remote, &options).is_empty());
+        assert_eq!(selection, BTreeSet::from(["@jsrs".to_string()]));
+        assert_eq!(
+            wanted_mods(&remote, &selection),
+            vec![&remote.required_mods[0], &remote.optional_mods[0]]
+        );
+
+        // selections persist, so later runs without flags keep the same mods
+        options.with_optional.clear();
+        options.all_optional = true;
+        options.without_optional = vec!["@JSRS".to_string(), "@ace".to_string()];
+
+        assert_eq!(
+            select_optional_mods(&mut selection, &remote, &options),
+            vec!["@jsrs"]
+        );
+        assert_eq!(
+            wanted_mods(&remote, &selection),
+            vec![&remote.required_mods[0], &remote.optional_mods[1]]
+        );
+    }
+
+    #[test]
+    fn empty_repo_test() {
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+
+        let url = test_server::serve(HashMap::from([(
+            "repo.json".to_string(),
+            serde_json::to_vec(&test_repository(&[])).unwrap(),
+        )]));
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert!(!cache_path.exists());
+    }
+
+    #[test]
+    fn resume_partial_download_test() {
+        let contents = b"the quick brown fox jumps over the lazy dog";
+
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), contents).unwrap();
+        let remote_srf = srf::scan_mod(&remote.path().join("@test")).unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+
+        // the server's copy differs in its first bytes, so a full download instead of a resumed
+        // one would be noticed
+        let mut served = contents.to_vec();
+        served[..3].copy_from_slice(b"THE");
+        let etag = format!("\"{}\"", Algorithm::Md5.digest(&served).to_hex_lower());
+
+        // what an earlier run that got killed halfway through would have left behind
+        let partial_path = partial_downloads::data_path(local.path(), "@test/a.txt");
+        std::fs::create_dir_all(partial_path.parent().unwrap()).unwrap();
+        std::fs::write(&partial_path, &contents[..20]).unwrap();
+
+        let mut partials = PartialDownloads::default();
+        partials.insert(
+            "@test/a.txt",
+            PartialDownload {
+                checksum: remote_srf.files[0].checksum.clone(),
+                validator: Some(etag),
+            },
+        );
+        partials
+            .to_disk(&partial_downloads::record_path(&cache_path))
+            .unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", served.as_slice())],
+        );
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert_eq!(
+            std::fs::read(local.path().join("@test/a.txt")).unwrap(),
+            contents
+        );
+        assert!(!partial_downloads::data_dir(local.path()).exists());
+        assert!(!partial_downloads::record_path(&cache_path).exists());
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        bytes: Mutex<u64>,
+        done: Mutex<Vec<String>>,
+        cancel: bool,
+    }
+
+    impl SyncObserver for Recorder {
+        fn on_bytes(&self, _file: &str, delta: u64) {
+            *self.bytes.lock().unwrap() += delta;
+        }
+
+        fn on_file_done(&self, file: &str, _size: u64, error: Option<&Error>) {
+            assert!(error.is_none());
+            self.done.lock().unwrap().push(file.to_string());
+        }
+
+        fn should_cancel(&self) -> bool {
+            self.cancel
+        }
+    }
+
+    #[test]
+    fn observer_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), b"aaaa").unwrap();
+        std::fs::write(remote.path().join("@test/b.txt"), b"bb").unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[
+                ("@test/a.txt", b"aaaa".as_slice()),
+                ("@test/b.txt", b"bb".as_slice()),
+            ],
+        );
+        let mirrors = http::Mirrors::new([url]);
+
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+
+        let cancelled = Recorder {
+            cancel: true,
+            ..Recorder::default()
+        };
+        let res = sync(
+            &test_client(),
+            &mirrors,
+            local.path(),
+            &cache_path,
+            &test_options(),
+            &cancelled,
+        );
+        assert!(matches!(res, Err(Error::Interrupted)));
+        assert!(!local.path().join("@test").exists());
+
+        let recorder = Recorder::default();
+        sync(
+            &test_client(),
+            &mirrors,
+            local.path(),
+            &cache_path,
+            &test_options(),
+            &recorder,
+        )
+        .unwrap();
+
+        let mut done = recorder.done.into_inner().unwrap();
+        done.sort();
+        assert_eq!(done, ["@test/a.txt", "@test/b.txt"]);
+        assert_eq!(recorder.bytes.into_inner().unwrap(), 6);
+    }
+
+    #[test]
+    fn validate_remote_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), b"aaaa").unwrap();
+        std::fs::write(remote.path().join("@test/b.txt"), b"bb").unwrap();
+
+        // b.txt made it into mod.srf, but never onto the server
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"aaaa".as_slice())],
+        );
+
+        let local = tempfile::tempdir().unwrap();
+        let options = SyncOptions {
+            validate_remote: true,
+            ..test_options()
+        };
+        let res = run_sync(&url, local.path(), &options);
+
+        assert!(matches!(res, Err(Error::UnavailableFiles { count: 1 })));
+        assert!(!local.path().join("@test/a.txt").exists());
+    }
+
+    #[test]
+    fn archive_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(remote.path().join("@test/addons")).unwrap();
+        std::fs::write(remote.path().join("@test/addons/a.txt"), b"aaaa").unwrap();
+        let remote_srf = srf::scan_mod(&remote.path().join("@test")).unwrap();
+
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        archive
+            .start_file(
+                "@test/addons/a.txt",
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+        archive.write_all(b"aaaa").unwrap();
+        let archive = archive.finish().unwrap().into_inner();
+
+        // no @test/mod.srf, only the archive
+        let url = test_server::serve(HashMap::from([
+            (
+                "repo.json".to_string(),
+                serde_json::to_vec(&test_repository(&[&remote_srf])).unwrap(),
+            ),
+            ("@test.zip".to_string(), archive),
+        ]));
+
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/stale.txt"), b"old").unwrap();
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert_eq!(
+            std::fs::read(local.path().join("@test/addons/a.txt")).unwrap(),
+            b"aaaa"
+        );
+        assert!(!local.path().join("@test/stale.txt").exists());
+
+        let mod_cache = ModCache::from_disk(&cache_path).unwrap();
+        assert!(mod_cache.mods.contains_key(&remote_srf.checksum));
+    }
+
+    #[test]
+    fn foreign_archive_test() {
+        let archive = |contents: &[u8]| {
+            let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+            archive
+                .start_file("a.txt", zip::write::SimpleFileOptions::default())
+                .unwrap();
+            archive.write_all(contents).unwrap();
+            archive.finish().unwrap().into_inner()
+        };
+
+        // packed by some other tool, so repo.json's checksum isn't the one of the extracted files
+        let mut repo = test_repository(&[]);
+        repo.required_mods.push(repository::Mod {
+            mod_name: "@test".to_string(),
+            checksum: Checksum::from_bytes(&[0xAB; 16]),
+            enabled: true,
+        });
+        let repo = serde_json::to_vec(&repo).unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+
+        for contents in [b"aaaa", b"bbbb"] {
+            let url = test_server::serve(HashMap::from([
+                ("repo.json".to_string(), repo.clone()),
+                ("@test.zip".to_string(), archive(contents)),
+            ]));
+
+            run_sync(&url, local.path(), &test_options()).unwrap();
+        }
+
+        // repo.json didn't change, so the archive wasn't fetched again
+        assert_eq!(
+            std::fs::read(local.path().join("@test/a.txt")).unwrap(),
+            b"aaaa"
+        );
+    }
+
+    #[test]
+    fn changed_ranges_test() {
+        let file = |parts: &[(u64, u64, u8)]| srf::File {
+            path: RelativePathBuf::from("a.bin"),
+            length: parts.iter().map(|part| part.1).sum(),
+            checksum: Checksum::default(),
+            r#type: srf::FileType::File,
+            extensions: None,
+            parts: parts
+                .iter()
+                .map(|&(start, length, checksum)| srf::Part {
+                    path: format!("a.bin_{}", start + length),
+                    length,
+                    start,
+                    checksum: Checksum::from_bytes(&[checksum; 16]),
+                })
+                .collect(),
+        };
+
+        let local = file(&[(0, 5, 0xAA), (5, 5, 0xBB), (10, 5, 0xCC), (15, 5, 0xDD)]);
+
+        // neighbouring changes merge into one range
+        let remote = file(&[(0, 5, 0xAA), (5, 5, 0xEE), (10, 5, 0xFF), (15, 5, 0xDD)]);
+        assert_eq!(changed_ranges(&remote, &local), vec![(5, 15)]);
+
+        let remote = file(&[(0, 5, 0xEE), (5, 5, 0xBB), (10, 5, 0xCC), (15, 5, 0xFF)]);
+        assert_eq!(changed_ranges(&remote, &local), vec![(0, 5), (15, 20)]);
+
+        // a shifted part has to be fetched again even if its contents are the same
+        let remote = file(&[(0, 6, 0xEE), (6, 5, 0xBB), (11, 5, 0xCC), (16, 5, 0xDD)]);
+        assert_eq!(changed_ranges(&remote, &local), vec![(0, 21)]);
+
+        // the file is only diffed because its checksum changed, so identical parts can only mean
+        // the parts are lying
+        let remote = file(&[(0, 5, 0xAA), (5, 5, 0xBB), (10, 5, 0xCC), (15, 5, 0xDD)]);
+        assert_eq!(changed_ranges(&remote, &local), vec![(0, 20)]);
+    }
+
+    #[test]
+    fn patch_file_test() {
+        let mut contents = vec![b'a'; 5_000_001];
+
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.bin"), &contents).unwrap();
+
+        contents[5_000_000] = b'b';
+
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.bin"), &contents).unwrap();
+
+        // the served copy differs in its first part too, which must not be fetched
+        let mut served = contents.clone();
+        served[0] = b'x';
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.bin", served.as_slice())],
+        );
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert!(std::fs::read(local.path().join("@test/a.bin")).unwrap() == contents);
+    }
+
+    #[test]
+    fn checksum_mismatch_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "new").unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "old").unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"bad".as_slice())],
+        );
+
+        let res = run_sync(&url, local.path(), &test_options());
+        assert!(matches!(res, Err(Error::ChecksumMismatch { .. })));
+
+        assert_eq!(
+            std::fs::read_to_string(local.path().join("@test/a.txt")).unwrap(),
+            "old"
+        );
+        assert!(!partial_downloads::data_path(local.path(), "@test/a.txt").exists());
+
+        // the mod is cached as it is on disk, but the repo wasn't applied
+        let cache_path = local.path().join("nimble-cache.json");
+        let mod_cache = ModCache::from_disk(&cache_path).unwrap();
+        assert!(mod_cache.mods.values().any(|r#mod| r#mod.name == "@test"));
+        assert!(mod_cache.repo_checksums.is_empty());
+        assert!(!repo_history::history_dir(&cache_path).exists());
+    }
+
+    #[test]
+    fn failed_new_mod_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "new").unwrap();
+
+        // nothing of the mod is on disk, and its only file fails to download
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"bad".as_slice())],
+        );
+
+        let res = run_sync(&url, local.path(), &test_options());
+        assert!(matches!(res, Err(Error::ChecksumMismatch { .. })));
+        assert!(!sync_journal::path(&cache_path).exists());
+
+        // nor is it served at all
+        let (url, _) = serve_mod(&remote.path().join("@test"), &[]);
+
+        let res = run_sync(&url, local.path(), &test_options());
+        assert!(matches!(res, Err(Error::Http { .. })));
+        assert!(!sync_journal::path(&cache_path).exists());
+        assert!(ModCache::from_disk(&cache_path).unwrap().mods.is_empty());
+    }
+
+    #[test]
+    fn read_remote_srf_test() {
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let legacy =
+            std::fs::read(Path::new(project_root).join("test_files/legacy_format_mod.srf"))
+                .unwrap();
+        let expected = srf::deserialize_legacy_srf(&mut Cursor::new(&legacy)).unwrap();
+
+        let with_bom = [b"\xEF\xBB\xBF".as_slice(), &legacy].concat();
+        assert_eq!(read_remote_srf(legacy.as_slice()).unwrap(), expected);
+        assert_eq!(read_remote_srf(with_bom.as_slice()).unwrap(), expected);
+
+        let json = serde_json::to_vec(&expected).unwrap();
+        let with_bom = [b"\xEF\xBB\xBF".as_slice(), &json].concat();
+        assert_eq!(read_remote_srf(json.as_slice()).unwrap(), expected);
+        assert_eq!(read_remote_srf(with_bom.as_slice()).unwrap(), expected);
+
+        // shorter than the peeked bytes
+        assert!(read_remote_srf(b"{}".as_slice()).is_err());
+    }
+
+    #[test]
+    fn cached_srf_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "new").unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "old").unwrap();
+        let local_srf = srf::scan_mod(&local.path().join("@test")).unwrap();
+        ModCache::from_mods([local_srf])
+            .to_disk(&cache_path)
+            .unwrap();
+
+        // the cache knows the mod's files, so this is never read
+        std::fs::write(local.path().join("@test/mod.srf"), "garbage").unwrap();
+
+        let (url, remote_srf) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"new".as_slice())],
+        );
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(local.path().join("@test/a.txt")).unwrap(),
+            "new"
+        );
+
+        let cache = ModCache::from_disk(&cache_path).unwrap();
+        assert_eq!(cache.mods.len(), 1);
+        assert_eq!(
+            cache.mods[&remote_srf.checksum].files,
+            Some(remote_srf.files)
+        );
+    }
+
+    #[test]
+    fn algorithm_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "same").unwrap();
+        std::fs::write(remote.path().join("@test/b.txt"), "new").unwrap();
+        let options = srf::ScanOptions {
+            algorithm: Algorithm::Sha256,
+            ..srf::ScanOptions::default()
+        };
+        let (remote_srf, _) =
+            srf::scan_mod_with_options(&remote.path().join("@test"), &options).unwrap();
+
+        // cached with md5, none of its checksums can be compared to the repo's as they are
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "same").unwrap();
+        let local_srf = srf::scan_mod(&local.path().join("@test")).unwrap();
+        ModCache::from_mods([local_srf])
+            .to_disk(&cache_path)
+            .unwrap();
+
+        // a.txt isn't served, so it must not be downloaded again
+        let url = test_server::serve(HashMap::from([
+            (
+                "repo.json".to_string(),
+                serde_json::to_vec(&test_repository(&[&remote_srf])).unwrap(),
+            ),
+            (
+                "@test/mod.srf".to_string(),
+                serde_json::to_vec(&remote_srf).unwrap(),
+            ),
+            ("@test/b.txt".to_string(), b"new".to_vec()),
+        ]));
+
+        let recorder = Recorder::default();
+        sync(
+            &test_client(),
+            &http::Mirrors::new([url]),
+            local.path(),
+            &cache_path,
+            &test_options(),
+            &recorder,
+        )
+        .unwrap();
+
+        assert_eq!(recorder.done.into_inner().unwrap(), ["@test/b.txt"]);
+
+        let cache = ModCache::from_disk(&cache_path).unwrap();
+        assert_eq!(cache.mods.len(), 1);
+        assert!(cache.mods.contains_key(&remote_srf.checksum));
+    }
+
+    #[test]
+    fn mixed_case_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(remote.path().join("@test/addons")).unwrap();
+        std::fs::write(remote.path().join("@test/addons/a.txt"), "same").unwrap();
+        std::fs::write(remote.path().join("@test/b.txt"), "new").unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir_all(local.path().join("@test/Addons")).unwrap();
+        std::fs::write(local.path().join("@test/Addons/A.txt"), "same").unwrap();
+        std::fs::write(local.path().join("@test/b.txt"), "old").unwrap();
+        let local_srf = srf::scan_mod(&local.path().join("@test")).unwrap();
+        ModCache::from_mods([local_srf])
+            .to_disk(&cache_path)
+            .unwrap();
+
+        // addons/a.txt isn't served, the local copy only differs in case
+        let (url, remote_srf) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/b.txt", b"new".as_slice())],
+        );
+
+        let recorder = Recorder::default();
+        sync(
+            &test_client(),
+            &http::Mirrors::new([url]),
+            local.path(),
+            &cache_path,
+            &test_options(),
+            &recorder,
+        )
+        .unwrap();
+
+        assert_eq!(recorder.done.into_inner().unwrap(), ["@test/b.txt"]);
+        assert!(local.path().join("@test/Addons/A.txt").exists());
+
+        let cache = ModCache::from_disk(&cache_path).unwrap();
+        assert!(cache.mods.contains_key(&remote_srf.checksum));
+    }
+
+    #[test]
+    fn disabled_mod_test() {
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "old").unwrap();
+        let local_srf = srf::scan_mod(&local.path().join("@test")).unwrap();
+        ModCache::from_mods([local_srf])
+            .to_disk(&cache_path)
+            .unwrap();
+
+        // a different checksum, so this would be a download if the mod were enabled
+        let mut remote_repo = test_repository(&[]);
+        remote_repo.required_mods = vec![repository::Mod {
+            mod_name: "@test".to_string(),
+            checksum: Checksum::default(),
+            enabled: false,
+        }];
+
+        let url = test_server::serve(HashMap::from([(
+            "repo.json".to_string(),
+            serde_json::to_vec(&remote_repo).unwrap(),
+        )]));
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert!(!local.path().join("@test").exists());
+        assert!(ModCache::from_disk(&cache_path).unwrap().mods.is_empty());
+    }
+
+    #[test]
+    fn excluded_mod_test() {
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "old").unwrap();
+        let local_srf = srf::scan_mod(&local.path().join("@test")).unwrap();
+        ModCache::from_mods([local_srf])
+            .to_disk(&cache_path)
+            .unwrap();
+
+        // nothing but repo.json is served, so checking the mod would fail
+        let mut remote_repo = test_repository(&[]);
+        remote_repo.required_mods = vec![repository::Mod {
+            mod_name: "@Test".to_string(),
+            checksum: Checksum::default(),
+            enabled: true,
+        }];
+
+        let url = test_server::serve(HashMap::from([(
+            "repo.json".to_string(),
+            serde_json::to_vec(&remote_repo).unwrap(),
+        )]));
+
+        let mut options = test_options();
+        options.exclude_mods = vec!["@test".to_string()];
+        options.keep_excluded = true;
+
+        run_sync(&url, local.path(), &options).unwrap();
+        assert!(local.path().join("@test/a.txt").exists());
+
+        options.keep_excluded = false;
+        run_sync(&url, local.path(), &options).unwrap();
+        assert!(!local.path().join("@test").exists());
+        assert!(ModCache::from_disk(&cache_path).unwrap().mods.is_empty());
+    }
+
+    #[test]
+    fn dropped_mod_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "a").unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir_all(local.path().join("@test/addons/old")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "a").unwrap();
+        std::fs::write(local.path().join("@test/addons/old/b.txt"), "b").unwrap();
+        std::fs::create_dir(local.path().join("@old")).unwrap();
+        std::fs::write(local.path().join("@old/c.txt"), "c").unwrap();
+
+        let local_srfs =
+            ["@test", "@old"].map(|name| srf::scan_mod(&local.path().join(name)).unwrap());
+        let mut mod_cache = ModCache::from_mods(local_srfs);
+        mod_cache
+            .repo_checksums
+            .insert("@old".to_string(), Checksum::default());
+        mod_cache.to_disk(&cache_path).unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"a".as_slice())],
+        );
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert!(local.path().join("@test/a.txt").exists());
+        assert!(!local.path().join("@test/addons").exists());
+        assert!(!local.path().join("@old").exists());
+
+        let mod_cache = ModCache::from_disk(&cache_path).unwrap();
+        assert!(mod_cache.mods.values().all(|r#mod| r#mod.name != "@old"));
+        assert!(!mod_cache.repo_checksums.contains_key("@old"));
+    }
+
+    #[test]
+    fn unrelated_mod_survives_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "a").unwrap();
+
+        // no cache, so it's generated from everything in the mod path, @local included
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        std::fs::create_dir(local.path().join("@local")).unwrap();
+        std::fs::write(local.path().join("@local/c.txt"), "c").unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"a".as_slice())],
+        );
+
+        for _ in 0..2 {
+            run_sync(&url, local.path(), &test_options()).unwrap();
+        }
+
+        assert!(local.path().join("@test/a.txt").exists());
+        assert!(local.path().join("@local/c.txt").exists());
+
+        let mod_cache = ModCache::from_disk(&cache_path).unwrap();
+        assert!(mod_cache.repo_checksums.contains_key("@test"));
+        assert!(!mod_cache.repo_checksums.contains_key("@local"));
+    }
+
+    #[test]
+    fn revalidate_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "a").unwrap();
+        std::fs::write(remote.path().join("@test/b.txt"), "b").unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "a").unwrap();
+        std::fs::write(local.path().join("@test/b.txt"), "b").unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/b.txt", b"b".as_slice())],
+        );
+
+        // the first sync builds the cache, which then goes stale
+        run_sync(&url, local.path(), &test_options()).unwrap();
+        std::fs::remove_file(local.path().join("@test/b.txt")).unwrap();
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+        assert!(!local.path().join("@test/b.txt").exists());
+
+        let options = SyncOptions {
+            revalidate: true,
+            ..test_options()
+        };
+        run_sync(&url, local.path(), &options).unwrap();
+        assert_eq!(
+            std::fs::read(local.path().join("@test/b.txt")).unwrap(),
+            b"b"
+        );
+    }
+
+    #[test]
+    fn journal_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "new").unwrap();
+
+        // a sync got as far as downloading a.txt before dying. without the journal, a mod with
+        // no mod.srf that isn't cached would be thrown away and downloaded again
+        let local = tempfile::tempdir().unwrap();
+        let cache_path = local.path().join("nimble-cache.json");
+        ModCache::new_empty().to_disk(&cache_path).unwrap();
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "new").unwrap();
+
+        let journal_path = sync_journal::path(&cache_path);
+        sync_journal::Journal {
+            mods: ["@test".to_string()].into(),
+        }
+        .to_disk(&journal_path)
+        .unwrap();
+
+        // a.txt isn't served, so the sync only succeeds if it picks up what is on disk
+        let (url, remote_srf) = serve_mod(&remote.path().join("@test"), &[]);
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert!(!journal_path.exists());
+        assert_eq!(
+            std::fs::read(local.path().join("@test/a.txt")).unwrap(),
+            b"new"
+        );
+        let mod_cache = ModCache::from_disk(&cache_path).unwrap();
+        assert!(mod_cache.mods.contains_key(&remote_srf.checksum));
+    }
+
+    #[test]
+    fn nimbleignore_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "new").unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        std::fs::write(local.path().join(".nimbleignore"), "*.xml\n").unwrap();
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "old").unwrap();
+        std::fs::write(local.path().join("@test/squad.xml"), "mine").unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"new".as_slice())],
+        );
+
+        run_sync(&url, local.path(), &test_options()).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(local.path().join("@test/a.txt")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            std::fs::read_to_string(local.path().join("@test/squad.xml")).unwrap(),
+            "mine"
+        );
+
+        // ignoring only keeps sync's hands off, the file is still part of the mod's checksum
+        let local_srf = srf::scan_mod(&local.path().join("@test")).unwrap();
+        assert!(local_srf
+            .files
+            .iter()
+            .any(|file| file.path.as_str() == "squad.xml"));
+    }
+
+    #[test]
+    fn apply_removals_test() {
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(local.path().join("@test/dir")).unwrap();
+        std::fs::write(local.path().join("@test/dir/a.txt"), "a").unwrap();
+        std::fs::write(local.path().join("@test/b.txt"), "b").unwrap();
+        std::fs::create_dir_all(local.path().join("@old/addons")).unwrap();
+        std::fs::write(local.path().join("@old/addons/c.txt"), "c").unwrap();
+
+        // a directory can't be removed as a file, and the missing file is simply skipped
+        let diff = SyncDiff {
+            leftovers: ["@test/dir", "@test/gone.txt", "@test/b.txt"]
+                .map(|path| local.path().join(path))
+                .to_vec(),
+            removed_mods: vec![
+                ("dropped", local.path().join("@old")),
+                ("dropped", local.path().to_owned()),
+            ],
+            ..SyncDiff::default()
+        };
+
+        assert_eq!(
+            apply_removals(local.path(), &diff),
+            vec![local.path().join("@test/dir")]
+        );
+        assert!(!local.path().join("@test/b.txt").exists());
+        assert!(local.path().join("@test/dir/a.txt").exists());
+        assert!(!local.path().join("@old").exists());
+    }
+
+    #[test]
+    fn is_inside_test() {
+        let base = Path::new("/mods");
+
+        assert!(is_inside(base, Path::new("/mods/@ace/addons")));
+        assert!(!is_inside(base, Path::new("/mods")));
+        assert!(!is_inside(base, Path::new("/other/@ace")));
+        assert!(!is_inside(base, Path::new("/mods/@ace/../../etc")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn dedup_test() {
+        use std::os::unix::fs::MetadataExt;
+
+        let remote = tempfile::tempdir().unwrap();
+        let remote_srfs: Vec<_> = ["@a", "@b"]
+            .iter()
+            .map(|name| {
+                std::fs::create_dir(remote.path().join(name)).unwrap();
+                std::fs::write(remote.path().join(name).join("shared.txt"), "shared").unwrap();
+                srf::scan_mod(&remote.path().join(name)).unwrap()
+            })
+            .collect();
+
+        let mut files = HashMap::from([(
+            "repo.json".to_string(),
+            serde_json::to_vec(&test_repository(&[&remote_srfs[0], &remote_srfs[1]])).unwrap(),
+        )]);
+        for remote_srf in &remote_srfs {
+            files.insert(
+                format!("{}/mod.srf", remote_srf.name),
+                serde_json::to_vec(remote_srf).unwrap(),
+            );
+            files.insert(
+                format!("{}/shared.txt", remote_srf.name),
+                b"shared".to_vec(),
+            );
+        }
+        let url = test_server::serve(files);
+
+        let local = tempfile::tempdir().unwrap();
+        let options = SyncOptions {
+            dedup: true,
+            ..test_options()
+        };
+
+        run_sync(&url, local.path(), &options).unwrap();
+
+        let ino = |name: &str| {
+            std::fs::metadata(local.path().join(name).join("shared.txt"))
+                .unwrap()
+                .ino()
+        };
+        assert_eq!(ino("@a"), ino("@b"));
+    }
+
+    #[test]
+    fn dry_run_test() {
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir(remote.path().join("@test")).unwrap();
+        std::fs::write(remote.path().join("@test/a.txt"), "new").unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir(local.path().join("@test")).unwrap();
+        std::fs::write(local.path().join("@test/a.txt"), "old").unwrap();
+        std::fs::write(local.path().join("@test/leftover.txt"), "leftover").unwrap();
+
+        let (url, _) = serve_mod(
+            &remote.path().join("@test"),
+            &[("@test/a.txt", b"new".as_slice())],
+        );
+
+        let options = SyncOptions {
+            dry_run: true,
+            ..test_options()
+        };
+
+        run_sync(&url, local.path(), &options).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(local.path().join("@test/a.txt")).unwrap(),
+            "old"
+        );
+        assert!(local.path().join("@test/leftover.txt").exists());
+    }
+
+    #[test]
+    fn summarize_downloads_test() {
+        let command = |file: &str, begin, end| DownloadCommand {
+            file: file.to_string(),
+            begin,
+            end,
+            length: 100,
+            parts: vec![],
+            checksum: Checksum::default(),
+        };
+
+        let summary = summarize_downloads(
+            &[
+                command("@ace/addons/a.pbo", 0, 100),
+                command("@cba/addons/b.pbo", 0, 10),
+                command("@cba/addons/b.pbo", 50, 60),
+                command("@cba/mod.cpp", 0, 5),
+            ],
+            &[("@rhs".to_string(), 40)],
+        );
+
+        assert_eq!(
+            summary,
+            DownloadSummary {
+                files: 4,
+                bytes: 165,
+                mods: BTreeMap::from([
+                    ("@ace".to_string(), (1, 100)),
+                    ("@cba".to_string(), (2, 25)),
+                    ("@rhs".to_string(), (1, 40)),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn resume_validator_test() {
+        let response: ureq::Response =
+            "HTTP/1.1 200 OK\r\nETag: \"abc\"\r\nLast-Modified: Tue, 15 Nov 1994 12:45:26 GMT\r\n\r\n"
+                .parse()
+                .unwrap();
+        assert_eq!(resume_validator(&response).as_deref(), Some("\"abc\""));
+
+        // weak etags can't be used with If-Range
+        let response: ureq::Response =
+            "HTTP/1.1 200 OK\r\nETag: W/\"abc\"\r\nLast-Modified: Tue, 15 Nov 1994 12:45:26 GMT\r\n\r\n"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            resume_validator(&response).as_deref(),
+            Some("Tue, 15 Nov 1994 12:45:26 GMT")
+        );
+
+        let response: ureq::Response = "HTTP/1.1 200 OK\r\n\r\n".parse().unwrap();
+        assert_eq!(resume_validator(&response), None);
+    }
+
+    fn part(start: u64, data: &[u8]) -> srf::Part {
+        srf::Part {
+            path: format!("part_{start}"),
+            length: data.len() as u64,
+            start,
+            checksum: Algorithm::Md5.digest(data),
+        }
+    }
+
+    #[test]
+    fn part_verifier_test() {
+        let parts = vec![part(0, b"hello"), part(5, b""), part(5, b" world")];
+
+        let mut verifier = PartVerifier::new(&parts).unwrap();
+        verifier.update(b"hel");
+        verifier.update(b"lo wo");
+        assert_eq!(verifier.corrupt_parts(), vec![&parts[2]]);
+        verifier.update(b"rld");
+        assert!(verifier.corrupt_parts().is_empty());
+
+        verifier.reset();
+        verifier.update(b"jello world");
+        assert_eq!(verifier.corrupt_parts(), vec![&parts[0]]);
+
+        // gaps between parts can't be verified
+        let parts = vec![part(0, b"hello"), part(6, b"world")];
+        assert!(PartVerifier::new(&parts).is_none());
+    }
+
+    #[test]
+    fn copy_buffered_test() {
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut output = Vec::new();
+
+        let written =
+            copy_buffered(&mut data.as_slice(), &mut output, &mut [0; 7], &(), "").unwrap();
+
+        assert_eq!(written, 1000);
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn detect_encoding_test() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"\0pbo contents").unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let gzip = Some(http::Encoding::Gzip);
+
+        assert_eq!(
+            detect_encoding(&mut Cursor::new(&gzipped), None, true).unwrap(),
+            gzip
+        );
+        assert_eq!(
+            detect_encoding(&mut Cursor::new(&gzipped), None, false).unwrap(),
+            None
+        );
+        assert_eq!(
+            detect_encoding(&mut Cursor::new(b"\0pbo contents"), None, true).unwrap(),
+            None
+        );
+        assert_eq!(
+            detect_encoding(&mut Cursor::new(b"anything"), gzip, false).unwrap(),
+            gzip
+        );
+    }
+
+    #[test]
+    fn match_files_test() {
+        let file = |path: &str| srf::File {
+            path: RelativePathBuf::from(path),
+            length: 0,
+            checksum: Checksum::default(),
+            r#type: srf::FileType::File,
+            extensions: None,
+            parts: vec![],
+        };
+
+        let remote = vec![
+            file("Addons/X.pbo"),
+            file("addons/x.pbo"),
+            file("Data/Y.paa"),
+        ];
+        let local = vec![file("addons/x.pbo"), file("data/y.paa"), file("other.txt")];
+
+        let (pairs, leftovers) = match_files(&remote, &local);
+        let pairs: Vec<_> = pairs
+            .into_iter()
+            .map(|(remote, local)| (remote.path.as_str(), local.map(|local| local.path.as_str())))
+            .collect();
+
+        assert_eq!(
+            pairs,
+            [
+                ("addons/x.pbo", Some("addons/x.pbo")),
+                ("Addons/X.pbo", None),
+                ("Data/Y.paa", Some("data/y.paa")),
+            ]
+        );
+        assert_eq!(leftovers, [&local[2]]);
+    }
+
+    #[test]
+    fn find_case_collision_test() {
+        let file = |path: &str| srf::File {
+            path: RelativePathBuf::from(path),
+            length: 0,
+            checksum: Checksum::default(),
+            r#type: srf::FileType::File,
+            extensions: None,
+            parts: vec![],
+        };
+
+        let files = vec![file("addons/config.bin"), file("addons/other.pbo")];
+        assert_eq!(find_case_collision(&files), None);
+
+        let files = vec![
+            file("addons/config.bin"),
+            file("addons/other.pbo"),
+            file("Addons/Config.bin"),
+        ];
+        assert_eq!(
+            find_case_collision(&files),
+            Some((&files[0].path, &files[2].path))
+        );
+    }
 }
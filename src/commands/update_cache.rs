@@ -0,0 +1,41 @@
+use crate::commands::find_mod_dir;
+use crate::commands::gen_srf;
+use crate::commands::gen_srf::gen_srf_for_mod;
+use crate::mod_cache;
+use crate::mod_cache::ModCache;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::path::Path;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("no mod named {} found in {}", name, base_path.display()))]
+    UnknownMod {
+        name: String,
+        base_path: std::path::PathBuf,
+    },
+    #[snafu(display("failed to open ModCache: {}", source))]
+    ModCacheOpen { source: mod_cache::Error },
+    #[snafu(display("failed to scan mod: {}", source))]
+    Scan { source: gen_srf::Error },
+    #[snafu(display("failed to write ModCache: {}", source))]
+    ModCacheWrite { source: mod_cache::Error },
+}
+
+pub fn update_cache(base_path: &Path, cache_path: &Path, mod_name: &str) -> Result<(), Error> {
+    let mod_path = find_mod_dir(base_path, mod_name).context(UnknownModSnafu {
+        name: mod_name,
+        base_path,
+    })?;
+
+    let mut mod_cache = ModCache::from_disk_or_empty(cache_path).context(ModCacheOpenSnafu)?;
+
+    let srf = gen_srf_for_mod(&mod_path, false).context(ScanSnafu)?;
+
+    // the checksum most likely changed, so the old entry can't be found by key
+    mod_cache.remove_by_name(&srf.name);
+    mod_cache.insert(srf);
+
+    mod_cache.to_disk(cache_path).context(ModCacheWriteSnafu)?;
+
+    Ok(())
+}
@@ -0,0 +1,294 @@
+use crate::commands::find_mod_dir;
+use crate::mod_cache::ModCache;
+use crate::output;
+use crate::{mod_cache, srf};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
+use rayon::prelude::*;
+use relative_path::RelativePathBuf;
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to open ModCache: {}", source))]
+    ModCacheOpen { source: mod_cache::Error },
+    #[snafu(display("failed to build thread pool: {}", source))]
+    ThreadPool { source: rayon::ThreadPoolBuildError },
+    #[snafu(display("failed to open mod.srf: {}", source))]
+    SrfOpen { source: std::io::Error },
+    #[snafu(display("failed to read mod.srf: {}", source))]
+    SrfRead { source: srf::Error },
+    #[snafu(display("failed to scan mod: {}", source))]
+    Scan { source: srf::Error },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileStatus {
+    Matching,
+    Different,
+    Missing,
+    // on disk, but not in the mod.srf
+    Unexpected,
+}
+
+impl Display for FileStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::Matching => "ok",
+            Self::Different => "different",
+            Self::Missing => "missing",
+            Self::Unexpected => "unexpected",
+        };
+
+        f.pad(description)
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: RelativePathBuf,
+    pub status: FileStatus,
+    pub expected: Option<srf::File>,
+    pub actual: Option<srf::File>,
+}
+
+impl FileReport {
+    pub fn is_problem(&self) -> bool {
+        matches!(self.status, FileStatus::Different | FileStatus::Missing)
+    }
+}
+
+#[derive(Debug)]
+pub struct ModReport {
+    pub name: String,
+    pub files: Result<Vec<FileReport>, Error>,
+}
+
+impl ModReport {
+    pub fn is_ok(&self) -> bool {
+        self.files
+            .as_ref()
+            .is_ok_and(|files| !files.iter().any(FileReport::is_problem))
+    }
+}
+
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub mods: Vec<ModReport>,
+}
+
+fn read_local_srf(mod_path: &Path) -> Result<srf::Mod, Error> {
+    let file = File::open(mod_path.join("mod.srf")).context(SrfOpenSnafu)?;
+    let mut reader = BufReader::new(file);
+
+    srf::read_srf(&mut reader).context(SrfReadSnafu)
+}
+
+// compares what the mod.srf in mod_path promises against a fresh scan of the files on disk
+pub fn verify_mod(mod_path: &Path) -> Result<Vec<FileReport>, Error> {
+    let expected = read_local_srf(mod_path)?;
+    let options = srf::ScanOptions {
+        algorithm: expected.algorithm,
+        ..srf::ScanOptions::default()
+    };
+    let (actual, _) = srf::scan_mod_with_options(mod_path, &options).context(ScanSnafu)?;
+
+    let mut actual_files: HashMap<_, _> = actual
+        .files
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect();
+
+    let mut reports = Vec::new();
+
+    for expected_file in expected.files {
+        let actual_file = actual_files.remove(&expected_file.path);
+
+        let status = match &actual_file {
+            Some(actual_file) if actual_file.checksum == expected_file.checksum => {
+                FileStatus::Matching
+            }
+            Some(_) => FileStatus::Different,
+            None => FileStatus::Missing,
+        };
+
+        reports.push(FileReport {
+            path: expected_file.path.clone(),
+            status,
+            expected: Some(expected_file),
+            actual: actual_file,
+        });
+    }
+
+    for (path, actual_file) in actual_files {
+        reports.push(FileReport {
+            path,
+            status: FileStatus::Unexpected,
+            expected: None,
+            actual: Some(actual_file),
+        });
+    }
+
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(reports)
+}
+
+fn verify_all(base_path: &Path, names: &[String]) -> VerifyReport {
+    let pb = ProgressBar::new(names.len() as u64);
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} mods",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+
+    if output::is_quiet() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
+
+    let mods = names
+        .par_iter()
+        .map(|name| {
+            let report = ModReport {
+                name: name.clone(),
+                // cached names are lowercase, the directory may not be
+                files: verify_mod(
+                    &find_mod_dir(base_path, name).unwrap_or_else(|| base_path.join(name)),
+                ),
+            };
+
+            pb.inc(1);
+
+            report
+        })
+        .collect();
+
+    pb.finish_and_clear();
+
+    VerifyReport { mods }
+}
+
+pub fn verify(
+    base_path: &Path,
+    cache_path: &Path,
+    jobs: Option<usize>,
+) -> Result<VerifyReport, Error> {
+    let mod_cache = ModCache::from_disk(cache_path).context(ModCacheOpenSnafu)?;
+
+    let mut names: Vec<_> = mod_cache
+        .mods
+        .into_values()
+        .map(|r#mod| r#mod.name)
+        .collect();
+    names.sort();
+
+    // scan_mod is parallel on its own, so running it inside the pool bounds both levels at once
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()
+        .context(ThreadPoolSnafu)?;
+
+    let report = pool.install(|| verify_all(base_path, &names));
+
+    for r#mod in &report.mods {
+        match &r#mod.files {
+            Ok(files) => {
+                for file in files.iter().filter(|file| file.is_problem()) {
+                    eprintln!("{}: {} {}", r#mod.name, file.status, file.path);
+                }
+            }
+            Err(e) => eprintln!("{}: {}", r#mod.name, e),
+        }
+    }
+
+    let failed = report.mods.iter().filter(|r#mod| !r#mod.is_ok()).count();
+    info!(
+        "verified {} mods, {} with problems",
+        report.mods.len(),
+        failed
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::gen_srf::gen_srf_for_mod;
+    use std::path::PathBuf;
+    use walkdir::WalkDir;
+
+    #[test]
+    fn verify_mod_test() {
+        let source: PathBuf = [env!("CARGO_MANIFEST_DIR"), "test_files", "@ace"]
+            .iter()
+            .collect();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mod_path = temp_dir.path().join("@ace");
+
+        for entry in WalkDir::new(&source).into_iter().map(Result::unwrap) {
+            let target = mod_path.join(entry.path().strip_prefix(&source).unwrap());
+
+            if entry.file_type().is_dir() {
+                std::fs::create_dir_all(target).unwrap();
+            } else {
+                std::fs::copy(entry.path(), target).unwrap();
+            }
+        }
+
+        gen_srf_for_mod(&mod_path, false).unwrap();
+
+        let reports = verify_mod(&mod_path).unwrap();
+        assert!(reports.iter().all(|r| r.status == FileStatus::Matching));
+
+        std::fs::write(
+            mod_path.join("addons/ace_advancedthis_will_break_tests_if_we_order_incorrectly.txt"),
+            "corrupted",
+        )
+        .unwrap();
+        std::fs::remove_file(mod_path.join("addons/ace_advanced_ballistics.pbo")).unwrap();
+        std::fs::write(mod_path.join("userconfig.hpp"), "").unwrap();
+
+        let statuses: Vec<_> = verify_mod(&mod_path)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.path.to_string(), r.status))
+            .collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                (
+                    "addons/ace_advanced_ballistics.pbo".to_string(),
+                    FileStatus::Missing
+                ),
+                (
+                    "addons/ace_advancedthis_will_break_tests_if_we_order_incorrectly.txt"
+                        .to_string(),
+                    FileStatus::Different
+                ),
+                ("userconfig.hpp".to_string(), FileStatus::Unexpected),
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_all_case_test() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mod_path = temp_dir.path().join("@CBA");
+        std::fs::create_dir(&mod_path).unwrap();
+        std::fs::write(mod_path.join("readme.txt"), "hello").unwrap();
+
+        let srf = gen_srf_for_mod(&mod_path, false).unwrap();
+        let report = verify_all(temp_dir.path(), &[srf.name]);
+
+        assert!(report.mods.iter().all(ModReport::is_ok));
+    }
+}
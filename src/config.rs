@@ -0,0 +1,135 @@
+use crate::commands::launch::AppId;
+use serde::{Deserialize, Deserializer};
+use snafu::{ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_FILE_NAME: &str = "nimble.toml";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to read {}: {}", path.display(), source))]
+    FileRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to parse {}: {}", path.display(), source))]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+}
+
+// defaults for flags that would otherwise be retyped on every run. flags given on the command line
+// win over these
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // a single url, or the mirrors of one repo
+    #[serde(default, deserialize_with = "deserialize_repo_urls")]
+    pub repo_url: Option<Vec<String>>,
+    pub path: Option<PathBuf>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub jobs: Option<usize>,
+    // directories to treat as mods even though their name doesn't start with @
+    #[serde(default)]
+    pub include_dirs: Vec<String>,
+    // treat every directory in the mod storage path as a mod
+    #[serde(default)]
+    pub all_dirs: bool,
+    // mods sync never downloads and launch leaves out
+    #[serde(default)]
+    pub exclude_mods: Vec<String>,
+    // steam app launch starts, a number or one of the presets --app-id knows
+    pub app_id: Option<AppId>,
+    // where sync copies the mods' keys to
+    pub keys_dir: Option<PathBuf>,
+}
+
+fn deserialize_repo_urls<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let urls = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => vec![url],
+        OneOrMany::Many(urls) => urls,
+    };
+
+    // an empty list is as good as none
+    Ok(Some(urls).filter(|urls| !urls.is_empty()))
+}
+
+pub fn load(path: &Path) -> Result<Config, Error> {
+    let contents = std::fs::read_to_string(path).context(FileReadSnafu { path })?;
+    let mut config: Config = toml::from_str(&contents).context(ParseSnafu { path })?;
+
+    // a relative mod path means relative to the config file, not to wherever nimble runs from
+    if let Some(dir) = path.parent() {
+        config.path = config.path.map(|mod_path| dir.join(mod_path));
+        config.keys_dir = config.keys_dir.map(|keys_dir| dir.join(keys_dir));
+    }
+
+    Ok(config)
+}
+
+// like load, but a missing file is just an empty config
+pub fn load_or_default(path: &Path) -> Result<Config, Error> {
+    match load(path) {
+        Err(Error::FileRead { source, .. }) if source.kind() == std::io::ErrorKind::NotFound => {
+            Ok(Config::default())
+        }
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_FILE_NAME);
+
+        assert_eq!(load_or_default(&path).unwrap(), Config::default());
+        assert!(load(&path).is_err());
+
+        std::fs::write(
+            &path,
+            "repo_url = \"https://example.com/swifty/\"\npath = \"mods\"\njobs = 4\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            load(&path).unwrap(),
+            Config {
+                repo_url: Some(vec!["https://example.com/swifty/".to_string()]),
+                path: Some(dir.path().join("mods")),
+                jobs: Some(4),
+                ..Config::default()
+            }
+        );
+
+        std::fs::write(
+            &path,
+            "repo_url = [\"https://a.example.com/\", \"https://b.example.com/\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load(&path).unwrap().repo_url,
+            Some(vec![
+                "https://a.example.com/".to_string(),
+                "https://b.example.com/".to_string()
+            ])
+        );
+
+        std::fs::write(&path, "repo-url = \"typo\"\n").unwrap();
+        assert!(matches!(load(&path), Err(Error::Parse { .. })));
+    }
+}
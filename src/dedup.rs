@@ -0,0 +1,209 @@
+use crate::checksum::Checksum;
+use crate::commands::find_mod_dir;
+use crate::mod_cache::ModCache;
+use log::debug;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+const COMPARE_BUFFER_SIZE: usize = 64 * 1024;
+
+// files already on disk by checksum and length, for new downloads to be linked to
+#[derive(Debug, Default)]
+pub struct Index {
+    files: HashMap<(Checksum, u64), PathBuf>,
+}
+
+impl Index {
+    pub fn from_cache(base_path: &Path, mod_cache: &ModCache) -> Self {
+        let mut index = Self::default();
+
+        for r#mod in mod_cache.mods.values() {
+            // cached names are lowercase, the directory may not be
+            let Some(mod_path) = find_mod_dir(base_path, &r#mod.name) else {
+                continue;
+            };
+
+            for file in r#mod.files.iter().flatten() {
+                index.insert(&file.checksum, file.length, file.path.to_path(&mod_path));
+            }
+        }
+
+        index
+    }
+
+    pub fn insert(&mut self, checksum: &Checksum, length: u64, path: PathBuf) {
+        self.files.entry((checksum.clone(), length)).or_insert(path);
+    }
+
+    pub fn get(&self, checksum: &Checksum, length: u64) -> Option<&Path> {
+        self.files
+            .get(&(checksum.clone(), length))
+            .map(PathBuf::as_path)
+    }
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+
+    Ok(len)
+}
+
+fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut buf_a = vec![0; COMPARE_BUFFER_SIZE];
+    let mut buf_b = vec![0; COMPARE_BUFFER_SIZE];
+
+    loop {
+        let len_a = read_full(&mut a, &mut buf_a)?;
+        let len_b = read_full(&mut b, &mut buf_b)?;
+
+        if buf_a[..len_a] != buf_b[..len_b] {
+            return Ok(false);
+        }
+
+        if len_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+// replaces path with a hardlink to original, if both really have the same contents. returns
+// whether it did. filesystems without hardlinks, or an original on another device, just leave
+// path as the copy it already is
+pub fn link_duplicate(original: &Path, path: &Path) -> std::io::Result<bool> {
+    // the cache may be older than what's on disk, so never trust it alone
+    match same_contents(original, path) {
+        Ok(true) => {}
+        Ok(false) => return Ok(false),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    }
+
+    let mut link_name = path.file_name().unwrap_or_default().to_owned();
+    link_name.push(".nimble-link");
+    let link_path = path.with_file_name(link_name);
+    let _ = std::fs::remove_file(&link_path);
+
+    if let Err(e) = std::fs::hard_link(original, &link_path) {
+        debug!(
+            "can't link {} to {}, keeping the copy: {e}",
+            path.display(),
+            original.display()
+        );
+        return Ok(false);
+    }
+
+    if let Err(e) = std::fs::rename(&link_path, path) {
+        let _ = std::fs::remove_file(&link_path);
+        return Err(e);
+    }
+
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn may_be_linked(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+// windows can't tell without unstable apis, so assume the worst
+#[cfg(not(unix))]
+fn may_be_linked(_metadata: &Metadata) -> bool {
+    true
+}
+
+// gives path its own copy of the data, so writing into it doesn't change the other names of a
+// linked file
+pub fn break_hardlink(path: &Path) -> std::io::Result<()> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !may_be_linked(&metadata) {
+        return Ok(());
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut copy = NamedTempFile::new_in(dir)?;
+    std::io::copy(&mut File::open(path)?, &mut copy)?;
+    std::fs::set_permissions(copy.path(), metadata.permissions())?;
+    copy.persist(path).map_err(|e| e.error)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_duplicate_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original.pbo");
+        let copy = dir.path().join("copy.pbo");
+        let different = dir.path().join("different.pbo");
+
+        std::fs::write(&original, "contents").unwrap();
+        std::fs::write(&copy, "contents").unwrap();
+        std::fs::write(&different, "contentz").unwrap();
+
+        assert!(!link_duplicate(&original, &different).unwrap());
+        assert!(!link_duplicate(&dir.path().join("missing.pbo"), &copy).unwrap());
+        assert!(link_duplicate(&original, &copy).unwrap());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let ino = |path: &Path| std::fs::metadata(path).unwrap().ino();
+            assert_eq!(ino(&original), ino(&copy));
+        }
+
+        // writing into one name afterwards must leave the other alone
+        break_hardlink(&copy).unwrap();
+        std::fs::write(&copy, "patched").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "contents");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn index_case_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mod_path = dir.path().join("@CBA");
+        std::fs::create_dir(&mod_path).unwrap();
+        std::fs::write(mod_path.join("a.txt"), "a").unwrap();
+
+        let srf = crate::srf::scan_mod(&mod_path).unwrap();
+        let file = srf.files[0].clone();
+        let index = Index::from_cache(dir.path(), &ModCache::from_mods([srf]));
+
+        assert_eq!(
+            index.get(&file.checksum, file.length),
+            Some(mod_path.join("a.txt").as_path())
+        );
+    }
+}
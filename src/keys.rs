@@ -0,0 +1,220 @@
+use crate::commands::find_mod_dir;
+use crate::srf;
+use log::{info, warn};
+use snafu::{ResultExt, Snafu};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to create {}: {}", path.display(), source))]
+    DirCreation {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to copy {} to {}: {}", from.display(), to.display(), source))]
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to remove {}: {}", path.display(), source))]
+    Remove {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to read the installed keys record: {}", source))]
+    RecordRead { source: std::io::Error },
+    #[snafu(display("failed to write the installed keys record: {}", source))]
+    RecordWrite { source: std::io::Error },
+    #[snafu(display("failed to persist the installed keys record: {}", source))]
+    RecordPersist { source: tempfile::PersistError },
+    #[snafu(display("installed keys record is corrupt: {}", source))]
+    Deserialization { source: serde_json::Error },
+}
+
+// the keys nimble put into each keys directory, so it only ever removes those and leaves the ones
+// that came with the game or were added by hand alone. lives next to the cache
+pub fn record_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_file_name("nimble-keys.json")
+}
+
+type Record = BTreeMap<PathBuf, BTreeSet<String>>;
+
+fn read_record(path: &Path) -> Result<Record, Error> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Record::new()),
+        Err(e) => return Err(Error::RecordRead { source: e }),
+    };
+
+    serde_json::from_slice(&contents).context(DeserializationSnafu)
+}
+
+fn write_record(path: &Path, record: &Record) -> Result<(), Error> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut file = NamedTempFile::new_in(dir).context(RecordWriteSnafu)?;
+
+    let contents = serde_json::to_vec(record).expect("a map of sets of strings always serializes");
+    file.write_all(&contents).context(RecordWriteSnafu)?;
+    file.persist(path).context(RecordPersistSnafu)?;
+
+    Ok(())
+}
+
+// the .bikey files shipped by the mods, by file name. mods usually share keys with the same name
+// when they share a signing authority, so the first one found wins
+fn find_keys<'a>(
+    base_path: &Path,
+    mod_names: impl IntoIterator<Item = &'a str>,
+) -> BTreeMap<String, PathBuf> {
+    let mut mod_names: Vec<_> = mod_names.into_iter().collect();
+    mod_names.sort();
+
+    let mut keys = BTreeMap::new();
+
+    for name in mod_names {
+        let is_key = |path: &PathBuf| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("bikey"))
+        };
+
+        // cached names are lowercase, the directory may not be
+        let Some(mod_path) = find_mod_dir(base_path, name) else {
+            continue;
+        };
+
+        for path in srf::mod_files(&mod_path).into_iter().filter(is_key) {
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+
+            keys.entry(file_name.to_string_lossy().into_owned())
+                .or_insert(path);
+        }
+    }
+
+    keys
+}
+
+// copies the keys of the given mods into keys_dir, and removes the keys earlier calls copied there
+// for mods that are gone now
+pub fn install_keys<'a>(
+    base_path: &Path,
+    mod_names: impl IntoIterator<Item = &'a str>,
+    keys_dir: &Path,
+    record_path: &Path,
+) -> Result<(), Error> {
+    let mut record = read_record(record_path)?;
+    // another keys directory may hold keys of the same name that nimble never touched
+    let dir_key = std::path::absolute(keys_dir).unwrap_or_else(|_| keys_dir.to_owned());
+    let previous = record.remove(&dir_key).unwrap_or_default();
+    let keys = find_keys(base_path, mod_names);
+    let mut installed = BTreeSet::new();
+
+    std::fs::create_dir_all(keys_dir).context(DirCreationSnafu { path: keys_dir })?;
+
+    for (name, from) in &keys {
+        let to = keys_dir.join(name);
+
+        // keys are a few hundred bytes, comparing them is cheaper than keeping checksums around
+        match (std::fs::read(from), std::fs::read(&to)) {
+            (Ok(new), Ok(existing)) if new == existing => {
+                // the same key may already have been there before nimble, that one isn't ours
+                if previous.contains(name) {
+                    installed.insert(name.clone());
+                }
+                continue;
+            }
+            (Ok(_), Ok(_)) if !previous.contains(name) => {
+                warn!("not replacing {}, nimble didn't put it there", to.display());
+                continue;
+            }
+            _ => {}
+        }
+
+        info!("installing key {}", to.display());
+        std::fs::copy(from, &to).context(CopySnafu { from, to: &to })?;
+        installed.insert(name.clone());
+    }
+
+    for name in previous.difference(&installed) {
+        let path = keys_dir.join(name);
+        info!("removing key {}", path.display());
+
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(Error::Remove { path, source: e });
+            }
+            _ => {}
+        }
+    }
+
+    if !installed.is_empty() {
+        record.insert(dir_key, installed);
+    }
+
+    write_record(record_path, &record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_keys_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("mods");
+        let keys_dir = dir.path().join("Keys");
+        let record_path = base_path.join("nimble-keys.json");
+
+        std::fs::create_dir_all(base_path.join("@ace/keys")).unwrap();
+        std::fs::write(base_path.join("@ace/keys/ace.bikey"), "ace").unwrap();
+        std::fs::create_dir_all(base_path.join("@CBA/Key")).unwrap();
+        std::fs::write(base_path.join("@CBA/Key/cba.BIKEY"), "cba").unwrap();
+        std::fs::create_dir_all(&keys_dir).unwrap();
+        std::fs::write(keys_dir.join("a3.bikey"), "a3").unwrap();
+
+        install_keys(&base_path, ["@ace", "@cba"], &keys_dir, &record_path).unwrap();
+        assert_eq!(std::fs::read(keys_dir.join("ace.bikey")).unwrap(), b"ace");
+        assert_eq!(std::fs::read(keys_dir.join("cba.BIKEY")).unwrap(), b"cba");
+
+        // @cba went away, only its key goes with it
+        install_keys(&base_path, ["@ace"], &keys_dir, &record_path).unwrap();
+        assert!(keys_dir.join("ace.bikey").exists());
+        assert!(!keys_dir.join("cba.BIKEY").exists());
+        assert!(keys_dir.join("a3.bikey").exists());
+
+        install_keys(&base_path, [], &keys_dir, &record_path).unwrap();
+        assert!(!keys_dir.join("ace.bikey").exists());
+        assert!(keys_dir.join("a3.bikey").exists());
+    }
+
+    #[test]
+    fn keys_dir_switch_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("mods");
+        let record_path = base_path.join("nimble-keys.json");
+        let old_keys_dir = dir.path().join("Keys");
+        let new_keys_dir = dir.path().join("OtherKeys");
+
+        std::fs::create_dir_all(base_path.join("@ace/keys")).unwrap();
+        std::fs::write(base_path.join("@ace/keys/ace.bikey"), "ace").unwrap();
+        install_keys(&base_path, ["@ace"], &old_keys_dir, &record_path).unwrap();
+
+        // the same key was put into the other directory by hand, so it isn't nimble's to remove
+        std::fs::create_dir_all(&new_keys_dir).unwrap();
+        std::fs::write(new_keys_dir.join("ace.bikey"), "ace").unwrap();
+        install_keys(&base_path, [], &new_keys_dir, &record_path).unwrap();
+        assert!(new_keys_dir.join("ace.bikey").exists());
+
+        // while the first directory still remembers its own
+        install_keys(&base_path, [], &old_keys_dir, &record_path).unwrap();
+        assert!(!old_keys_dir.join("ace.bikey").exists());
+    }
+}
@@ -1,33 +1,323 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::{Parser, Subcommand};
 
+mod checksum;
 mod commands;
-mod md5_digest;
+mod config;
+mod dedup;
+mod http;
+mod interrupt;
+mod keys;
 mod mod_cache;
+mod mod_source;
+mod nimbleignore;
+mod output;
+mod partial_downloads;
 mod pbo;
+mod repo_history;
 mod repository;
+mod signature;
 mod srf;
+mod sync_journal;
+mod sync_progress;
+#[cfg(test)]
+mod test_server;
 
 #[derive(Subcommand)]
 enum Commands {
     Sync {
-        #[clap(short, long)]
-        repo_url: String,
+        /// defaults to repo_url from nimble.toml. mirrors of the same repo can be given as more
+        /// urls, separated by commas or by repeating the flag
+        #[clap(short, long, value_delimiter = ',')]
+        repo_url: Vec<String>,
 
+        /// defaults to path from nimble.toml
         #[clap(short, long)]
-        path: PathBuf,
+        path: Option<PathBuf>,
 
         #[clap(short, long)]
         dry_run: bool,
+
+        /// maximum size of repo.json, in bytes
+        #[clap(long, default_value_t = repository::DEFAULT_MAX_REPO_SIZE)]
+        max_repo_size: u64,
+
+        /// check every part of downloaded files against the repository, refetching corrupt parts
+        #[clap(long)]
+        verify_parts: bool,
+
+        /// size of the buffer used when writing downloads to disk, in bytes
+        #[clap(long, default_value_t = commands::sync::DEFAULT_DOWNLOAD_BUFFER as u64, value_parser = clap::value_parser!(u64).range(1..))]
+        download_buffer: u64,
+
+        /// set the modification time of downloaded files from pbo timestamps or the server
+        #[clap(long)]
+        preserve_mtimes: bool,
+
+        /// replace downloaded files that already exist elsewhere in the mod path with hardlinks
+        #[clap(long)]
+        dedup: bool,
+
+        /// check signatures of downloaded pbos against the .bikey files in this directory
+        #[clap(long, value_name = "KEYS DIR")]
+        check_signatures: Option<PathBuf>,
+
+        /// copy the installed mods' .bikey files into this directory, usually the game's Keys
+        #[clap(long, value_name = "KEYS DIR")]
+        keys_dir: Option<PathBuf>,
+
+        /// json prints newline delimited events to stdout instead of progress bars
+        #[clap(long, value_enum, default_value_t)]
+        format: output::Format,
+
+        /// number of concurrent downloads, defaults to the number of CPUs
+        #[clap(short, long)]
+        jobs: Option<usize>,
+
+        /// how many times to retry requests failing with connection or server errors
+        #[clap(long, default_value_t = http::DEFAULT_RETRIES)]
+        retries: u32,
+
+        /// seconds to wait before the first retry, doubling with every further one
+        #[clap(long, default_value_t = http::DEFAULT_RETRY_DELAY)]
+        retry_delay: u64,
+
+        /// also install this optional mod, and keep it updated in later syncs
+        #[clap(long = "with-optional", value_name = "NAME")]
+        with_optional: Vec<String>,
+
+        /// stop installing this optional mod, removing it from disk
+        #[clap(long = "without-optional", value_name = "NAME")]
+        without_optional: Vec<String>,
+
+        /// install every optional mod the repo currently lists
+        #[clap(long)]
+        all_optional: bool,
+
+        /// never download this mod, even if the repo requires it, repeatable
+        #[clap(long = "exclude-mod", value_name = "NAME")]
+        exclude_mods: Vec<String>,
+
+        /// leave excluded mods that are already on disk alone instead of removing them
+        #[clap(long)]
+        keep_excluded: bool,
+
+        /// rescan cached mods whose files changed on disk since they were cached
+        #[clap(long)]
+        revalidate: bool,
+
+        /// check that the server has every file before downloading, one request per file
+        #[clap(long)]
+        validate_remote: bool,
     },
     GenSrf {
         #[clap(short, long)]
         path: PathBuf,
+
+        /// write mod.srf files in Swifty's legacy format instead of JSON
+        #[clap(long)]
+        legacy: bool,
+
+        /// rescan every mod, even those that didn't change since their mod.srf was written
+        #[clap(long)]
+        force: bool,
+
+        /// number of threads used for scanning, defaults to the number of CPUs
+        #[clap(short, long)]
+        jobs: Option<usize>,
+
+        /// treat a directory as a mod even though its name doesn't start with @, repeatable
+        #[clap(long = "include-dir", value_name = "NAME")]
+        include_dirs: Vec<String>,
+
+        /// treat every directory in the mod storage path as a mod
+        #[clap(long)]
+        all_dirs: bool,
+
+        /// leave up to this many unreadable files per mod out of its mod.srf instead of failing
+        #[clap(long, default_value_t = 0)]
+        max_scan_failures: usize,
+
+        /// bytes of a regular file covered by each part. changing it changes every checksum
+        #[clap(long, default_value_t = srf::DEFAULT_PART_SIZE, value_parser = clap::value_parser!(u64).range(1..))]
+        part_size: u64,
+
+        /// hash with this algorithm. only nimble understands anything but md5
+        #[clap(long, value_enum, default_value_t, conflicts_with = "legacy")]
+        checksum_algorithm: checksum::Algorithm,
+
+        /// treat pbos with more entries than this as corrupt
+        #[clap(long, default_value_t = pbo::DEFAULT_MAX_ENTRIES)]
+        max_pbo_entries: usize,
+    },
+    /// write a repo.json listing every mod in the mod storage path as required
+    GenRepo {
+        #[clap(short, long)]
+        path: PathBuf,
+
+        #[clap(long)]
+        repo_name: String,
+
+        /// where to write repo.json, defaults to inside the mod storage path
+        #[clap(short, long)]
+        out: Option<PathBuf>,
+
+        /// version of the repo's contents, shown to players
+        #[clap(long, default_value = "1")]
+        repo_version: String,
+
+        /// parameters clients pass to the game
+        #[clap(long, default_value = "", allow_hyphen_values = true)]
+        client_parameters: String,
+
+        /// name,address,port[,password[,battleye]] of a server to list, repeatable
+        #[clap(long = "server", value_name = "SERVER", value_parser = commands::gen_repo::parse_server)]
+        servers: Vec<repository::Server>,
+
+        /// username clients should use for the mods
+        #[clap(long, requires = "repo_password")]
+        repo_username: Option<String>,
+
+        /// password clients should use for the mods
+        #[clap(long, requires = "repo_username")]
+        repo_password: Option<String>,
+    },
+    /// show which of the repo's mods are installed and up to date, without downloading anything
+    Status {
+        /// defaults to repo_url from nimble.toml. mirrors of the same repo can be given as more
+        /// urls, separated by commas or by repeating the flag
+        #[clap(short, long, value_delimiter = ',')]
+        repo_url: Vec<String>,
+
+        /// defaults to path from nimble.toml
+        #[clap(short, long)]
+        path: Option<PathBuf>,
     },
     Launch {
+        /// defaults to path from nimble.toml
+        #[clap(short, long)]
+        path: Option<PathBuf>,
+
+        /// start the game executable directly instead of going through steam
+        #[clap(long)]
+        direct: bool,
+
+        /// path to arma3_x64.exe, found in the steam libraries by default. implies --direct
+        #[clap(long)]
+        executable: Option<PathBuf>,
+
+        /// run the executable through this proton script, which direct launches need outside
+        /// windows. implies --direct
+        #[cfg(not(windows))]
+        #[clap(long)]
+        proton: Option<PathBuf>,
+
+        /// repository to take client parameters and servers from, defaults to repo_url from
+        /// nimble.toml. mirrors can be given as more urls, separated by commas
+        #[clap(short, long, value_delimiter = ',')]
+        repo_url: Vec<String>,
+
+        /// join this server from the repository once the game has started
+        #[clap(long, value_name = "SERVER NAME")]
+        connect: Option<String>,
+
+        /// leave this mod out of -mod= even though it's installed, repeatable
+        #[clap(long = "exclude-mod", value_name = "NAME")]
+        exclude_mods: Vec<String>,
+
+        /// steam app to start through steam, a number or one of arma3, reforger and dayz. defaults to
+        /// arma3. --direct only starts arma3
+        #[clap(long, value_name = "ID")]
+        app_id: Option<commands::launch::AppId>,
+    },
+    /// check the mods on disk against their mod.srf without downloading anything
+    Verify {
+        #[clap(short, long)]
+        path: PathBuf,
+
+        /// number of threads used for scanning, defaults to the number of CPUs
+        #[clap(short, long)]
+        jobs: Option<usize>,
+    },
+    /// list what a mod's mod.srf expects and how it compares to the files on disk
+    Inspect {
+        #[clap(short, long)]
+        path: PathBuf,
+
+        mod_name: String,
+    },
+    /// show a single pbo's header and the parts its checksum is computed from
+    InspectPbo {
+        path: PathBuf,
+
+        /// hash with this algorithm
+        #[clap(long, value_enum, default_value_t)]
+        checksum_algorithm: checksum::Algorithm,
+    },
+    /// list the mods that changed in the repository since a previously synced version
+    Changelog {
+        /// mirrors of the same repo can be given as more urls, separated by commas
+        #[clap(short, long, required = true, value_delimiter = ',')]
+        repo_url: Vec<String>,
+
+        #[clap(short, long)]
+        path: PathBuf,
+
+        /// repository version to compare against
+        #[clap(long)]
+        from: String,
+    },
+    /// check that every mod in the repository has a reachable mod.srf
+    CheckRepoUrls {
+        /// mirrors of the same repo can be given as more urls, separated by commas
+        #[clap(short, long, required = true, value_delimiter = ',')]
+        repo_url: Vec<String>,
+
+        /// also check the first file listed in each mod.srf
+        #[clap(long)]
+        sample_files: bool,
+    },
+    /// print the part checksums of installed mods as they are scanned from disk
+    ExportChecksums {
+        #[clap(short, long)]
+        path: PathBuf,
+
+        #[clap(required_unless_present = "all")]
+        mod_name: Option<String>,
+
+        /// export every mod instead of a single one
+        #[clap(long, conflicts_with = "mod_name")]
+        all: bool,
+
+        #[clap(long, value_enum, default_value_t = commands::export_checksums::Format::Json)]
+        format: commands::export_checksums::Format,
+
+        /// hash compressed pbo entries by their decompressed contents, like most pbo tools do,
+        /// instead of their raw bytes like mod.srf files
+        #[clap(long)]
+        decompress_pbos: bool,
+    },
+    /// rescan a single mod and refresh its entry in the cache
+    UpdateCache {
+        #[clap(short, long)]
+        path: PathBuf,
+
+        mod_name: String,
+    },
+    /// list mods missing from the cache and leftover temporary files, and the space they take up
+    Clean {
         #[clap(short, long)]
         path: PathBuf,
+
+        /// remove what was found instead of only listing it
+        #[clap(short, long)]
+        yes: bool,
+
+        /// also remove the mod cache, so the next command rebuilds it from disk
+        #[clap(long)]
+        cache: bool,
     },
 }
 
@@ -35,28 +325,445 @@ enum Commands {
 struct Args {
     #[clap(subcommand)]
     command: Commands,
+
+    /// config file with defaults for the other flags, defaults to nimble.toml inside the mod
+    /// storage path or the current directory
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// where to store the mod cache, defaults to nimble-cache.json inside the mod storage path
+    #[clap(long, global = true)]
+    cache_path: Option<PathBuf>,
+
+    /// create the mod storage path if it doesn't exist yet
+    #[clap(long, global = true)]
+    create: bool,
+
+    /// only print warnings and errors, and hide progress bars
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// print more details about what is going on, repeat for even more
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// seconds to wait for a connection to the server
+    #[clap(long, global = true, default_value_t = http::DEFAULT_CONNECT_TIMEOUT)]
+    connect_timeout: u64,
+
+    /// seconds to wait for repo.json, mod.srf and other small requests
+    #[clap(long, global = true, default_value_t = http::DEFAULT_METADATA_TIMEOUT)]
+    metadata_timeout: u64,
+
+    /// seconds a file download may go without receiving any data
+    #[clap(long, alias = "read-timeout", global = true, default_value_t = http::DEFAULT_DOWNLOAD_TIMEOUT)]
+    download_timeout: u64,
+
+    /// username for repositories behind basic authentication
+    #[clap(long, global = true, requires = "password")]
+    username: Option<String>,
+
+    /// password for repositories behind basic authentication
+    #[clap(long, global = true, requires = "username")]
+    password: Option<String>,
+}
+
+impl Commands {
+    // the mod storage path given on the command line, for commands that can also take it from the
+    // config file
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Sync { path, .. } | Self::Status { path, .. } | Self::Launch { path, .. } => {
+                path.as_deref()
+            }
+            Self::GenSrf { path, .. }
+            | Self::GenRepo { path, .. }
+            | Self::ExportChecksums { path, .. }
+            | Self::Clean { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+fn load_config(args: &Args) -> Result<config::Config, config::Error> {
+    match &args.config {
+        Some(path) => config::load(path),
+        // a path that isn't a directory is reported once the command checks it
+        None => match args.command.path() {
+            Some(dir) if !dir.is_dir() => Ok(config::Config::default()),
+            dir => {
+                let dir = dir.unwrap_or(Path::new("."));
+                config::load_or_default(&dir.join(config::DEFAULT_FILE_NAME))
+            }
+        },
+    }
+}
+
+// unwraps a value that can come from either a flag or the config file. a missing one is a usage
+// error, so it exits with the status clap uses for those rather than the 1 of a failed command
+fn required<T>(value: Option<T>, flag: &str, key: &str) -> T {
+    value.unwrap_or_else(|| {
+        eprintln!("{flag} is required, either as a flag or as {key} in nimble.toml");
+        std::process::exit(2);
+    })
+}
+
+// the mirrors given with --repo-url, or else the ones from nimble.toml
+fn repo_mirrors(repo_url: Vec<String>, config: Option<Vec<String>>) -> Option<http::Mirrors> {
+    let urls = if repo_url.is_empty() {
+        config?
+    } else {
+        repo_url
+    };
+
+    Some(http::Mirrors::new(urls))
 }
 
 fn main() {
     let args = Args::parse();
 
-    let mut agent = ureq::AgentBuilder::new()
-        .user_agent("nimble (like Swifty)/0.1")
-        .build();
+    // json output is for programs, which don't want the human one mixed in
+    let json = matches!(
+        args.command,
+        Commands::Sync {
+            format: output::Format::Json,
+            ..
+        }
+    );
+    output::init_logger(args.verbose, args.quiet || json);
+
+    let config = load_config(&args).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
+    let mut mod_dirs = commands::ModDirs {
+        include: config.include_dirs,
+        all: config.all_dirs,
+    };
+
+    let cache_path = |base_path: &Path| {
+        args.cache_path
+            .clone()
+            .unwrap_or_else(|| mod_cache::default_path(base_path))
+    };
+
+    // a mistyped mod storage path fails here, rather than somewhere deep inside the command
+    let local_path = |path: PathBuf| {
+        if let Err(e) = commands::check_local_path(&path, args.create) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+
+        path
+    };
+
+    let client = http::Client::new(
+        Duration::from_secs(args.connect_timeout),
+        Duration::from_secs(args.metadata_timeout),
+        Duration::from_secs(args.download_timeout),
+    );
+
+    let credentials = match (args.username, args.password) {
+        (Some(username), Some(password)) => Some((username, password)),
+        _ => config.username.zip(config.password),
+    };
+
+    let client = match &credentials {
+        Some((username, password)) => client.with_basic_auth(username, password),
+        None => client,
+    };
 
     match args.command {
         Commands::Sync {
             repo_url,
             path,
             dry_run,
+            max_repo_size,
+            verify_parts,
+            download_buffer,
+            preserve_mtimes,
+            dedup,
+            check_signatures,
+            keys_dir,
+            format,
+            jobs,
+            retries,
+            retry_delay,
+            with_optional,
+            without_optional,
+            all_optional,
+            mut exclude_mods,
+            keep_excluded,
+            revalidate,
+            validate_remote,
+        } => {
+            let mirrors = required(
+                repo_mirrors(repo_url, config.repo_url),
+                "--repo-url",
+                "repo_url",
+            );
+            let path = local_path(required(path.or(config.path), "--path", "path"));
+            let client = client.with_retries(retries, Duration::from_secs(retry_delay));
+            exclude_mods.extend(config.exclude_mods);
+
+            let options = commands::sync::SyncOptions {
+                dry_run,
+                max_repo_size,
+                verify_parts,
+                download_buffer: download_buffer as usize,
+                preserve_mtimes,
+                dedup,
+                check_signatures,
+                keys_dir: keys_dir.or(config.keys_dir),
+                jobs: jobs.or(config.jobs),
+                with_optional,
+                without_optional,
+                all_optional,
+                exclude_mods,
+                keep_excluded,
+                mod_dirs,
+                revalidate,
+                validate_remote,
+                format,
+            };
+
+            // only sync checks for it, everything else should just die on ctrl-c
+            if let Err(e) = interrupt::install_handler() {
+                log::warn!("failed to install ctrl-c handler: {e}");
+            }
+
+            let progress = sync_progress::ProgressBars::new(format);
+
+            match commands::sync::sync(
+                &client,
+                &mirrors,
+                &path,
+                &cache_path(&path),
+                &options,
+                &progress,
+            ) {
+                Ok(()) => {}
+                Err(commands::sync::Error::Interrupted) => std::process::exit(130),
+                Err(e) => {
+                    eprintln!("sync failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::GenSrf {
+            path,
+            legacy,
+            force,
+            jobs,
+            include_dirs,
+            all_dirs,
+            max_scan_failures,
+            part_size,
+            checksum_algorithm,
+            max_pbo_entries,
         } => {
-            commands::sync::sync(&mut agent, &repo_url, &path, dry_run).unwrap();
+            let path = local_path(path);
+            mod_dirs.include.extend(include_dirs);
+            mod_dirs.all |= all_dirs;
+
+            let options = commands::gen_srf::GenSrfOptions {
+                legacy,
+                force,
+                jobs,
+                mod_dirs,
+                scan: srf::ScanOptions {
+                    part_size,
+                    max_failures: max_scan_failures,
+                    algorithm: checksum_algorithm,
+                    max_pbo_entries,
+                    ..srf::ScanOptions::default()
+                },
+            };
+            if let Err(e) = commands::gen_srf::gen_srf(&path, &cache_path(&path), &options) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::GenRepo {
+            path,
+            repo_name,
+            out,
+            repo_version,
+            client_parameters,
+            servers,
+            repo_username,
+            repo_password,
+        } => {
+            let path = local_path(path);
+            let options = commands::gen_repo::GenRepoOptions {
+                repo_name,
+                version: repo_version,
+                client_parameters,
+                basic_auth: repo_username
+                    .zip(repo_password)
+                    .map(|(username, password)| repository::BasicAuth { username, password }),
+                servers,
+                mod_dirs,
+            };
+            let out = out.unwrap_or_else(|| path.join("repo.json"));
+
+            if let Err(e) = commands::gen_repo::gen_repo(&path, &out, &options) {
+                eprintln!("failed to generate repo.json: {e}");
+                std::process::exit(1);
+            }
         }
-        Commands::GenSrf { path } => {
-            commands::gen_srf::gen_srf(&path);
+        Commands::Status { repo_url, path } => {
+            let mirrors = required(
+                repo_mirrors(repo_url, config.repo_url),
+                "--repo-url",
+                "repo_url",
+            );
+            let path = local_path(required(path.or(config.path), "--path", "path"));
+
+            if let Err(e) =
+                commands::status::status(&client, &mirrors, &path, &cache_path(&path), &mod_dirs)
+            {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
         }
-        Commands::Launch { path } => {
-            commands::launch::launch(&path).unwrap();
+        Commands::Launch {
+            path,
+            direct,
+            executable,
+            #[cfg(not(windows))]
+            proton,
+            repo_url,
+            connect,
+            mut exclude_mods,
+            app_id,
+        } => {
+            let path = local_path(required(path.or(config.path), "--path", "path"));
+            let mirrors = repo_mirrors(repo_url, config.repo_url);
+            exclude_mods.extend(config.exclude_mods);
+            let options = commands::launch::LaunchOptions {
+                direct,
+                executable,
+                #[cfg(not(windows))]
+                proton,
+                connect,
+                app_id: app_id.or(config.app_id).unwrap_or_default(),
+                exclude_mods,
+                mod_dirs,
+            };
+
+            if let Err(e) = commands::launch::launch(
+                &client,
+                mirrors.as_ref(),
+                &path,
+                &cache_path(&path),
+                &options,
+            ) {
+                eprintln!("failed to launch arma: {e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::Verify { path, jobs } => {
+            let path = local_path(path);
+            // exit codes tell scripts apart problems with the mods from failing to check them
+            match commands::verify::verify(&path, &cache_path(&path), jobs) {
+                Ok(report) if report.mods.iter().all(|r#mod| r#mod.is_ok()) => {}
+                Ok(_) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("failed to verify mods: {e}");
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::Inspect { path, mod_name } => {
+            let path = local_path(path);
+            if let Err(e) = commands::inspect::inspect(&path, &mod_name) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::InspectPbo {
+            path,
+            checksum_algorithm,
+        } => {
+            if let Err(e) = commands::inspect_pbo::inspect_pbo(&path, checksum_algorithm) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::Changelog {
+            repo_url,
+            path,
+            from,
+        } => {
+            let path = local_path(path);
+            let mirrors = http::Mirrors::new(repo_url);
+            if let Err(e) =
+                commands::changelog::changelog(&client, &mirrors, &cache_path(&path), &from)
+            {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::CheckRepoUrls {
+            repo_url,
+            sample_files,
+        } => {
+            let mirrors = http::Mirrors::new(repo_url);
+            if let Err(e) =
+                commands::check_repo_urls::check_repo_urls(&client, &mirrors, sample_files)
+            {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportChecksums {
+            path,
+            mod_name,
+            all: _,
+            format,
+            decompress_pbos,
+        } => {
+            let path = local_path(path);
+            let hashing = if decompress_pbos {
+                srf::PboHashing::Decompressed
+            } else {
+                srf::PboHashing::Raw
+            };
+
+            if let Err(e) = commands::export_checksums::export_checksums(
+                &path,
+                mod_name.as_deref(),
+                format,
+                hashing,
+                &mod_dirs,
+            ) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::UpdateCache { path, mod_name } => {
+            let path = local_path(path);
+            if let Err(e) =
+                commands::update_cache::update_cache(&path, &cache_path(&path), &mod_name)
+            {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Commands::Clean { path, yes, cache } => {
+            let path = local_path(path);
+            let options = commands::clean::CleanOptions {
+                yes,
+                cache,
+                mod_dirs,
+            };
+
+            if let Err(e) = commands::clean::clean(&path, &cache_path(&path), &options) {
+                eprintln!("failed to clean: {e}");
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -1,10 +1,12 @@
-use crate::md5_digest::Md5Digest;
+use crate::checksum::{Algorithm, Checksum};
+use log::warn;
 use serde::{Deserialize, Serialize};
-use snafu::{ResultExt, Snafu};
-use std::collections::HashMap;
+use snafu::{ensure, ResultExt, Snafu};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -12,60 +14,208 @@ pub enum Error {
     FileCreation { source: std::io::Error },
     #[snafu(display("failed to open cache file: {}", source))]
     FileOpen { source: std::io::Error },
+    #[snafu(display("failed to write cache file: {}", source))]
+    FileWrite { source: std::io::Error },
+    #[snafu(display("failed to replace cache file: {}", source))]
+    FilePersist { source: tempfile::PersistError },
     #[snafu(display("serde failed to serialize: {}", source))]
     Serialization { source: serde_json::Error },
     #[snafu(display("serde failed to deserialize: {}", source))]
     Deserialization { source: serde_json::Error },
+    #[snafu(display("cache file is corrupt: integrity checksum does not match its contents"))]
+    IntegrityMismatch,
+    #[snafu(display(
+        "cache file has version {}, but this nimble only understands version {}",
+        found,
+        supported
+    ))]
+    UnsupportedCacheVersion { found: u32, supported: u32 },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Mod {
     pub name: String,
+    // the mod's srf files, so syncs don't have to read mod.srf again. caches written before
+    // version 2 don't have them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<crate::srf::File>>,
 }
 
 impl From<crate::srf::Mod> for Mod {
     fn from(value: crate::srf::Mod) -> Self {
-        Mod { name: value.name }
+        Mod {
+            name: value.name,
+            files: Some(value.files),
+        }
     }
 }
 
 type SrfMod = crate::srf::Mod;
 
+impl Mod {
+    // rebuilds the srf this entry was made from, if the cache has its files
+    pub fn to_srf(&self, checksum: &Checksum) -> Option<SrfMod> {
+        Some(SrfMod {
+            name: self.name.clone(),
+            checksum: checksum.clone(),
+            files: self.files.clone()?,
+            // the checksum tells, there's no need to store it
+            algorithm: checksum.algorithm(),
+        })
+    }
+}
+
+pub const DEFAULT_FILE_NAME: &str = "nimble-cache.json";
+
+// bump this whenever the format changes in a way older versions would misread, adding a migration
+// from the previous version to MIGRATIONS
+const VERSION: u32 = 2;
+
+type Migration = fn(serde_json::Value) -> serde_json::Value;
+
+// MIGRATIONS[n] turns a version n + 1 cache into a version n + 2 one
+const MIGRATIONS: [Migration; VERSION as usize - 1] = [
+    // 2 added srf files to mods, which are optional anyway
+    |cache| cache,
+];
+
+// just enough of a cache to tell which version wrote it
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: u32,
+}
+
+// brings a cache written by any supported version up to the one described by migrations
+fn migrate(
+    mut cache: serde_json::Value,
+    migrations: &[Migration],
+) -> Result<serde_json::Value, Error> {
+    let supported = migrations.len() as u32 + 1;
+    let found = VersionProbe::deserialize(&cache)
+        .context(DeserializationSnafu)?
+        .version;
+
+    ensure!(
+        (1..=supported).contains(&found),
+        UnsupportedCacheVersionSnafu { found, supported }
+    );
+
+    for migration in &migrations[found as usize - 1..] {
+        cache = migration(cache);
+    }
+
+    cache["version"] = supported.into();
+
+    Ok(cache)
+}
+
+// where the cache lives when --cache-path isn't given
+pub fn default_path(base_path: &Path) -> PathBuf {
+    base_path.join(DEFAULT_FILE_NAME)
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ModCache {
     version: u32,
-    pub mods: HashMap<Md5Digest, Mod>,
+    // checksum over mods and repo checksums, catches corruption that still happens to
+    // deserialize. caches written before this existed don't have it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    integrity: Option<Checksum>,
+    pub mods: HashMap<Checksum, Mod>,
+    // lowercased names of the optional mods the user opted into
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub optional: BTreeSet<String>,
+    // the checksum repo.json listed for each mod, by lowercased name, when it was last synced.
+    // repos don't always list the checksum the mod.srf ends up with, this still tells whether
+    // the mod changed since
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub repo_checksums: BTreeMap<String, Checksum>,
+}
+
+// what to_disk actually writes, so the integrity checksum can be filled in without a &mut self
+#[derive(Serialize)]
+struct StoredModCache<'a> {
+    version: u32,
+    integrity: Checksum,
+    mods: &'a HashMap<Checksum, Mod>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    optional: &'a BTreeSet<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    repo_checksums: &'a BTreeMap<String, Checksum>,
 }
 
 impl ModCache {
-    pub fn new(mods: HashMap<Md5Digest, SrfMod>) -> Self {
-        Self {
-            version: 1,
-            mods: mods.into_iter().map(|(k, v)| (k, v.into())).collect(),
+    // inserts the mods one by one, so identical ones are warned about instead of silently dropped
+    pub fn from_mods(mods: impl IntoIterator<Item = SrfMod>) -> Self {
+        let mut cache = Self::new_empty();
+
+        for r#mod in mods {
+            cache.insert(r#mod);
         }
+
+        cache
     }
 
     pub fn new_empty() -> Self {
         Self {
-            version: 1,
+            version: VERSION,
+            integrity: None,
             mods: HashMap::new(),
+            optional: BTreeSet::new(),
+            repo_checksums: BTreeMap::new(),
         }
     }
 
-    pub fn from_disk(repo_path: &Path) -> Result<Self, Error> {
-        let path = repo_path.join("nimble-cache.json");
+    fn compute_integrity(&self) -> Result<Checksum, Error> {
+        // HashMap iteration order is random, sort so the same contents always hash the same
+        let mut entries: Vec<_> = self.mods.iter().collect();
+        entries.sort_by_key(|(checksum, _)| checksum.as_bytes());
+
+        let mut serialized = serde_json::to_vec(&entries).context(SerializationSnafu)?;
+
+        // a wrong repo checksum makes sync skip a mod that changed. left out while there are
+        // none, so caches written before they were recorded still verify
+        if !self.repo_checksums.is_empty() {
+            serialized
+                .extend(serde_json::to_vec(&self.repo_checksums).context(SerializationSnafu)?);
+        }
+
+        Ok(Algorithm::Md5.digest(serialized))
+    }
+
+    pub fn from_disk(path: &Path) -> Result<Self, Error> {
         let open_result = File::open(path);
         match open_result {
             Ok(file) => {
                 let reader = BufReader::new(file);
-                serde_json::from_reader(reader).context(DeserializationSnafu)
+                let stored: serde_json::Value =
+                    serde_json::from_reader(reader).context(DeserializationSnafu)?;
+                let migrated =
+                    VersionProbe::deserialize(&stored).is_ok_and(|probe| probe.version != VERSION);
+
+                let mut cache: Self = serde_json::from_value(migrate(stored, &MIGRATIONS)?)
+                    .context(DeserializationSnafu)?;
+
+                // the checksum was computed over the old format, the next write adds a new one
+                if migrated {
+                    cache.integrity = None;
+                }
+
+                if let Some(integrity) = &cache.integrity {
+                    ensure!(
+                        integrity.verify(&cache.compute_integrity()?),
+                        IntegrityMismatchSnafu
+                    );
+                }
+
+                Ok(cache)
             }
             Err(e) => Err(Error::FileOpen { source: e }),
         }
     }
 
-    pub fn from_disk_or_empty(repo_path: &Path) -> Result<Self, Error> {
-        match Self::from_disk(repo_path) {
+    pub fn from_disk_or_empty(path: &Path) -> Result<Self, Error> {
+        match Self::from_disk(path) {
             Ok(cache) => Ok(cache),
             Err(Error::FileOpen { source }) if source.kind() == std::io::ErrorKind::NotFound => {
                 Ok(Self::new_empty())
@@ -74,21 +224,176 @@ impl ModCache {
         }
     }
 
-    pub fn to_disk(&self, repo_path: &Path) -> Result<(), Error> {
-        let path = repo_path.join("nimble-cache.json");
-        let file = File::create(path).context(FileCreationSnafu)?;
-        let writer = BufWriter::new(file);
+    pub fn to_disk(&self, path: &Path) -> Result<(), Error> {
+        let stored = StoredModCache {
+            version: self.version,
+            integrity: self.compute_integrity()?,
+            mods: &self.mods,
+            optional: &self.optional,
+            repo_checksums: &self.repo_checksums,
+        };
+
+        // write to a temp file in the same directory and rename it over the cache, so an
+        // interrupted write never leaves a truncated cache behind
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let file = NamedTempFile::new_in(dir).context(FileCreationSnafu)?;
+        let mut writer = BufWriter::new(file);
+
+        serde_json::to_writer(&mut writer, &stored).context(SerializationSnafu)?;
+        writer.flush().context(FileWriteSnafu)?;
 
-        serde_json::to_writer(writer, &self).context(SerializationSnafu)?;
+        let file = writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context(FileWriteSnafu)?;
+
+        // without this, a power loss right after the rename can still leave an empty cache on
+        // filesystems that reorder the rename before the data
+        file.as_file().sync_all().context(FileWriteSnafu)?;
+        file.persist(path).context(FilePersistSnafu)?;
 
         Ok(())
     }
 
-    pub fn remove(&mut self, checksum: &Md5Digest) {
-        self.mods.remove(checksum);
+    pub fn remove_by_name(&mut self, name: &str) {
+        self.mods
+            .retain(|_, r#mod| !r#mod.name.eq_ignore_ascii_case(name));
+        self.repo_checksums.remove(&name.to_lowercase());
     }
 
+    // mods are keyed by checksum, so of two mods with identical contents, e.g. two empty ones,
+    // only the last one inserted stays cached
     pub fn insert(&mut self, r#mod: crate::srf::Mod) {
+        if let Some(existing) = self.mods.get(&r#mod.checksum) {
+            if !existing.name.eq_ignore_ascii_case(&r#mod.name) {
+                warn!(
+                    "{} and {} have the same checksum {}, only {} is kept in the cache",
+                    existing.name, r#mod.name, r#mod.checksum, r#mod.name
+                );
+            }
+        }
+
         self.mods.insert(r#mod.checksum.clone(), r#mod.into());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integrity_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_FILE_NAME);
+
+        let mut cache = ModCache::new_empty();
+        cache.mods.insert(
+            Checksum::default(),
+            Mod {
+                name: "@ace".to_string(),
+                files: None,
+            },
+        );
+        cache.to_disk(&path).unwrap();
+
+        assert_eq!(ModCache::from_disk(&path).unwrap().mods.len(), 1);
+
+        let tampered = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("@ace", "@cba");
+        std::fs::write(&path, tampered).unwrap();
+
+        assert!(matches!(
+            ModCache::from_disk(&path),
+            Err(Error::IntegrityMismatch)
+        ));
+
+        cache
+            .repo_checksums
+            .insert("@ace".to_string(), Checksum::default());
+        cache.to_disk(&path).unwrap();
+        assert_eq!(ModCache::from_disk(&path).unwrap().repo_checksums.len(), 1);
+
+        let tampered = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("\"@ace\":\"", "\"@cba\":\"");
+        std::fs::write(&path, tampered).unwrap();
+        assert!(matches!(
+            ModCache::from_disk(&path),
+            Err(Error::IntegrityMismatch)
+        ));
+    }
+
+    #[test]
+    fn checksum_collision_test() {
+        let empty = |name: &str| SrfMod {
+            name: name.to_string(),
+            checksum: Checksum::default(),
+            files: vec![],
+            algorithm: Algorithm::Md5,
+        };
+
+        // identical mods share a key, the cache can't hold both
+        let cache = ModCache::from_mods([empty("@a"), empty("@b")]);
+        assert_eq!(cache.mods.len(), 1);
+        assert_eq!(cache.mods[&Checksum::default()].name, "@b");
+    }
+
+    #[test]
+    fn migrate_test() {
+        fn add_optional(mut cache: serde_json::Value) -> serde_json::Value {
+            cache["optional"] = serde_json::json!(["@jsrs"]);
+            cache
+        }
+
+        let migrations: [Migration; 1] = [add_optional];
+
+        let v1 = serde_json::json!({ "version": 1, "mods": {} });
+        assert_eq!(
+            migrate(v1, &migrations).unwrap(),
+            serde_json::json!({ "version": 2, "mods": {}, "optional": ["@jsrs"] })
+        );
+
+        let v2 = serde_json::json!({ "version": 2, "mods": {} });
+        assert_eq!(migrate(v2.clone(), &migrations).unwrap(), v2);
+
+        for version in [0, 3] {
+            assert!(matches!(
+                migrate(serde_json::json!({ "version": version }), &migrations),
+                Err(Error::UnsupportedCacheVersion { found, supported: 2 }) if found == version
+            ));
+        }
+
+        assert!(matches!(
+            migrate(serde_json::json!({ "mods": {} }), &migrations),
+            Err(Error::Deserialization { .. })
+        ));
+    }
+
+    #[test]
+    fn from_disk_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_FILE_NAME);
+
+        assert!(matches!(
+            ModCache::from_disk(&path),
+            Err(Error::FileOpen { source }) if source.kind() == std::io::ErrorKind::NotFound
+        ));
+        assert!(ModCache::from_disk_or_empty(&path).unwrap().mods.is_empty());
+
+        std::fs::write(&path, r#"{"version":1,"mods":{}}"#).unwrap();
+        assert!(ModCache::from_disk(&path).is_ok());
+
+        std::fs::write(&path, r#"{"version":3,"mods":{}}"#).unwrap();
+        assert!(matches!(
+            ModCache::from_disk(&path),
+            Err(Error::UnsupportedCacheVersion {
+                found: 3,
+                supported: 2
+            })
+        ));
+    }
+}
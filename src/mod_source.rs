@@ -0,0 +1,194 @@
+use crate::srf;
+use snafu::{ResultExt, Snafu};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to open archive {}: {}", path.display(), source))]
+    ArchiveOpen {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+    #[snafu(display("failed to extract archive {}: {}", path.display(), source))]
+    ArchiveExtract {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+    #[snafu(display("failed to scan {}: {}", path.display(), source))]
+    Scan { path: PathBuf, source: srf::Error },
+    #[snafu(display("io error: {}", source))]
+    Io { source: std::io::Error },
+}
+
+// archives are extracted into a temporary directory next to the mod, named like this
+pub const EXTRACT_PREFIX: &str = ".nimble-extract";
+
+// where a mod's files come from: a directory, like swifty repos ship them, or a single zip archive
+// holding that directory, like some communities publish instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModSource {
+    Directory(PathBuf),
+    Archive(PathBuf),
+}
+
+impl ModSource {
+    pub fn new(path: &Path) -> Self {
+        let is_zip = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+
+        if is_zip && path.is_file() {
+            Self::Archive(path.to_owned())
+        } else {
+            Self::Directory(path.to_owned())
+        }
+    }
+
+    // the mod's name, which for archives is the file name without .zip
+    pub fn name(&self) -> String {
+        let name = match self {
+            Self::Directory(path) => path.file_name(),
+            Self::Archive(path) => path.file_stem(),
+        };
+
+        name.unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    // hashes the mod's files the same way for both, so an archive gets the checksum its extracted
+    // directory would have
+    pub fn scan(&self, options: &srf::ScanOptions) -> Result<(srf::Mod, Vec<srf::Warning>), Error> {
+        match self {
+            Self::Directory(path) => {
+                srf::scan_mod_with_options(path, options).context(ScanSnafu { path })
+            }
+            Self::Archive(path) => {
+                let dir = tempfile::tempdir().context(IoSnafu)?;
+                let mod_path = dir.path().join(self.name());
+                extract_archive(path, &mod_path)?;
+
+                srf::scan_mod_with_options(&mod_path, options).context(ScanSnafu { path })
+            }
+        }
+    }
+}
+
+// extracts a mod archive into dest, replacing whatever is there. archives may hold the mod's files
+// directly or wrapped in a directory named after the mod, either way they end up directly in dest
+pub fn extract_archive(path: &Path, dest: &Path) -> Result<(), Error> {
+    let file = File::open(path).context(IoSnafu)?;
+    let mut archive = ZipArchive::new(file).context(ArchiveOpenSnafu { path })?;
+
+    // extracting next to dest keeps the final rename on the same filesystem
+    let parent = dest.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(parent).context(IoSnafu)?;
+    let staging = tempfile::Builder::new()
+        .prefix(EXTRACT_PREFIX)
+        .tempdir_in(parent)
+        .context(IoSnafu)?;
+
+    // entries escaping the staging directory, e.g. through .., are refused by zip itself
+    archive
+        .extract(staging.path())
+        .context(ArchiveExtractSnafu { path })?;
+
+    let name = dest.file_name().unwrap_or_default();
+    let entries = std::fs::read_dir(staging.path())
+        .context(IoSnafu)?
+        .collect::<Result<Vec<_>, _>>()
+        .context(IoSnafu)?;
+
+    let root = match entries.as_slice() {
+        [entry]
+            if entry.file_type().context(IoSnafu)?.is_dir()
+                && entry.file_name().eq_ignore_ascii_case(name) =>
+        {
+            entry.path()
+        }
+        _ => staging.path().to_owned(),
+    };
+
+    if dest.exists() {
+        std::fs::remove_dir_all(dest).context(IoSnafu)?;
+    }
+
+    if root == staging.path() {
+        // tempdirs are only accessible by their owner, the mod should be as open as its neighbours
+        let permissions = std::fs::metadata(parent).context(IoSnafu)?.permissions();
+        std::fs::set_permissions(staging.path(), permissions).context(IoSnafu)?;
+
+        std::fs::rename(staging.into_path(), dest).context(IoSnafu)?;
+    } else {
+        std::fs::rename(root, dest).context(IoSnafu)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn write_archive(path: &Path, files: &[(&str, &str)]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+
+        for (name, contents) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn archive_scan_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("@Test/addons")).unwrap();
+        std::fs::write(dir.path().join("@Test/a.txt"), "a").unwrap();
+        std::fs::write(dir.path().join("@Test/addons/b.txt"), "b").unwrap();
+
+        let flat = dir.path().join("flat/@Test.zip");
+        std::fs::create_dir(flat.parent().unwrap()).unwrap();
+        write_archive(&flat, &[("a.txt", "a"), ("addons/b.txt", "b")]);
+
+        let wrapped = dir.path().join("wrapped/@Test.zip");
+        std::fs::create_dir(wrapped.parent().unwrap()).unwrap();
+        write_archive(
+            &wrapped,
+            &[("@test/a.txt", "a"), ("@test/addons/b.txt", "b")],
+        );
+
+        let options = srf::ScanOptions::default();
+        let (expected, _) = ModSource::new(&dir.path().join("@Test"))
+            .scan(&options)
+            .unwrap();
+
+        for path in [&flat, &wrapped] {
+            let source = ModSource::new(path);
+            assert_eq!(source, ModSource::Archive(path.clone()));
+            assert_eq!(source.name(), "@Test");
+
+            let (r#mod, _) = source.scan(&options).unwrap();
+            assert_eq!(r#mod.name, expected.name);
+            assert_eq!(r#mod.checksum, expected.checksum);
+        }
+
+        let dest = dir.path().join("out/@Test");
+        extract_archive(&wrapped, &dest).unwrap();
+        assert_eq!(std::fs::read(dest.join("addons/b.txt")).unwrap(), b"b");
+
+        extract_archive(&flat, &dest).unwrap();
+        assert_eq!(std::fs::read(dest.join("a.txt")).unwrap(), b"a");
+        assert_eq!(
+            std::fs::metadata(&dest).unwrap().permissions(),
+            std::fs::metadata(dest.parent().unwrap())
+                .unwrap()
+                .permissions()
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+use std::path::Path;
+
+pub const FILE_NAME: &str = ".nimbleignore";
+
+// user files inside mod directories that sync must never touch, listed in a .nimbleignore in the
+// mod storage path. the syntax is gitignore's, patterns being relative to the mod storage path.
+// like in git, the last pattern matching a path decides, so a later !pattern takes back an
+// earlier one
+#[derive(Debug)]
+pub struct NimbleIgnore {
+    matcher: Gitignore,
+}
+
+impl NimbleIgnore {
+    pub fn load(base_path: &Path) -> Self {
+        // arma doesn't care about case in mod paths, so neither do we
+        let mut builder = GitignoreBuilder::new(base_path);
+        builder.case_insensitive(true).ok();
+
+        let ignore_path = base_path.join(FILE_NAME);
+
+        if ignore_path.is_file() {
+            if let Some(e) = builder.add(&ignore_path) {
+                warn!(
+                    "some patterns in {} are invalid: {e}",
+                    ignore_path.display()
+                );
+            }
+        }
+
+        let matcher = builder.build().unwrap_or_else(|e| {
+            warn!("ignoring {}: {e}", ignore_path.display());
+            Gitignore::empty()
+        });
+
+        Self { matcher }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matcher.is_empty()
+    }
+
+    // whether path, or a directory it is in, is ignored. paths outside the mod storage path never
+    // are
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.is_empty() || !path.starts_with(self.matcher.path()) {
+            return false;
+        }
+
+        self.matcher
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_ignored_test() {
+        let base = tempfile::tempdir().unwrap();
+        std::fs::write(
+            base.path().join(FILE_NAME),
+            "# squad configs\n*.xml\n!@ace/keep.xml\n@CBA/userconfig/\n",
+        )
+        .unwrap();
+
+        let ignore = NimbleIgnore::load(base.path());
+        let ignored = |path: &str| ignore.is_ignored(&base.path().join(path), false);
+
+        assert!(ignored("@ace/squad.xml"));
+        assert!(ignored("@ace/addons/squad.XML"));
+        assert!(!ignored("@ace/keep.xml"));
+        assert!(ignored("@cba/userconfig/settings.hpp"));
+        assert!(!ignored("@cba/addons/cba.pbo"));
+        assert!(!ignore.is_ignored(Path::new("/elsewhere/squad.xml"), false));
+
+        assert!(NimbleIgnore::load(&base.path().join("missing")).is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use log::{Level, LevelFilter};
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Human,
+    Json,
+}
+
+// what sync reports with --format json, one object per line on stdout
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    ModsToCheck {
+        mods: Vec<&'a str>,
+    },
+    DownloadStarted {
+        file: &'a str,
+        size: u64,
+    },
+    DownloadFinished {
+        file: &'a str,
+        size: u64,
+    },
+    DownloadFailed {
+        file: &'a str,
+        error: String,
+    },
+    // files and bytes are what the sync set out to download, or would have in a dry run
+    Summary {
+        dry_run: bool,
+        files: usize,
+        bytes: u64,
+        success: bool,
+    },
+}
+
+// prints event as a line of json, if that's what was asked for
+pub fn emit(format: Format, event: &Event) {
+    if format != Format::Json {
+        return;
+    }
+
+    let mut stdout = std::io::stdout().lock();
+
+    // a frontend that went away can't be told about it anyway
+    let _ = serde_json::to_writer(&mut stdout, event);
+    let _ = writeln!(stdout);
+}
+
+// logs go to stderr, so stdout only carries what commands are asked to print. -q shows only warnings
+// and errors, each -v adds a level of detail. RUST_LOG still overrides both
+pub fn init_logger(verbosity: u8, quiet: bool) {
+    let level = match (quiet, verbosity) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+// progress bars are informational output too, so they follow the log level
+pub fn is_quiet() -> bool {
+    log::max_level() < LevelFilter::Info
+}
+
+// formats a byte count with binary units, e.g. 4.2 GiB
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_size_test() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(4509715661), "4.2 GiB");
+    }
+
+    #[test]
+    fn event_test() {
+        let event = Event::DownloadStarted {
+            file: "@ace/addons/ace_common.pbo",
+            size: 1024,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"download_started","file":"@ace/addons/ace_common.pbo","size":1024}"#
+        );
+    }
+}
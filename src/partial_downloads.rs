@@ -0,0 +1,124 @@
+use crate::checksum::{Algorithm, Checksum};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to open partial download record: {}", source))]
+    FileOpen { source: std::io::Error },
+    #[snafu(display("failed to create partial download record: {}", source))]
+    FileCreation { source: std::io::Error },
+    #[snafu(display("failed to write partial download record: {}", source))]
+    FileWrite { source: std::io::Error },
+    #[snafu(display("failed to persist partial download record: {}", source))]
+    FilePersist { source: tempfile::PersistError },
+    #[snafu(display("serde failed to serialize: {}", source))]
+    Serialization { source: serde_json::Error },
+    #[snafu(display("serde failed to deserialize: {}", source))]
+    Deserialization { source: serde_json::Error },
+}
+
+// the record lives next to the cache, like the repo history
+pub fn record_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_file_name("nimble-partial.json")
+}
+
+// partial data lives next to the mods instead, so finished downloads can be renamed into place
+pub fn data_dir(base_path: &Path) -> PathBuf {
+    base_path.join(".nimble-partial")
+}
+
+pub fn data_path(base_path: &Path, file: &str) -> PathBuf {
+    let key = Algorithm::Md5.digest(file.to_lowercase());
+
+    data_dir(base_path).join(key.to_hex_lower())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PartialDownload {
+    // checksum of the remote file when the download started, so we never resume into a newer version
+    pub checksum: Checksum,
+    // ETag or Last-Modified sent as If-Range, none if the transfer can't be resumed
+    pub validator: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PartialDownloads {
+    downloads: HashMap<String, PartialDownload>,
+}
+
+impl PartialDownloads {
+    pub fn from_disk_or_empty(path: &Path) -> Result<Self, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::FileOpen { source: e }),
+        };
+
+        serde_json::from_reader(BufReader::new(file)).context(DeserializationSnafu)
+    }
+
+    pub fn to_disk(&self, path: &Path) -> Result<(), Error> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut writer = BufWriter::new(NamedTempFile::new_in(dir).context(FileCreationSnafu)?);
+
+        serde_json::to_writer(&mut writer, self).context(SerializationSnafu)?;
+        writer.flush().context(FileWriteSnafu)?;
+
+        let file = writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context(FileWriteSnafu)?;
+        file.persist(path).context(FilePersistSnafu)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, file: &str) -> Option<&PartialDownload> {
+        self.downloads.get(file)
+    }
+
+    pub fn insert(&mut self, file: &str, download: PartialDownload) {
+        self.downloads.insert(file.to_string(), download);
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        self.downloads.remove(file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = record_path(&dir.path().join("nimble-cache.json"));
+
+        assert!(PartialDownloads::from_disk_or_empty(&path)
+            .unwrap()
+            .get("@ace/addons/ace_main.pbo")
+            .is_none());
+
+        let download = PartialDownload {
+            checksum: Checksum::new("787662722D70C36DF28CD1D5EE8D8E86").unwrap(),
+            validator: Some("\"abc\"".to_string()),
+        };
+
+        let mut partials = PartialDownloads::default();
+        partials.insert("@ace/addons/ace_main.pbo", download.clone());
+        partials.to_disk(&path).unwrap();
+
+        let partials = PartialDownloads::from_disk_or_empty(&path).unwrap();
+        assert_eq!(partials.get("@ace/addons/ace_main.pbo"), Some(&download));
+    }
+}
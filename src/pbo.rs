@@ -2,18 +2,25 @@ use std::ffi::FromVecWithNulError;
 use std::{
     collections::HashMap,
     ffi::CString,
-    io::{BufRead, Seek},
+    io::{BufRead, ErrorKind, Read, Seek},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use byteorder::{LittleEndian, ReadBytesExt};
-use snafu::{ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+// real pbos have a few thousand entries at most, this only stops garbage input from allocating
+// without bound
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+// some packers write counters or garbage instead of real timestamps. anything before 2001-01-01
+// predates the engine and can't be a real one
+const MIN_PLAUSIBLE_TIMESTAMP: u32 = 978_307_200;
 
 #[derive(Debug)]
 pub struct Pbo<I: BufRead + Seek> {
     pub input: I,
     pub header_len: u64,
-    // We parse this but never really use it.
-    #[allow(dead_code)]
     pub extensions: HashMap<String, String>,
     pub entries: Vec<PboEntry>,
 }
@@ -31,12 +38,9 @@ pub struct PboEntry {
     pub filename: String,
     pub r#type: EntryType,
     pub data_size: u32,
-    // We parse this but never really use it.
-    #[allow(dead_code)]
     pub original_size: u32,
-    #[allow(dead_code)]
+    // where the data starts, but nothing reads pbos by it and most packers leave it at 0
     pub offset: u32,
-    #[allow(dead_code)]
     pub timestamp: u32,
 }
 
@@ -48,23 +52,107 @@ pub enum Error {
     PboType { r#type: u32 },
     #[snafu(display("string deserialization error: {}", source))]
     StringDeserialization { source: FromVecWithNulError },
+    #[snafu(display("pbo has more than {} entries", limit))]
+    TooManyEntries { limit: usize },
+    #[snafu(display("corrupt compressed entry"))]
+    CorruptCompressedData,
+    #[snafu(display("not a pbo, it doesn't start with a version header"))]
+    NotAPbo,
+    #[snafu(display("pbo header ends unexpectedly"))]
+    UnexpectedEof,
+    #[snafu(display(
+        "entry {} declares its data at offset {}, but the entries before it end at {}",
+        filename,
+        offset,
+        expected
+    ))]
+    MisplacedEntry {
+        filename: String,
+        offset: u32,
+        expected: u64,
+    },
+}
+
+fn read_u32<I: Read>(input: &mut I) -> Result<u32, Error> {
+    input
+        .read_u32::<LittleEndian>()
+        .map_err(|source| match source.kind() {
+            ErrorKind::UnexpectedEof => Error::UnexpectedEof,
+            _ => Error::Io { source },
+        })
 }
 
 fn read_string<I: BufRead + Seek>(input: &mut I) -> Result<String, Error> {
     let mut buf = Vec::new();
 
     input.read_until(b'\0', &mut buf).context(IoSnafu {})?;
+    ensure!(buf.last() == Some(&b'\0'), UnexpectedEofSnafu);
 
     let cstring = CString::from_vec_with_nul(buf).context(StringDeserializationSnafu)?;
 
     Ok(cstring.to_string_lossy().to_string())
 }
 
+// pbos use a flavour of lzss: every flag byte covers the next 8 tokens, where a set bit is a literal
+// byte and a clear one a back reference with a 12 bit distance and a 4 bit length. the data also
+// ends with a checksum, which we don't check
+fn decompress_lzss(data: &[u8], original_size: usize) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    let mut input = data.iter().copied();
+    let mut next = || input.next().context(CorruptCompressedDataSnafu);
+
+    while output.len() < original_size {
+        let flags = next()?;
+
+        for bit in 0..8 {
+            if output.len() >= original_size {
+                break;
+            }
+
+            if flags & (1 << bit) != 0 {
+                output.push(next()?);
+                continue;
+            }
+
+            let low = next()?;
+            let high = next()?;
+            let distance = usize::from(low) | (usize::from(high & 0xF0) << 4);
+            let length = usize::from(high & 0x0F) + 3;
+
+            ensure!(distance > 0, CorruptCompressedDataSnafu);
+
+            for _ in 0..length.min(original_size - output.len()) {
+                // references reaching back past the start of the output read as spaces
+                let byte = match output.len().checked_sub(distance) {
+                    Some(pos) => output[pos],
+                    None => b' ',
+                };
+
+                output.push(byte);
+            }
+        }
+    }
+
+    Ok(output)
+}
+
 impl PboEntry {
+    // reads the entry's data from the current position of input, decompressing it if needed
+    pub fn read_data<R: Read>(&self, input: &mut R) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; self.data_size as usize];
+        input.read_exact(&mut data).context(IoSnafu)?;
+
+        if self.r#type == EntryType::Cprs {
+            decompress_lzss(&data, self.original_size as usize)
+        } else {
+            Ok(data)
+        }
+    }
+
     fn read<I: BufRead + Seek>(input: &mut I) -> Result<Self, Error> {
         let filename = read_string(input)?;
 
-        let r#type = input.read_u32::<LittleEndian>().context(IoSnafu {})?;
+        let r#type = read_u32(input)?;
 
         let r#type = match r#type {
             0x56657273 => EntryType::Vers,
@@ -74,10 +162,10 @@ impl PboEntry {
             _ => return Err(Error::PboType { r#type }),
         };
 
-        let original_size = input.read_u32::<LittleEndian>().context(IoSnafu {})?;
-        let offset = input.read_u32::<LittleEndian>().context(IoSnafu {})?;
-        let timestamp = input.read_u32::<LittleEndian>().context(IoSnafu {})?;
-        let data_size = input.read_u32::<LittleEndian>().context(IoSnafu {})?;
+        let original_size = read_u32(input)?;
+        let offset = read_u32(input)?;
+        let timestamp = read_u32(input)?;
+        let data_size = read_u32(input)?;
 
         Ok(PboEntry {
             filename,
@@ -107,10 +195,74 @@ fn read_extensions<I: BufRead + Seek>(input: &mut I) -> Result<HashMap<String, S
 }
 
 impl<I: BufRead + Seek> Pbo<I> {
-    pub fn read(mut input: I) -> Result<Self, Error> {
-        let mut extensions = HashMap::new();
+    // the path the game mounts the pbo's files under
+    pub fn prefix(&self) -> Option<&str> {
+        self.extensions.get("prefix").map(String::as_str)
+    }
+
+    // newest entry timestamp, if the packer recorded any
+    pub fn newest_timestamp(&self) -> Option<SystemTime> {
+        self.entries
+            .iter()
+            .map(|entry| entry.timestamp)
+            .filter(|&timestamp| timestamp >= MIN_PLAUSIBLE_TIMESTAMP)
+            .max()
+            .map(|timestamp| UNIX_EPOCH + Duration::from_secs(timestamp.into()))
+    }
+
+    // entry data is read back to back in header order, as the engine and swifty do. when a packer
+    // did declare offsets, they have to agree with that, counted either from the end of the
+    // header or from the start of the file
+    pub fn check_offsets(&self) -> Result<(), Error> {
+        let entries = self.entries.iter().skip(1);
+
+        if entries.clone().all(|entry| entry.offset == 0) {
+            return Ok(());
+        }
+
+        let mut expected = 0;
+
+        for entry in entries {
+            let offset = u64::from(entry.offset);
+
+            ensure!(
+                offset == expected || offset == self.header_len + expected,
+                MisplacedEntrySnafu {
+                    filename: &entry.filename,
+                    offset: entry.offset,
+                    expected,
+                }
+            );
+
+            expected += u64::from(entry.data_size);
+        }
+
+        Ok(())
+    }
 
-        let mut entries = Vec::new();
+    pub fn read(input: I) -> Result<Self, Error> {
+        Self::read_with_limit(input, DEFAULT_MAX_ENTRIES)
+    }
+
+    // like read, but fails on headers with more than max_entries entries
+    pub fn read_with_limit(mut input: I, max_entries: usize) -> Result<Self, Error> {
+        // every pbo starts with an unnamed, empty Vers entry. anything that can't even be read as
+        // one is some other kind of file
+        let header = match PboEntry::read(&mut input) {
+            Ok(header) => header,
+            Err(
+                Error::PboType { .. } | Error::StringDeserialization { .. } | Error::UnexpectedEof,
+            ) => return NotAPboSnafu.fail(),
+            Err(e) => return Err(e),
+        };
+
+        ensure!(
+            header.r#type == EntryType::Vers && header.filename.is_empty() && header.data_size == 0,
+            NotAPboSnafu
+        );
+
+        let mut extensions = read_extensions(&mut input)?;
+        let mut entries = vec![header];
 
         loop {
             let entry = PboEntry::read(&mut input)?;
@@ -119,6 +271,11 @@ impl<I: BufRead + Seek> Pbo<I> {
                 break;
             }
 
+            ensure!(
+                entries.len() < max_entries,
+                TooManyEntriesSnafu { limit: max_entries }
+            );
+
             if entry.r#type == EntryType::Vers {
                 extensions = read_extensions(&mut input)?;
             }
@@ -147,5 +304,123 @@ mod tests {
         let bytes = include_bytes!("../test_files/@ace/addons/ace_advanced_ballistics.pbo");
         let pbo = Pbo::read(Cursor::new(&bytes)).unwrap();
         assert_eq!(pbo.entries.len(), 49);
+        assert_eq!(pbo.prefix(), Some("z\\ace\\addons\\advanced_ballistics"));
+        assert_eq!(
+            pbo.newest_timestamp(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_664_293_851))
+        );
+    }
+
+    #[test]
+    fn read_data_test() {
+        let entry = PboEntry {
+            filename: "abc.txt".to_string(),
+            r#type: EntryType::Cprs,
+            data_size: 10,
+            original_size: 12,
+            offset: 0,
+            timestamp: 0,
+        };
+
+        // "abc" as literals, then 9 bytes from 3 back, then a checksum we ignore
+        let data = [0x07, b'a', b'b', b'c', 0x03, 0x06, 0xDE, 0xAD, 0xBE, 0xEF];
+        assert_eq!(
+            entry.read_data(&mut Cursor::new(data)).unwrap(),
+            b"abcabcabcabc"
+        );
+
+        // a reference before the start of the output
+        assert_eq!(decompress_lzss(&[0x00, 0x05, 0x00], 3).unwrap(), b"   ");
+        assert!(matches!(
+            decompress_lzss(&[0x01], 2),
+            Err(Error::CorruptCompressedData)
+        ));
+
+        let entry = PboEntry {
+            r#type: EntryType::None,
+            original_size: 0,
+            ..entry
+        };
+        assert_eq!(entry.read_data(&mut Cursor::new(data)).unwrap(), data);
+    }
+
+    #[test]
+    fn invalid_pbo_test() {
+        let bytes = include_bytes!("../test_files/@ace/addons/ace_advanced_ballistics.pbo");
+
+        assert!(matches!(
+            Pbo::read(Cursor::new(b"class CfgPatches {};\n")),
+            Err(Error::NotAPbo)
+        ));
+        assert!(matches!(Pbo::read(Cursor::new(b"")), Err(Error::NotAPbo)));
+
+        // a first entry that is a plain file instead of the version header
+        let mut no_header = b"a.txt\0".to_vec();
+        no_header.extend([0; 20]);
+        assert!(matches!(
+            Pbo::read(Cursor::new(no_header)),
+            Err(Error::NotAPbo)
+        ));
+
+        // cut off halfway through the entry list
+        assert!(matches!(
+            Pbo::read(Cursor::new(&bytes[..500])),
+            Err(Error::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn check_offsets_test() {
+        let bytes = include_bytes!("../test_files/@ace/addons/ace_advanced_ballistics.pbo");
+        let mut pbo = Pbo::read(Cursor::new(&bytes)).unwrap();
+        assert!(pbo.check_offsets().is_ok());
+
+        let mut end = 0;
+        for entry in pbo.entries.iter_mut().skip(1) {
+            entry.offset = end;
+            end += entry.data_size;
+        }
+        assert!(pbo.check_offsets().is_ok());
+
+        // the second and third entries swapped places
+        let (second, third) = (pbo.entries[2].offset, pbo.entries[3].offset);
+        pbo.entries[2].offset = third;
+        pbo.entries[3].offset = second;
+        assert!(matches!(
+            pbo.check_offsets(),
+            Err(Error::MisplacedEntry { .. })
+        ));
+    }
+
+    #[test]
+    fn entry_limit_test() {
+        // a header of empty entries, the version header counting as one of them
+        let header = |count: usize| {
+            let mut bytes = vec![0];
+            for field in [0x56657273u32, 0, 0, 0, 0] {
+                bytes.extend(field.to_le_bytes());
+            }
+            bytes.push(0);
+
+            for _ in 1..count {
+                bytes.extend(b"a\0");
+                bytes.extend([0; 20]);
+            }
+
+            bytes.extend([0; 21]);
+            bytes
+        };
+
+        assert!(Pbo::read(Cursor::new(header(DEFAULT_MAX_ENTRIES))).is_ok());
+        assert!(matches!(
+            Pbo::read(Cursor::new(header(DEFAULT_MAX_ENTRIES + 1))),
+            Err(Error::TooManyEntries { .. })
+        ));
+
+        assert!(Pbo::read_with_limit(Cursor::new(header(10)), 10).is_ok());
+        assert!(matches!(
+            Pbo::read_with_limit(Cursor::new(header(11)), 10),
+            Err(Error::TooManyEntries { limit: 10 })
+        ));
     }
 }
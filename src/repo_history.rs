@@ -0,0 +1,93 @@
+use crate::repository::Repository;
+use snafu::{ResultExt, Snafu};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to create history directory: {}", source))]
+    DirCreation { source: std::io::Error },
+    #[snafu(display("failed to create snapshot file: {}", source))]
+    FileCreation { source: std::io::Error },
+    #[snafu(display("no snapshot of repository version {} was recorded", version))]
+    UnknownVersion { version: String },
+    #[snafu(display("failed to open snapshot file: {}", source))]
+    FileOpen { source: std::io::Error },
+    #[snafu(display("serde failed to serialize: {}", source))]
+    Serialization { source: serde_json::Error },
+    #[snafu(display("serde failed to deserialize: {}", source))]
+    Deserialization { source: serde_json::Error },
+}
+
+// snapshots live next to the cache, so per-repo caches also get per-repo histories
+pub fn history_dir(cache_path: &Path) -> PathBuf {
+    cache_path.with_file_name("nimble-history")
+}
+
+fn snapshot_path(dir: &Path, version: &str) -> PathBuf {
+    let file_name: String = version
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    dir.join(format!("{file_name}.json"))
+}
+
+pub fn record(dir: &Path, repo: &Repository) -> Result<(), Error> {
+    std::fs::create_dir_all(dir).context(DirCreationSnafu)?;
+
+    let file = File::create(snapshot_path(dir, &repo.version)).context(FileCreationSnafu)?;
+    serde_json::to_writer(BufWriter::new(file), repo).context(SerializationSnafu)?;
+
+    Ok(())
+}
+
+pub fn load(dir: &Path, version: &str) -> Result<Repository, Error> {
+    let file = match File::open(snapshot_path(dir, version)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return UnknownVersionSnafu { version }.fail()
+        }
+        Err(e) => return Err(Error::FileOpen { source: e }),
+    };
+
+    serde_json::from_reader(BufReader::new(file)).context(DeserializationSnafu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_roundtrip_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo: Repository = serde_json::from_str(
+            r#"{
+                "repoName": "test",
+                "checksum": "",
+                "requiredMods": [],
+                "optionalMods": [],
+                "clientParameters": "",
+                "repoBasicAuthentication": null,
+                "version": "1.0/beta",
+                "servers": []
+            }"#,
+        )
+        .unwrap();
+
+        record(dir.path(), &repo).unwrap();
+
+        assert_eq!(load(dir.path(), "1.0/beta").unwrap().repo_name, "test");
+        assert!(matches!(
+            load(dir.path(), "2.0"),
+            Err(Error::UnknownVersion { .. })
+        ));
+    }
+}
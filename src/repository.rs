@@ -1,8 +1,14 @@
-use crate::md5_digest::Md5Digest;
+use crate::checksum::Checksum;
+use crate::http;
 use serde::{Deserialize, Deserializer, Serialize};
 use snafu::prelude::*;
+use std::collections::HashSet;
+use std::io::Read;
 use std::{fmt::Display, net::IpAddr, str::FromStr};
 
+// repo.json only lists mods and servers, so anything past this is almost certainly garbage
+pub const DEFAULT_MAX_REPO_SIZE: u64 = 4 * 1024 * 1024;
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Error while requesting repository data: {}", source))]
@@ -12,8 +18,28 @@ pub enum Error {
         #[snafu(source(from(ureq::Error, Box::new)))]
         source: Box<ureq::Error>,
     },
-    #[snafu(display("Error while deserializing: {}", source))]
-    Deserialization { source: std::io::Error },
+    #[snafu(display("Error while reading repository data: {}", source))]
+    Io { source: std::io::Error },
+    #[snafu(display(
+        "Repository data at {} exceeds the maximum size of {} bytes",
+        url,
+        limit
+    ))]
+    TooLarge { url: String, limit: u64 },
+    #[snafu(display(
+        "{} is not a valid repo.json, served as {}: {}. It starts with: {}",
+        url,
+        content_type,
+        source,
+        snippet
+    ))]
+    Deserialization {
+        url: String,
+        content_type: String,
+        // the start of the body, which usually tells what the server sent instead
+        snippet: String,
+        source: serde_json::Error,
+    },
 }
 
 pub fn deserialize_number_from_string<'de, T, D>(deserializer: D) -> Result<T, D::Error>
@@ -35,31 +61,31 @@ where
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")] // this particular file is camelcase for reasons
 pub struct Mod {
     pub mod_name: String,
     #[serde(rename = "checkSum")] // why
-    pub checksum: Md5Digest,
+    pub checksum: Checksum,
     pub enabled: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")] // this particular file is camelcase for reasons
 pub struct BasicAuth {
-    username: String,
-    password: String,
+    pub username: String,
+    pub password: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")] // this particular file is camelcase for reasons
 pub struct Server {
-    name: String,
-    address: IpAddr,
+    pub name: String,
+    pub address: IpAddr,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    port: u16,
-    password: String,
-    battle_eye: bool,
+    pub port: u16,
+    pub password: String,
+    pub battle_eye: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -71,15 +97,173 @@ pub struct Repository {
     pub optional_mods: Vec<Mod>,
     pub client_parameters: String,
     pub repo_basic_authentication: Option<BasicAuth>,
+    // the version of the repo's contents, picked freely by whoever publishes it (e.g. "1.0/beta").
+    // it says nothing about the format of repo.json, so there is nothing to check it against
     pub version: String,
     pub servers: Vec<Server>,
 }
 
-pub fn get_repository_info(agent: &mut ureq::Agent, url: &str) -> Result<Repository, Error> {
-    agent
-        .get(url)
-        .call()
-        .context(HttpSnafu { url })?
-        .into_json()
-        .context(DeserializationSnafu)
+impl Repository {
+    // lowercased names of the mods the repo lists but has turned off
+    pub fn disabled_mod_names(&self) -> HashSet<String> {
+        self.required_mods
+            .iter()
+            .chain(&self.optional_mods)
+            .filter(|r#mod| !r#mod.enabled)
+            .map(|r#mod| r#mod.mod_name.to_lowercase())
+            .collect()
+    }
+
+    // the repo may bring its own credentials for everything past repo.json
+    pub fn authorized_client(&self, client: &http::Client) -> http::Client {
+        match &self.repo_basic_authentication {
+            Some(auth) => client
+                .clone()
+                .with_basic_auth(&auth.username, &auth.password),
+            None => client.clone(),
+        }
+    }
+}
+
+// reads at most limit bytes, returning None if the input had more than that
+fn read_limited(input: impl Read, limit: u64) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let mut buf = Vec::new();
+    input.take(limit + 1).read_to_end(&mut buf)?;
+
+    if buf.len() as u64 > limit {
+        return Ok(None);
+    }
+
+    Ok(Some(buf))
+}
+
+// the first few hundred bytes of a body on a single line, to show in errors
+fn body_snippet(body: &[u8]) -> String {
+    const MAX_SNIPPET: usize = 200;
+
+    let text = String::from_utf8_lossy(&body[..body.len().min(MAX_SNIPPET)]);
+    let snippet = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if body.len() > MAX_SNIPPET {
+        format!("{snippet}...")
+    } else {
+        snippet
+    }
+}
+
+// fetches repo.json from whichever mirror answers
+pub fn get_mirrored_repository_info(
+    client: &http::Client,
+    mirrors: &http::Mirrors,
+    max_size: u64,
+) -> Result<Repository, Error> {
+    let (response, mirror) = mirrors
+        .call(client, "repo.json", |url| client.get_metadata(url))
+        .context(HttpSnafu {
+            url: mirrors.url("repo.json"),
+        })?;
+
+    read_repository_info(response, &format!("{mirror}repo.json"), max_size)
+}
+
+fn read_repository_info(
+    response: ureq::Response,
+    url: &str,
+    max_size: u64,
+) -> Result<Repository, Error> {
+    // proxies in front of a repo sometimes answer with an html error page and a 200
+    let content_type = response.content_type().to_string();
+    let reader = http::decoded_reader(response);
+
+    let body = read_limited(reader, max_size)
+        .context(IoSnafu)?
+        .context(TooLargeSnafu {
+            url,
+            limit: max_size,
+        })?;
+
+    serde_json::from_slice(&body).context(DeserializationSnafu {
+        url,
+        content_type,
+        snippet: body_snippet(&body),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_limited_test() {
+        let input = [0u8; 16];
+
+        assert_eq!(
+            read_limited(Cursor::new(input), 16).unwrap().unwrap().len(),
+            16
+        );
+        assert!(read_limited(Cursor::new(input), 15).unwrap().is_none());
+    }
+
+    #[test]
+    fn html_error_page_test() {
+        let response: ureq::Response = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<html>\n  <h1>404 Not Found</h1>\n</html>"
+            .parse()
+            .unwrap();
+
+        let e = read_repository_info(
+            response,
+            "http://example.com/repo.json",
+            DEFAULT_MAX_REPO_SIZE,
+        )
+        .unwrap_err();
+        let message = e.to_string();
+
+        assert!(message.contains("served as text/html"), "{message}");
+        assert!(
+            message.ends_with("It starts with: <html> <h1>404 Not Found</h1> </html>"),
+            "{message}"
+        );
+        assert_eq!(
+            body_snippet(&[b'a'; 300]),
+            format!("{}...", "a".repeat(200))
+        );
+    }
+
+    #[test]
+    fn authorized_client_test() {
+        let mut repo: Repository = serde_json::from_value(serde_json::json!({
+            "repoName": "test",
+            "checksum": "",
+            "requiredMods": [],
+            "optionalMods": [],
+            "clientParameters": "",
+            "repoBasicAuthentication": null,
+            "version": "1",
+            "servers": []
+        }))
+        .unwrap();
+        let client = http::Client::new(
+            std::time::Duration::from_secs(5),
+            std::time::Duration::from_secs(5),
+            std::time::Duration::from_secs(5),
+        );
+        let authorization = |client: &http::Client| {
+            client
+                .get_file("http://localhost/")
+                .header("Authorization")
+                .map(str::to_owned)
+        };
+
+        assert_eq!(authorization(&repo.authorized_client(&client)), None);
+
+        repo.repo_basic_authentication = Some(BasicAuth {
+            username: "Aladdin".to_string(),
+            password: "open sesame".to_string(),
+        });
+        assert_eq!(
+            authorization(&repo.authorized_client(&client)).as_deref(),
+            Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==")
+        );
+    }
 }
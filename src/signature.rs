@@ -0,0 +1,461 @@
+use crate::pbo::{self, Pbo};
+use byteorder::{LittleEndian, ReadBytesExt};
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("io error: {}", source))]
+    Io { source: std::io::Error },
+    #[snafu(display("failed to read {}: {}", path.display(), source))]
+    FileRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to read pbo: {}", source))]
+    PboRead { source: pbo::Error },
+    #[snafu(display("invalid key: {}", reason))]
+    InvalidKey { reason: &'static str },
+    #[snafu(display("unsupported signature version {}", version))]
+    UnsupportedVersion { version: u32 },
+    #[snafu(display("pbo is not signed"))]
+    NotSigned,
+    #[snafu(display("signed by {}, which has no key", authority))]
+    UnknownAuthority { authority: String },
+    #[snafu(display("signature doesn't match the pbo"))]
+    SignatureMismatch,
+}
+
+// what a PUBLICKEYBLOB for an RSA signing key starts with, everything up to "RSA1"
+const KEY_BLOB_HEADER: [u8; 12] = [
+    0x06, 0x02, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, b'R', b'S', b'A', b'1',
+];
+
+// the size DSCreateKey makes, smaller keys are either broken or forged
+const MIN_KEY_BITS: u32 = 1024;
+
+// DER prefix of a pkcs#1 DigestInfo holding a sha1 hash
+const SHA1_DIGEST_INFO: [u8; 15] = [
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+
+// version 2 signs every file except these, version 3 only these
+const V2_UNSIGNED_EXTENSIONS: [&str; 13] = [
+    "paa", "jpg", "p3d", "tga", "rvmat", "lip", "ogg", "wss", "png", "rtm", "pac", "fxy", "wrp",
+];
+const V3_SIGNED_EXTENSIONS: [&str; 11] = [
+    "sqf", "inc", "bikb", "ext", "fsm", "sqm", "hpp", "cfg", "sqs", "h", "sqfc",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    pub authority: String,
+    bits: u32,
+    exponent: BigUint,
+    modulus: BigUint,
+}
+
+#[derive(Debug)]
+pub struct Signature {
+    pub authority: String,
+    version: u32,
+    // hash of the whole pbo, of its names and prefix, and of its file contents
+    signatures: [BigUint; 3],
+}
+
+fn read_cstring<R: Read>(input: &mut R) -> Result<String, Error> {
+    let mut buf = Vec::new();
+
+    loop {
+        match input.read_u8().context(IoSnafu)? {
+            0 => break,
+            byte => buf.push(byte),
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+// length prefixed little endian number, like the signatures themselves
+fn read_number<R: Read>(input: &mut R) -> Result<BigUint, Error> {
+    let len = input.read_u32::<LittleEndian>().context(IoSnafu)?;
+    let mut buf = Vec::new();
+    input
+        .take(len.into())
+        .read_to_end(&mut buf)
+        .context(IoSnafu)?;
+
+    Ok(BigUint::from_bytes_le(&buf))
+}
+
+fn read_key_blob<R: Read>(input: &mut R, authority: String) -> Result<PublicKey, Error> {
+    let len = input.read_u32::<LittleEndian>().context(IoSnafu)?;
+    let mut header = [0; KEY_BLOB_HEADER.len()];
+    input.read_exact(&mut header).context(IoSnafu)?;
+
+    ensure!(
+        header == KEY_BLOB_HEADER,
+        InvalidKeySnafu {
+            reason: "not an rsa public key"
+        }
+    );
+
+    let bits = input.read_u32::<LittleEndian>().context(IoSnafu)?;
+    let exponent = input.read_u32::<LittleEndian>().context(IoSnafu)?;
+
+    ensure!(
+        bits % 8 == 0 && len == 20 + bits / 8,
+        InvalidKeySnafu {
+            reason: "key length doesn't match its size"
+        }
+    );
+    // anything smaller can't even fit the padding verify expects, and bi's tools never make them
+    ensure!(
+        bits >= MIN_KEY_BITS,
+        InvalidKeySnafu {
+            reason: "key is too small"
+        }
+    );
+
+    let mut modulus = vec![0; bits as usize / 8];
+    input.read_exact(&mut modulus).context(IoSnafu)?;
+
+    Ok(PublicKey {
+        authority,
+        bits,
+        exponent: exponent.into(),
+        modulus: BigUint::from_bytes_le(&modulus),
+    })
+}
+
+impl PublicKey {
+    // reads a .bikey
+    pub fn read<R: Read>(input: &mut R) -> Result<Self, Error> {
+        let authority = read_cstring(input)?;
+
+        read_key_blob(input, authority)
+    }
+
+    // checks a signature made with the matching private key over hash
+    fn verify(&self, signature: &BigUint, hash: &[u8]) -> bool {
+        // pkcs#1 v1.5 padding: 00 01 ff .. ff 00 DigestInfo
+        let len = self.bits as usize / 8;
+        let mut padded = vec![0xff; len];
+        let digest_start = len - SHA1_DIGEST_INFO.len() - hash.len();
+        padded[0] = 0x00;
+        padded[1] = 0x01;
+        padded[digest_start - 1] = 0x00;
+        padded[digest_start..len - hash.len()].copy_from_slice(&SHA1_DIGEST_INFO);
+        padded[len - hash.len()..].copy_from_slice(hash);
+
+        signature.modpow(&self.exponent, &self.modulus) == BigUint::from_bytes_be(&padded)
+    }
+}
+
+// sha1 of the lowercased names of entries with data, then of the data selected by version
+fn entry_hashes<I: BufRead + Seek>(
+    pbo: &mut Pbo<I>,
+    version: u32,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    // data follows the header in entry order, but the hashes go by name
+    let mut offset = pbo.header_len;
+    let mut entries: Vec<_> = pbo
+        .entries
+        .iter()
+        .map(|entry| {
+            let start = offset;
+            offset += u64::from(entry.data_size);
+            (entry.filename.to_lowercase(), start, entry.data_size)
+        })
+        .filter(|(_, _, data_size)| *data_size > 0)
+        .collect();
+    entries.sort();
+
+    let mut name_hasher = Sha1::new();
+    let mut file_hasher = Sha1::new();
+    let mut hashed_any = false;
+
+    for (name, start, data_size) in &entries {
+        name_hasher.update(name.as_bytes());
+
+        let extension = name.rsplit_once('.').map_or("", |(_, extension)| extension);
+        let signed = match version {
+            2 => !V2_UNSIGNED_EXTENSIONS.contains(&extension),
+            _ => V3_SIGNED_EXTENSIONS.contains(&extension),
+        };
+
+        if signed {
+            pbo.input.seek(SeekFrom::Start(*start)).context(IoSnafu)?;
+            std::io::copy(
+                &mut (&mut pbo.input).take((*data_size).into()),
+                &mut file_hasher,
+            )
+            .context(IoSnafu)?;
+            hashed_any = true;
+        }
+    }
+
+    if !hashed_any {
+        file_hasher.update(if version == 2 { "nothing" } else { "gnihton" });
+    }
+
+    Ok((
+        name_hasher.finalize().to_vec(),
+        file_hasher.finalize().to_vec(),
+    ))
+}
+
+// the three hashes a signature covers
+fn pbo_hashes<I: BufRead + Seek>(pbo: &mut Pbo<I>, version: u32) -> Result<[Vec<u8>; 3], Error> {
+    // everything up to the trailing checksum the packer appends
+    let data_end = pbo.header_len
+        + pbo
+            .entries
+            .iter()
+            .map(|entry| u64::from(entry.data_size))
+            .sum::<u64>();
+
+    let mut hasher = Sha1::new();
+    pbo.input.seek(SeekFrom::Start(0)).context(IoSnafu)?;
+    std::io::copy(&mut (&mut pbo.input).take(data_end), &mut hasher).context(IoSnafu)?;
+    let pbo_hash = hasher.finalize().to_vec();
+
+    let (name_hash, file_hash) = entry_hashes(pbo, version)?;
+
+    let prefix = match pbo.prefix() {
+        Some(prefix) if !prefix.is_empty() && !prefix.ends_with('\\') => format!("{prefix}\\"),
+        Some(prefix) => prefix.to_string(),
+        None => String::new(),
+    };
+
+    let with_names = |hash: &[u8]| {
+        Sha1::new()
+            .chain_update(hash)
+            .chain_update(&name_hash)
+            .chain_update(prefix.as_bytes())
+            .finalize()
+            .to_vec()
+    };
+
+    Ok([
+        pbo_hash.clone(),
+        with_names(&pbo_hash),
+        with_names(&file_hash),
+    ])
+}
+
+impl Signature {
+    // reads a .bisign
+    pub fn read<R: Read>(input: &mut R) -> Result<Self, Error> {
+        let authority = read_cstring(input)?;
+        // the signer's public key, which the one we trust has to match anyway
+        read_key_blob(input, authority.clone())?;
+
+        let first = read_number(input)?;
+        let version = input.read_u32::<LittleEndian>().context(IoSnafu)?;
+        let second = read_number(input)?;
+        let third = read_number(input)?;
+
+        ensure!(
+            version == 2 || version == 3,
+            UnsupportedVersionSnafu { version }
+        );
+
+        Ok(Signature {
+            authority,
+            version,
+            signatures: [first, second, third],
+        })
+    }
+
+    pub fn verify<I: BufRead + Seek>(
+        &self,
+        key: &PublicKey,
+        pbo: &mut Pbo<I>,
+    ) -> Result<(), Error> {
+        let hashes = pbo_hashes(pbo, self.version)?;
+
+        ensure!(
+            self.signatures
+                .iter()
+                .zip(&hashes)
+                .all(|(signature, hash)| key.verify(signature, hash)),
+            SignatureMismatchSnafu
+        );
+
+        Ok(())
+    }
+}
+
+// the keys in dir by authority, like a server's keys directory
+pub fn load_keys(dir: &Path) -> Result<HashMap<String, PublicKey>, Error> {
+    let mut keys = HashMap::new();
+
+    for entry in std::fs::read_dir(dir).context(FileReadSnafu { path: dir })? {
+        let path = entry.context(FileReadSnafu { path: dir })?.path();
+
+        let is_key = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("bikey"));
+
+        if !is_key {
+            continue;
+        }
+
+        let file = File::open(&path).context(FileReadSnafu { path: &path })?;
+        let key = PublicKey::read(&mut BufReader::new(file))?;
+        keys.insert(key.authority.clone(), key);
+    }
+
+    Ok(keys)
+}
+
+// the .bisign files next to a pbo, named <pbo>.<authority>.bisign
+fn signature_paths(pbo_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let dir = pbo_path.parent().unwrap_or(Path::new("."));
+    let prefix = format!(
+        "{}.",
+        pbo_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase()
+    );
+
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(dir).context(FileReadSnafu { path: dir })? {
+        let entry = entry.context(FileReadSnafu { path: dir })?;
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+
+        if name.starts_with(&prefix) && name.ends_with(".bisign") {
+            paths.push(entry.path());
+        }
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+// succeeds if any of the pbo's signatures was made by one of keys
+pub fn check_pbo(pbo_path: &Path, keys: &HashMap<String, PublicKey>) -> Result<(), Error> {
+    let file = File::open(pbo_path).context(FileReadSnafu { path: pbo_path })?;
+    let mut pbo = Pbo::read(BufReader::new(file)).context(PboReadSnafu)?;
+
+    let mut result = NotSignedSnafu.fail();
+
+    for path in signature_paths(pbo_path)? {
+        let file = File::open(&path).context(FileReadSnafu { path: &path })?;
+        let signature = Signature::read(&mut BufReader::new(file))?;
+
+        result = keys
+            .get(&signature.authority)
+            .context(UnknownAuthoritySnafu {
+                authority: &signature.authority,
+            })
+            .and_then(|key| signature.verify(key, &mut pbo));
+
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // a throwaway 1024 bit key, the same size DSCreateKey makes
+    const MODULUS: &str = "eb8a66d55085279d33529d187839d24fba035dce8d44f130a0658ccf0b0990035ad689fd8c5d8caf1eacf48685780bdb8a546b790d88eba0ef558950a5839123c0381b15d16e70eca713e3dd3df32cdb89bd7a3447e4f7b1de623bd969a101bdb585059686ad4e2ced72120222e7318aa5d6f411a9fa3b67f1e1623d859bf071";
+    const PRIVATE_EXPONENT: &str = "387815ef4e3e9e20b20d3c5c81765b5581e30cba58640eeef9855f1cc428a6be2729082c215acc3fafbb0764b8daeefcd34d29f6fba7b7d5fd8b2440916dd97da2f99fb423405cd1eb9455a1f2666454fba4a808be9c708754271697c0ac7a20fdfd7b27ffd00d3dc7f26d8c5abc151bee5d2cc62fea25f361e40b3371086981";
+
+    fn key_blob(modulus: &BigUint) -> Vec<u8> {
+        let mut blob = 148u32.to_le_bytes().to_vec();
+        blob.extend(KEY_BLOB_HEADER);
+        blob.extend(1024u32.to_le_bytes());
+        blob.extend(65537u32.to_le_bytes());
+        blob.extend(modulus.to_bytes_le());
+        blob
+    }
+
+    fn sign(hash: &[u8], modulus: &BigUint) -> BigUint {
+        let mut padded = vec![0x00, 0x01];
+        padded.resize(128 - SHA1_DIGEST_INFO.len() - hash.len() - 1, 0xff);
+        padded.push(0x00);
+        padded.extend(SHA1_DIGEST_INFO);
+        padded.extend(hash);
+
+        let private_exponent = BigUint::parse_bytes(PRIVATE_EXPONENT.as_bytes(), 16).unwrap();
+        BigUint::from_bytes_be(&padded).modpow(&private_exponent, modulus)
+    }
+
+    #[test]
+    fn verify_test() {
+        let bytes = include_bytes!("../test_files/@ace/addons/ace_advanced_ballistics.pbo");
+        let mut pbo = Pbo::read(Cursor::new(&bytes[..])).unwrap();
+        let modulus = BigUint::parse_bytes(MODULUS.as_bytes(), 16).unwrap();
+
+        let mut bikey = b"test\0".to_vec();
+        bikey.extend(key_blob(&modulus));
+        let key = PublicKey::read(&mut Cursor::new(bikey)).unwrap();
+
+        let mut bisign = b"test\0".to_vec();
+        bisign.extend(key_blob(&modulus));
+
+        for (i, hash) in pbo_hashes(&mut pbo, 3).unwrap().iter().enumerate() {
+            let signature = sign(hash, &modulus).to_bytes_le();
+            bisign.extend((signature.len() as u32).to_le_bytes());
+            bisign.extend(signature);
+
+            if i == 0 {
+                bisign.extend(3u32.to_le_bytes());
+            }
+        }
+
+        let signature = Signature::read(&mut Cursor::new(&bisign)).unwrap();
+        assert_eq!(signature.authority, "test");
+        assert!(signature.verify(&key, &mut pbo).is_ok());
+
+        // the same signature claiming to cover version 2 hashes different files
+        let version_2 = Signature {
+            version: 2,
+            ..Signature::read(&mut Cursor::new(&bisign)).unwrap()
+        };
+        assert!(matches!(
+            version_2.verify(&key, &mut pbo),
+            Err(Error::SignatureMismatch)
+        ));
+
+        let mut tampered = bytes.to_vec();
+        tampered[pbo.header_len as usize + 100] ^= 0xff;
+        let mut tampered = Pbo::read(Cursor::new(tampered)).unwrap();
+        assert!(matches!(
+            signature.verify(&key, &mut tampered),
+            Err(Error::SignatureMismatch)
+        ));
+    }
+
+    #[test]
+    fn small_key_test() {
+        let mut bikey = b"test\0".to_vec();
+        bikey.extend(52u32.to_le_bytes());
+        bikey.extend(KEY_BLOB_HEADER);
+        bikey.extend(256u32.to_le_bytes());
+        bikey.extend(65537u32.to_le_bytes());
+        bikey.extend([0xff; 32]);
+
+        assert!(matches!(
+            PublicKey::read(&mut Cursor::new(bikey)),
+            Err(Error::InvalidKey { .. })
+        ));
+    }
+}
@@ -1,28 +1,29 @@
-use crate::md5_digest::Md5Digest;
-use md5::{Digest, Md5};
+use crate::checksum::{Algorithm, Checksum};
+use log::debug;
 use rayon::prelude::*;
 use relative_path::RelativePathBuf;
-use serde::{Deserialize, Deserializer, Serialize};
-use snafu::{OptionExt, ResultExt, Snafu};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::io::{BufReader, Seek, SeekFrom};
 use std::{
     io,
-    io::{BufRead, Read},
-    path::Path,
+    io::{BufRead, Read, Write},
+    path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "PascalCase")]
 pub struct Part {
-    path: String,
-    length: u64,
-    start: u64,
-    checksum: String,
+    pub path: String,
+    pub length: u64,
+    pub start: u64,
+    pub checksum: Checksum,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum FileType {
     #[serde(rename = "SwiftyFile")]
     File,
@@ -30,6 +31,41 @@ pub enum FileType {
     Pbo,
 }
 
+// swifty hashes the raw bytes of every pbo entry, compressed or not. most other pbo tools hash what
+// compressed entries decompress to instead
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PboHashing {
+    #[default]
+    Raw,
+    Decompressed,
+}
+
+// how much of a regular file each part covers. swifty uses 5 MB, and mod checksums depend on it
+pub const DEFAULT_PART_SIZE: u64 = 5_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub hashing: PboHashing,
+    pub part_size: u64,
+    // files that may fail to scan before the whole mod does, see scan_mod_with_options
+    pub max_failures: usize,
+    pub algorithm: Algorithm,
+    // pbos with more entries than this are treated as corrupt
+    pub max_pbo_entries: usize,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            hashing: PboHashing::Raw,
+            part_size: DEFAULT_PART_SIZE,
+            max_failures: 0,
+            algorithm: Algorithm::Md5,
+            max_pbo_entries: crate::pbo::DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("io error: {}", source))]
@@ -40,8 +76,33 @@ pub enum Error {
     LegacySrfParseFailure { description: &'static str },
     #[snafu(display("legacy srf failed to parse size as u32: {}", source))]
     LegacySrfU32ParseFailure { source: std::num::ParseIntError },
-    #[snafu(display("failed to decode md5 digest: {}", source))]
-    DigestParse { source: crate::md5_digest::Error },
+    #[snafu(display("failed to decode checksum: {}", source))]
+    DigestParse { source: crate::checksum::Error },
+    #[snafu(display("srf deserialization failure: {}", source))]
+    JsonDeserialization { source: serde_json::Error },
+    #[snafu(display("{} is corrupt: {}", path.display(), detail))]
+    CorruptPbo { path: PathBuf, detail: String },
+    #[snafu(display("failed to scan {}: {}", path.display(), source))]
+    FileScan { path: PathBuf, source: Box<Error> },
+    #[snafu(display(
+        "{} files failed to scan, more than the {} allowed. the first: {}",
+        count,
+        max,
+        first
+    ))]
+    TooManyScanFailures {
+        count: usize,
+        max: usize,
+        first: Box<Error>,
+    },
+}
+
+// something a scan got past without failing. scanning never prints, so showing these is up to
+// the caller
+#[derive(Debug, Snafu)]
+pub enum Warning {
+    #[snafu(display("skipped a file: {}", source))]
+    SkippedFile { source: Error },
 }
 
 impl FileType {
@@ -54,6 +115,13 @@ impl FileType {
             }),
         }
     }
+
+    fn to_legacy_srf(&self) -> &'static str {
+        match self {
+            Self::Pbo => "PBO",
+            Self::File => "FILE",
+        }
+    }
 }
 
 // needed because swifty doesn't (didn't?) normalize windows paths
@@ -61,62 +129,113 @@ pub fn deserialize_relative_pathbuf<'de, D>(deserializer: D) -> Result<RelativeP
 where
     D: Deserializer<'de>,
 {
-    let stringly = String::deserialize(deserializer)?;
-    Ok(RelativePathBuf::from_path(stringly.replace('\\', "/")).unwrap())
+    let stringly = String::deserialize(deserializer)?.replace('\\', "/");
+
+    // remote srfs aren't trusted, and their paths end up joined to the mod directory. a drive
+    // prefix only reads as absolute on windows, so it is checked by hand
+    let has_drive = stringly.as_bytes().get(1) == Some(&b':');
+    let path = RelativePathBuf::from_path(&stringly)
+        .ok()
+        .filter(|_| !has_drive)
+        .ok_or_else(|| D::Error::custom(format!("{stringly} is not a relative path")))?;
+
+    if path
+        .components()
+        .any(|component| component == relative_path::Component::ParentDir)
+    {
+        return Err(D::Error::custom(format!(
+            "{stringly} leaves the mod directory"
+        )));
+    }
+
+    Ok(path)
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "PascalCase")]
 pub struct File {
     #[serde(deserialize_with = "deserialize_relative_pathbuf")]
     pub path: RelativePathBuf,
     pub length: u64,
-    pub checksum: String,
+    pub checksum: Checksum,
     pub r#type: FileType,
     pub parts: Vec<Part>,
+    // header extensions of a pbo, like its prefix. they aren't part of any checksum, and swifty
+    // just skips over them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<BTreeMap<String, String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "PascalCase")]
 pub struct Mod {
     pub name: String,
-    pub checksum: Md5Digest,
+    pub checksum: Checksum,
     pub files: Vec<File>,
+    // left out for md5, so swifty still reads the srfs nimble writes
+    #[serde(default, skip_serializing_if = "Algorithm::is_md5")]
+    pub algorithm: Algorithm,
 }
 
 impl Mod {
     pub fn generate_invalid(remote: &Self) -> Self {
         Self {
-            checksum: Md5Digest::default(),
+            checksum: Checksum::default(),
             files: vec![],
             ..remote.clone()
         }
     }
 }
 
-fn generate_hash(file: &mut BufReader<std::fs::File>, len: u64) -> Result<String, Error> {
-    let mut hasher = Md5::new();
-    let mut stream = file.take(len);
+fn generate_hash(
+    file: &mut BufReader<std::fs::File>,
+    len: u64,
+    algorithm: Algorithm,
+) -> Result<Checksum, Error> {
+    Checksum::from_reader(algorithm, &mut file.take(len)).context(IoSnafu {})
+}
 
-    std::io::copy(&mut stream, &mut hasher).context(IoSnafu {})?;
+// swifty hashes the uppercase hex strings of the part checksums, not their bytes
+fn hash_part_checksums(parts: &[Part], algorithm: Algorithm) -> Checksum {
+    let mut hasher = algorithm.hasher();
 
-    let hash = hasher.finalize();
+    for part in parts {
+        hasher.update(part.checksum.to_hex_upper());
+    }
 
-    Ok(format!("{hash:X}"))
+    hasher.finalize()
 }
 
-pub fn scan_pbo(path: &Path, base_path: &Path) -> Result<File, Error> {
+pub fn scan_pbo(path: &Path, base_path: &Path, algorithm: Algorithm) -> Result<File, Error> {
+    scan_pbo_with(
+        path,
+        base_path,
+        PboHashing::Raw,
+        algorithm,
+        crate::pbo::DEFAULT_MAX_ENTRIES,
+    )
+}
+
+pub fn scan_pbo_with(
+    path: &Path,
+    base_path: &Path,
+    hashing: PboHashing,
+    algorithm: Algorithm,
+    max_entries: usize,
+) -> Result<File, Error> {
     let mut file = BufReader::new(std::fs::File::open(path).context(IoSnafu)?);
 
     let mut parts = Vec::new();
-    let pbo = crate::pbo::Pbo::read(&mut file).context(PboSnafu)?;
+    let pbo = crate::pbo::Pbo::read_with_limit(&mut file, max_entries).context(PboSnafu)?;
+    pbo.check_offsets().context(PboSnafu)?;
+    let extensions: BTreeMap<_, _> = pbo.extensions.clone().into_iter().collect();
     let mut offset = 0;
 
     let length = pbo.input.seek(SeekFrom::End(0)).context(IoSnafu)?;
     pbo.input.seek(SeekFrom::Start(0)).context(IoSnafu)?;
 
     {
-        let header_hash = generate_hash(pbo.input, pbo.header_len)?;
+        let header_hash = generate_hash(pbo.input, pbo.header_len, algorithm)?;
         offset += pbo.header_len;
 
         parts.push(Part {
@@ -129,7 +248,27 @@ pub fn scan_pbo(path: &Path, base_path: &Path) -> Result<File, Error> {
 
     // swifty, as always, does very strange things
     for entry in pbo.entries.iter().skip(1) {
-        let hash = generate_hash(pbo.input, u64::from(entry.data_size))?;
+        // entry sizes come from the header, so a damaged pbo can claim more data than it has.
+        // hashing past the end would just hash less and produce a wrong checksum
+        let end = offset + u64::from(entry.data_size);
+        ensure!(
+            end <= length,
+            CorruptPboSnafu {
+                path,
+                detail: format!(
+                    "entry {} ends at byte {}, but the file only has {}",
+                    entry.filename, end, length
+                ),
+            }
+        );
+
+        let hash = match hashing {
+            PboHashing::Raw => generate_hash(pbo.input, u64::from(entry.data_size), algorithm)?,
+            PboHashing::Decompressed => {
+                let data = entry.read_data(pbo.input).context(PboSnafu)?;
+                algorithm.digest(data)
+            }
+        };
 
         parts.push(Part {
             path: entry.filename.clone(),
@@ -142,10 +281,10 @@ pub fn scan_pbo(path: &Path, base_path: &Path) -> Result<File, Error> {
     }
 
     {
-        // TODO: this once panicked due to underflow.
+        // every entry was checked to end within the file
         let remaining_len = length - offset;
 
-        let end_hash = generate_hash(pbo.input, remaining_len)?;
+        let end_hash = generate_hash(pbo.input, remaining_len, algorithm)?;
         parts.push(Part {
             path: "$$END$$".to_string(),
             length: remaining_len,
@@ -154,15 +293,7 @@ pub fn scan_pbo(path: &Path, base_path: &Path) -> Result<File, Error> {
         });
     }
 
-    let checksum = {
-        let mut hasher = Md5::new();
-
-        for part in &parts {
-            hasher.update(&part.checksum);
-        }
-
-        format!("{:X}", hasher.finalize())
-    };
+    let checksum = hash_part_checksums(&parts, algorithm);
 
     let path = RelativePathBuf::from_path(path.strip_prefix(base_path).unwrap()).unwrap();
 
@@ -172,10 +303,20 @@ pub fn scan_pbo(path: &Path, base_path: &Path) -> Result<File, Error> {
         parts,
         checksum,
         length,
+        extensions: (!extensions.is_empty()).then_some(extensions),
     })
 }
 
-pub fn scan_file(path: &Path, base_path: &Path) -> Result<File, Error> {
+pub fn scan_file(path: &Path, base_path: &Path, algorithm: Algorithm) -> Result<File, Error> {
+    scan_file_with(path, base_path, DEFAULT_PART_SIZE, algorithm)
+}
+
+pub fn scan_file_with(
+    path: &Path,
+    base_path: &Path,
+    part_size: u64,
+    algorithm: Algorithm,
+) -> Result<File, Error> {
     let file = std::fs::File::open(path).context(IoSnafu)?;
     let mut parts = Vec::new();
 
@@ -185,22 +326,20 @@ pub fn scan_file(path: &Path, base_path: &Path) -> Result<File, Error> {
     let mut pos = 0;
 
     while pos < file_len {
-        let mut hasher = Md5::new();
-        let mut stream = reader.by_ref().take(5000000);
+        let mut hasher = algorithm.hasher();
+        let mut stream = reader.by_ref().take(part_size);
 
         let pre_copy_pos = pos;
         let copied = std::io::copy(&mut stream, &mut hasher).context(IoSnafu {})?;
         pos += copied;
 
-        let hash = hasher.finalize();
-
         parts.push(Part {
-            checksum: format!("{hash:X}"),
+            checksum: hasher.finalize(),
             length: copied,
             path: format!(
                 "{}_{}",
                 path.components()
-                    .last()
+                    .next_back()
                     .unwrap()
                     .as_os_str()
                     .to_string_lossy(),
@@ -210,31 +349,30 @@ pub fn scan_file(path: &Path, base_path: &Path) -> Result<File, Error> {
         });
     }
 
-    // final checksum generation
-    // swifty hashes the checksum strings
-    let mut hasher = Md5::new();
-
-    for part in &parts {
-        hasher.update(&part.checksum);
-    }
-
     let path = RelativePathBuf::from_path(path.strip_prefix(base_path).unwrap()).unwrap();
 
     Ok(File {
-        checksum: format!("{:X}", hasher.finalize()),
+        checksum: hash_part_checksums(&parts, algorithm),
         length: pos,
         parts,
         path,
         r#type: FileType::File,
+        extensions: None,
     })
 }
 
-fn recurse(path: &Path, base_path: &Path) -> Result<Vec<File>, Error> {
-    println!("recursing into {:#?}", &path);
+// written next to mod.srf by gen-srf, neither of them is part of the mod
+pub const MANIFEST_FILE_NAME: &str = "nimble-manifest.json";
 
-    let entries: Vec<_> = WalkDir::new(path)
+// the files of the mod at path that get hashed. .nimbleignore doesn't apply here, it only decides
+// what sync may touch, so checksums always describe what is on disk
+pub fn mod_files(path: &Path) -> Vec<PathBuf> {
+    WalkDir::new(path)
         .into_iter()
-        .filter_entry(|e| e.file_name() != OsStr::new("mod.srf"))
+        .filter_entry(|e| {
+            e.file_name() != OsStr::new("mod.srf")
+                && e.file_name() != OsStr::new(MANIFEST_FILE_NAME)
+        })
         .filter_map(Result::ok)
         .filter(|e| {
             // someday this spaghetti can just be replaced by Option::contains
@@ -245,25 +383,80 @@ fn recurse(path: &Path, base_path: &Path) -> Result<Vec<File>, Error> {
             }
         })
         .map(|entry| entry.path().to_owned())
-        .collect();
+        .collect()
+}
+
+// scans every file on its own, so one bad file doesn't hide how the others went
+fn recurse(path: &Path, base_path: &Path, options: &ScanOptions) -> Vec<Result<File, Error>> {
+    debug!("recursing into {:#?}", &path);
 
-    let files: Result<Vec<_>, _> = entries
+    let entries = mod_files(path);
+
+    entries
         .par_iter()
         .map(|path| {
             let extension = path.extension();
 
-            match extension {
-                Some(extension) if extension == "pbo" => scan_pbo(path, base_path),
-                _ => scan_file(path, base_path),
-            }
+            let file = match extension {
+                Some(extension) if extension == "pbo" => scan_pbo_with(
+                    path,
+                    base_path,
+                    options.hashing,
+                    options.algorithm,
+                    options.max_pbo_entries,
+                ),
+                _ => scan_file_with(path, base_path, options.part_size, options.algorithm),
+            };
+
+            file.map_err(|e| Error::FileScan {
+                path: path.clone(),
+                source: Box::new(e),
+            })
         })
-        .collect();
-
-    files
+        .collect()
 }
 
 pub fn scan_mod(path: &Path) -> Result<Mod, Error> {
-    let mut files = recurse(path, path)?;
+    scan_mod_with(path, PboHashing::Raw)
+}
+
+pub fn scan_mod_with(path: &Path, hashing: PboHashing) -> Result<Mod, Error> {
+    let options = ScanOptions {
+        hashing,
+        ..ScanOptions::default()
+    };
+
+    scan_mod_with_options(path, &options).map(|(r#mod, _)| r#mod)
+}
+
+// scans the mod at path as options say. up to options.max_failures files that fail to scan are
+// left out of the mod instead of failing it, and come back as warnings alongside it
+pub fn scan_mod_with_options(
+    path: &Path,
+    options: &ScanOptions,
+) -> Result<(Mod, Vec<Warning>), Error> {
+    let max_failures = options.max_failures;
+    let (files, failures): (Vec<_>, Vec<_>) = recurse(path, path, options)
+        .into_iter()
+        .partition(Result::is_ok);
+    let mut files: Vec<File> = files.into_iter().filter_map(Result::ok).collect();
+    let mut failures: Vec<Error> = failures.into_iter().filter_map(Result::err).collect();
+
+    if failures.len() > max_failures {
+        let count = failures.len();
+        let first = failures.swap_remove(0);
+
+        // a lone failure is clearer on its own
+        if count == 1 {
+            return Err(first);
+        }
+
+        return Err(Error::TooManyScanFailures {
+            count,
+            max: max_failures,
+            first: Box::new(first),
+        });
+    }
 
     files.sort_by(|a, b| {
         a.path
@@ -273,29 +466,36 @@ pub fn scan_mod(path: &Path) -> Result<Mod, Error> {
     });
 
     let checksum = {
-        let mut hasher = Md5::new();
+        let mut hasher = options.algorithm.hasher();
 
         for file in &files {
-            hasher.update(&file.checksum);
+            hasher.update(file.checksum.to_hex_upper());
             let relpath = file.path.as_str().to_lowercase().replace('\\', "/");
             hasher.update(relpath);
         }
 
-        let output = hasher.finalize();
-        Md5Digest::from_bytes(output.into())
+        hasher.finalize()
     };
 
-    Ok(Mod {
+    let r#mod = Mod {
         name: path
             .components()
-            .last()
+            .next_back()
             .unwrap()
             .as_os_str()
             .to_string_lossy()
             .to_lowercase(),
         checksum,
         files,
-    })
+        algorithm: options.algorithm,
+    };
+
+    let warnings = failures
+        .into_iter()
+        .map(|source| Warning::SkippedFile { source })
+        .collect();
+
+    Ok((r#mod, warnings))
 }
 
 fn read_legacy_srf_addon(line: &str) -> Result<(Mod, u32), Error> {
@@ -332,13 +532,15 @@ fn read_legacy_srf_addon(line: &str) -> Result<(Mod, u32), Error> {
         })?
         .to_string();
 
-    let checksum = Md5Digest::new(&checksum_digest).context(DigestParseSnafu)?;
+    let checksum = Checksum::new(&checksum_digest).context(DigestParseSnafu)?;
 
     Ok((
         Mod {
             name,
             checksum,
             files: Vec::new(),
+            // legacy srfs predate anything but md5
+            algorithm: Algorithm::Md5,
         },
         size,
     ))
@@ -370,12 +572,10 @@ fn read_legacy_srf_part(line: &str) -> Result<Part, Error> {
         .parse()
         .context(LegacySrfU32ParseFailureSnafu)?;
 
-    let checksum = split
-        .next()
-        .context(LegacySrfParseFailureSnafu {
-            description: "part line missing checksum",
-        })?
-        .to_string();
+    let checksum = split.next().context(LegacySrfParseFailureSnafu {
+        description: "part line missing checksum",
+    })?;
+    let checksum = Checksum::new(checksum).context(DigestParseSnafu)?;
 
     Ok(Part {
         path,
@@ -385,9 +585,22 @@ fn read_legacy_srf_part(line: &str) -> Result<Part, Error> {
     })
 }
 
+// a read error is an io error, running out of lines a parse failure
+fn next_legacy_srf_line(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    missing: &'static str,
+) -> Result<String, Error> {
+    lines
+        .next()
+        .context(LegacySrfParseFailureSnafu {
+            description: missing,
+        })?
+        .context(IoSnafu)
+}
+
 fn read_legacy_srf_file(
     line: &str,
-    lines: &mut impl Iterator<Item = String>,
+    lines: &mut impl Iterator<Item = io::Result<String>>,
 ) -> Result<File, Error> {
     let mut split = line.split(':');
 
@@ -395,13 +608,14 @@ fn read_legacy_srf_file(
         description: "no first element",
     })?)?;
 
+    // legacy srfs use windows style separators
     let path = RelativePathBuf::from(
         split
             .next()
             .context(LegacySrfParseFailureSnafu {
                 description: "file line missing path",
             })?
-            .to_string(),
+            .replace('\\', "/"),
     );
 
     let length: u64 = split
@@ -420,19 +634,15 @@ fn read_legacy_srf_file(
         .parse()
         .context(LegacySrfU32ParseFailureSnafu)?;
 
-    let checksum = split
-        .next()
-        .context(LegacySrfParseFailureSnafu {
-            description: "file line missing checksum",
-        })?
-        .to_string();
+    let checksum = split.next().context(LegacySrfParseFailureSnafu {
+        description: "file line missing checksum",
+    })?;
+    let checksum = Checksum::new(checksum).context(DigestParseSnafu)?;
 
     let mut parts = Vec::new();
 
     for _ in 0..part_count {
-        let line = lines.next().context(LegacySrfParseFailureSnafu {
-            description: "part line missing",
-        })?;
+        let line = next_legacy_srf_line(lines, "part line missing")?;
 
         parts.push(read_legacy_srf_part(&line)?);
     }
@@ -443,6 +653,8 @@ fn read_legacy_srf_file(
         checksum,
         r#type,
         parts,
+        // the legacy format has no place for these
+        extensions: None,
     })
 }
 
@@ -455,33 +667,72 @@ pub fn is_legacy_srf<I: Read + Seek>(input: &mut I) -> Result<bool, io::Error> {
     Ok(String::from_utf8_lossy(&buf) == "ADDON")
 }
 
-pub fn deserialize_legacy_srf<I: BufRead + Seek>(input: &mut I) -> Result<Mod, Error> {
+// reads from wherever the input currently is, so callers skip BOMs and the like themselves. this
+// also works on streams that can't seek
+pub fn deserialize_legacy_srf<I: BufRead>(input: &mut I) -> Result<Mod, Error> {
     // swifty's legacy srf format is stateful
-    input.seek(SeekFrom::Start(0)).context(IoSnafu)?;
     let mut files = Vec::<File>::new();
 
-    let mut iter = input.lines().map(|line| line.expect("input.lines failed"));
+    let mut iter = input.lines();
 
-    let first_line = iter.next().context(LegacySrfParseFailureSnafu {
-        description: "no first line",
-    })?;
+    let first_line = next_legacy_srf_line(&mut iter, "no first line")?;
 
-    let (addon, file_count) = read_legacy_srf_addon(&first_line)?;
+    let (mut addon, file_count) = read_legacy_srf_addon(&first_line)?;
 
     for _ in 0..file_count {
-        let file = read_legacy_srf_file(
-            &iter.next().context(LegacySrfParseFailureSnafu {
-                description: "line missing",
-            })?,
-            &mut iter,
-        )?;
+        let file =
+            read_legacy_srf_file(&next_legacy_srf_line(&mut iter, "line missing")?, &mut iter)?;
 
         files.push(file);
     }
 
+    addon.files = files;
+
     Ok(addon)
 }
 
+// reads a mod.srf in either format
+pub fn read_srf<I: BufRead + Seek>(input: &mut I) -> Result<Mod, Error> {
+    if is_legacy_srf(input).context(IoSnafu)? {
+        deserialize_legacy_srf(input)
+    } else {
+        serde_json::from_reader(input).context(JsonDeserializationSnafu)
+    }
+}
+
+pub fn serialize_legacy_srf<W: Write>(r#mod: &Mod, output: &mut W) -> Result<(), io::Error> {
+    // swifty wrote these on windows, so we mirror its line endings and path separators
+    write!(
+        output,
+        "ADDON:{}:{}:{}\r\n",
+        r#mod.name,
+        r#mod.files.len(),
+        r#mod.checksum
+    )?;
+
+    for file in &r#mod.files {
+        write!(
+            output,
+            "{}:{}:{}:{}:{}\r\n",
+            file.r#type.to_legacy_srf(),
+            file.path.as_str().replace('/', "\\"),
+            file.length,
+            file.parts.len(),
+            file.checksum
+        )?;
+
+        for part in &file.parts {
+            write!(
+                output,
+                "{}:{}:{}:{}\r\n",
+                part.path, part.start, part.length, part.checksum
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -497,8 +748,90 @@ mod tests {
         assert_eq!(deserialized.name, "@lambs_danger");
         assert_eq!(
             deserialized.checksum,
-            Md5Digest::new("44C1B8021822F80E1E560689D2AAB0BF").unwrap()
+            Checksum::new("44C1B8021822F80E1E560689D2AAB0BF").unwrap()
+        );
+
+        assert_eq!(deserialized.files.len(), 19);
+        let bisign = &deserialized.files[0];
+        assert_eq!(
+            bisign.path,
+            "addons/lambs_formations.pbo.lambs_danger_2.5.3-6bb8150d.bisign"
+        );
+        assert_eq!(bisign.parts.len(), 1);
+        assert_eq!(
+            bisign.parts[0].checksum.to_string(),
+            "71E2B570D4B0316E7858050739578DB7"
         );
+
+        // parsing starts wherever the input is, e.g. past a BOM the caller skipped
+        let mut with_bom = b"\xEF\xBB\xBF".to_vec();
+        with_bom.extend(input);
+        let mut cursor = Cursor::new(with_bom);
+        cursor.set_position(3);
+        assert_eq!(deserialize_legacy_srf(&mut cursor).unwrap(), deserialized);
+    }
+
+    #[test]
+    fn legacy_srf_fixture_roundtrip_test() {
+        let input = include_bytes!("../test_files/legacy_format_mod.srf");
+        let deserialized = deserialize_legacy_srf(&mut Cursor::new(input)).unwrap();
+
+        let mut serialized = Vec::new();
+        serialize_legacy_srf(&deserialized, &mut serialized).unwrap();
+
+        // git may have changed the fixture's line endings, which don't matter to the parser
+        let normalize = |bytes: &[u8]| String::from_utf8_lossy(bytes).replace("\r\n", "\n");
+        assert_eq!(normalize(&serialized), normalize(input));
+    }
+
+    #[test]
+    fn untrusted_path_test() {
+        let file = |path: &str| {
+            serde_json::from_value::<File>(serde_json::json!({
+                "Path": path,
+                "Length": 0,
+                "Checksum": "D41D8CD98F00B204E9800998ECF8427E",
+                "Type": "SwiftyFile",
+                "Parts": [],
+            }))
+        };
+
+        assert_eq!(file("addons\\a.pbo").unwrap().path, "addons/a.pbo");
+        assert!(file("/etc/passwd").is_err());
+        assert!(file("C:\\Windows\\a.dll").is_err());
+        assert!(file("addons/../../a.dll").is_err());
+    }
+
+    #[test]
+    fn legacy_srf_read_error_test() {
+        struct Flaky<'a>(&'a [u8]);
+
+        impl Read for Flaky<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+                }
+
+                self.0.read(buf)
+            }
+        }
+
+        // the connection drops right after the first part line
+        let input = include_bytes!("../test_files/legacy_format_mod.srf");
+        let end = input
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w == b"\r\n")
+            .nth(2)
+            .unwrap()
+            .0
+            + 2;
+
+        let mut reader = std::io::BufReader::new(Flaky(&input[..end]));
+        assert!(matches!(
+            deserialize_legacy_srf(&mut reader),
+            Err(Error::Io { .. })
+        ));
     }
 
     #[test]
@@ -513,7 +846,282 @@ mod tests {
 
         assert_eq!(
             r#mod.checksum,
-            Md5Digest::new("787662722D70C36DF28CD1D5EE8D8E86").unwrap()
+            Checksum::new("787662722D70C36DF28CD1D5EE8D8E86").unwrap()
+        );
+    }
+
+    #[test]
+    fn legacy_srf_roundtrip_test() {
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let mut r#mod = scan_mod(
+            &[project_root, "test_files", "@ace"]
+                .iter()
+                .collect::<PathBuf>(),
+        )
+        .unwrap();
+
+        let mut serialized = Vec::new();
+        serialize_legacy_srf(&r#mod, &mut serialized).unwrap();
+
+        let mut cursor = Cursor::new(serialized);
+        assert!(is_legacy_srf(&mut cursor).unwrap());
+        let deserialized = deserialize_legacy_srf(&mut cursor).unwrap();
+
+        // pbo extensions are the one thing the legacy format can't hold
+        assert!(r#mod.files.iter().any(|file| file.extensions.is_some()));
+        for file in &mut r#mod.files {
+            file.extensions = None;
+        }
+
+        assert_eq!(deserialized, r#mod);
+    }
+
+    // builds a mod out of (path, contents) pairs in a tempdir, so fixtures don't bloat the repo
+    fn fixture_mod(files: &[(&str, &[u8])]) -> Mod {
+        fixture_mod_with(files, PboHashing::Raw)
+    }
+
+    fn fixture_mod_with(files: &[(&str, &[u8])], hashing: PboHashing) -> Mod {
+        let dir = tempfile::tempdir().unwrap();
+        let mod_path = dir.path().join("@fixture");
+
+        for (path, contents) in files {
+            let path = mod_path.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        scan_mod_with(&mod_path, hashing).unwrap()
+    }
+
+    fn patterned(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    // a version header, a single "a.txt" entry holding "hello", the terminator and a dummy hash
+    fn single_entry_pbo() -> Vec<u8> {
+        pbo_with_entry(0, 0, b"hello")
+    }
+
+    fn pbo_with_entry(r#type: u32, original_size: u32, data: &[u8]) -> Vec<u8> {
+        let mut pbo = Vec::new();
+
+        let entry = |pbo: &mut Vec<u8>, name: &str, r#type: u32, original_size: u32, data_size| {
+            pbo.extend(name.as_bytes());
+            pbo.push(0);
+            for field in [r#type, original_size, 0, 1_664_293_851, data_size] {
+                pbo.extend(field.to_le_bytes());
+            }
+        };
+
+        entry(&mut pbo, "", 0x56657273, 0, 0);
+        pbo.extend(b"prefix\0fixture\0\0");
+        entry(&mut pbo, "a.txt", r#type, original_size, data.len() as u32);
+        entry(&mut pbo, "", 0, 0, 0);
+        pbo.extend(data);
+        pbo.push(0);
+        pbo.extend([0; 20]);
+
+        pbo
+    }
+
+    #[test]
+    fn truncated_pbo_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("truncated.pbo");
+
+        // cut off in the middle of the entry data
+        let pbo = single_entry_pbo();
+        std::fs::write(&path, &pbo[..pbo.len() - 24]).unwrap();
+
+        assert!(matches!(
+            scan_pbo(&path, dir.path(), Algorithm::Md5),
+            Err(Error::CorruptPbo { detail, .. }) if detail.starts_with("entry a.txt ends at byte")
+        ));
+    }
+
+    #[test]
+    fn part_size_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, patterned(10)).unwrap();
+
+        let file = scan_file_with(&path, dir.path(), 4, Algorithm::Md5).unwrap();
+        let parts: Vec<_> = file
+            .parts
+            .iter()
+            .map(|part| (part.path.as_str(), part.start, part.length))
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                ("data.bin_4", 0, 4),
+                ("data.bin_8", 4, 4),
+                ("data.bin_10", 8, 2)
+            ]
+        );
+
+        assert_eq!(
+            scan_file(&path, dir.path(), Algorithm::Md5).unwrap(),
+            scan_file_with(&path, dir.path(), DEFAULT_PART_SIZE, Algorithm::Md5).unwrap()
+        );
+    }
+
+    #[test]
+    fn scan_failures_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mod_path = dir.path().join("@test");
+        std::fs::create_dir(&mod_path).unwrap();
+        std::fs::write(mod_path.join("readme.txt"), "hello").unwrap();
+
+        let pbo = single_entry_pbo();
+        std::fs::write(mod_path.join("truncated.pbo"), &pbo[..pbo.len() - 24]).unwrap();
+
+        // the error has to say which file it was about
+        assert!(matches!(
+            scan_mod(&mod_path),
+            Err(Error::FileScan { path, .. }) if path == mod_path.join("truncated.pbo")
+        ));
+
+        let options = ScanOptions {
+            max_failures: 1,
+            ..ScanOptions::default()
+        };
+        let (r#mod, warnings) = scan_mod_with_options(&mod_path, &options).unwrap();
+        assert_eq!(r#mod.files.len(), 1);
+        assert_eq!(r#mod.files[0].path, "readme.txt");
+        assert!(matches!(
+            warnings.as_slice(),
+            [Warning::SkippedFile {
+                source: Error::FileScan { .. }
+            }]
+        ));
+    }
+
+    #[test]
+    fn decompressed_hashing_test() {
+        // "abc" as literals, 9 bytes from 3 back and the checksum
+        let compressed = [0x07, b'a', b'b', b'c', 0x03, 0x06, 0, 0, 0, 0];
+        let pbo = pbo_with_entry(0x43707273, 12, &compressed);
+
+        let raw = fixture_mod(&[("compressed.pbo", &pbo)]);
+        assert_eq!(
+            raw.files[0].parts[1].checksum,
+            Algorithm::Md5.digest(compressed)
+        );
+
+        let decompressed = fixture_mod_with(&[("compressed.pbo", &pbo)], PboHashing::Decompressed);
+        let part = &decompressed.files[0].parts[1];
+        assert_eq!(part.checksum, Algorithm::Md5.digest(b"abcabcabcabc"));
+        // offsets still describe the file on disk
+        assert_eq!(part.length, compressed.len() as u64);
+        assert_eq!(
+            decompressed.files[0].parts[2].start,
+            raw.files[0].parts[2].start
+        );
+
+        // uncompressed entries hash the same either way
+        let pbo = single_entry_pbo();
+        let plain = [("plain.pbo", pbo.as_slice())];
+        assert_eq!(
+            fixture_mod(&plain).checksum,
+            fixture_mod_with(&plain, PboHashing::Decompressed).checksum
+        );
+    }
+
+    #[test]
+    fn algorithm_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mod_path = dir.path().join("@fixture");
+        std::fs::create_dir(&mod_path).unwrap();
+        std::fs::write(mod_path.join("a.txt"), "hello").unwrap();
+        std::fs::write(mod_path.join("b.pbo"), single_entry_pbo()).unwrap();
+
+        let options = ScanOptions {
+            algorithm: Algorithm::Sha256,
+            ..ScanOptions::default()
+        };
+        let (sha256, _) = scan_mod_with_options(&mod_path, &options).unwrap();
+
+        assert_eq!(sha256.algorithm, Algorithm::Sha256);
+        assert_eq!(sha256.checksum.algorithm(), Algorithm::Sha256);
+        assert_eq!(
+            sha256.files[0].parts[0].checksum.to_hex_lower(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(
+            sha256.files[1].parts[1].checksum,
+            sha256.files[0].parts[0].checksum
+        );
+
+        // only non-md5 srfs say which algorithm they use
+        let json = serde_json::to_value(&sha256).unwrap();
+        assert_eq!(json["Algorithm"], "SHA256");
+        assert_eq!(serde_json::from_value::<Mod>(json).unwrap(), sha256);
+
+        let md5 = scan_mod(&mod_path).unwrap();
+        assert!(serde_json::to_value(&md5)
+            .unwrap()
+            .get("Algorithm")
+            .is_none());
+        assert_eq!(md5.algorithm, Algorithm::Md5);
+    }
+
+    #[test]
+    fn checksum_stability_test() {
+        let empty = fixture_mod(&[("empty.txt", b"")]);
+        assert!(empty.files[0].parts.is_empty());
+        assert_eq!(
+            empty.files[0].checksum.to_string(),
+            "D41D8CD98F00B204E9800998ECF8427E"
+        );
+        assert_eq!(
+            empty.checksum,
+            Checksum::new("FCF307C890D49E584048143012D52C0B").unwrap()
+        );
+
+        let boundary = fixture_mod(&[
+            ("exact.bin", &patterned(5_000_000)),
+            ("over.bin", &patterned(5_000_001)),
+        ]);
+        assert_eq!(boundary.files[0].parts.len(), 1);
+        assert_eq!(boundary.files[1].parts.len(), 2);
+        assert_eq!(boundary.files[1].parts[1].length, 1);
+        assert_eq!(
+            boundary.files[0].checksum.to_string(),
+            "DDE5991FC39B3F986341A5ED228E9C73"
+        );
+        assert_eq!(
+            boundary.files[1].checksum.to_string(),
+            "69CA8A35BEFC0EEC63BA32F6916647FF"
+        );
+        assert_eq!(
+            boundary.checksum,
+            Checksum::new("30F7E82DEAAB3AF914DC77D7A2E6E396").unwrap()
+        );
+
+        let pbo = fixture_mod(&[("addons/single.pbo", &single_entry_pbo())]);
+        let parts: Vec<_> = pbo.files[0].parts.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(parts, ["$$HEADER$$", "a.txt", "$$END$$"]);
+        assert_eq!(
+            pbo.files[0].parts[1].checksum.to_string(),
+            "5D41402ABC4B2A76B9719D911017C592"
+        );
+        assert_eq!(
+            pbo.files[0].checksum.to_string(),
+            "E7A6109618513BE9051344AF121EACBC"
+        );
+        assert_eq!(
+            pbo.checksum,
+            Checksum::new("AB824D241B95D53E152C169F6C71687D").unwrap()
+        );
+
+        // names are lowercased before hashing, which has to hold for non-ascii ones too
+        let unicode = fixture_mod(&[("Ñandú/Ção.txt", b"unicode")]);
+        assert_eq!(unicode.files[0].path, "Ñandú/Ção.txt");
+        assert_eq!(
+            unicode.checksum,
+            Checksum::new("C5EF41A750B791D0B98717F7975632BA").unwrap()
         );
     }
 }
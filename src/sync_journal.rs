@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to open sync journal: {}", source))]
+    FileOpen { source: std::io::Error },
+    #[snafu(display("failed to create sync journal: {}", source))]
+    FileCreation { source: std::io::Error },
+    #[snafu(display("failed to write sync journal: {}", source))]
+    FileWrite { source: std::io::Error },
+    #[snafu(display("failed to persist sync journal: {}", source))]
+    FilePersist { source: tempfile::PersistError },
+    #[snafu(display("failed to remove sync journal: {}", source))]
+    FileRemove { source: std::io::Error },
+    #[snafu(display("serde failed to serialize: {}", source))]
+    Serialization { source: serde_json::Error },
+    #[snafu(display("serde failed to deserialize: {}", source))]
+    Deserialization { source: serde_json::Error },
+}
+
+// the journal lives next to the cache, like the partial download record
+pub fn path(cache_path: &Path) -> PathBuf {
+    cache_path.with_file_name("nimble-journal.json")
+}
+
+// the mods a sync is about to change, written before it removes or downloads anything and removed
+// once the cache describes the result. finding one means the sync that wrote it never got that
+// far, so the cache can't be trusted for the mods it lists. those are rescanned rather than
+// replayed: whatever downloads or removals didn't happen show up in the next diff anyway
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    pub mods: BTreeSet<String>,
+}
+
+impl Journal {
+    pub fn from_disk(path: &Path) -> Result<Option<Self>, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::FileOpen { source: e }),
+        };
+
+        serde_json::from_reader(BufReader::new(file)).context(DeserializationSnafu)
+    }
+
+    pub fn to_disk(&self, path: &Path) -> Result<(), Error> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut writer = BufWriter::new(NamedTempFile::new_in(dir).context(FileCreationSnafu)?);
+
+        serde_json::to_writer(&mut writer, self).context(SerializationSnafu)?;
+        writer.flush().context(FileWriteSnafu)?;
+
+        let file = writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context(FileWriteSnafu)?;
+        // the journal is only worth anything if it hits the disk before the changes it describes
+        file.as_file().sync_all().context(FileWriteSnafu)?;
+        file.persist(path).context(FilePersistSnafu)?;
+
+        Ok(())
+    }
+}
+
+pub fn remove(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::FileRemove { source: e }),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,125 @@
+use crate::commands::sync::{Error, SyncObserver};
+use crate::{interrupt, output};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use log::info;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+// what sync shows on a terminal: the overall progress, with a bar for every file being downloaded
+// below it. ctrl-c cancels
+pub struct ProgressBars {
+    multi_progress: MultiProgress,
+    total: OnceLock<ProgressBar>,
+    files: Mutex<HashMap<String, ProgressBar>>,
+    downloads: AtomicUsize,
+    done: AtomicUsize,
+    // failures are already on stdout with json output
+    format: output::Format,
+}
+
+impl ProgressBars {
+    pub fn new(format: output::Format) -> Self {
+        let multi_progress = if output::is_quiet() {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+
+        Self {
+            multi_progress,
+            total: OnceLock::new(),
+            files: Mutex::new(HashMap::new()),
+            downloads: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            format,
+        }
+    }
+
+    fn create_file_bar(&self, file: &str, size: u64) -> ProgressBar {
+        let pb = self.multi_progress.add(ProgressBar::new(size));
+        pb.set_message(file.rsplit('/').next().unwrap_or(file).to_string());
+
+        pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {wide_msg}")
+            .unwrap()
+            .with_key("eta", |state: &ProgressState, w: &mut dyn std::fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+            .progress_chars("#>-"));
+
+        pb
+    }
+}
+
+impl SyncObserver for ProgressBars {
+    fn on_diff_complete(&self, downloads: usize, bytes: u64) {
+        self.downloads.store(downloads, Ordering::Relaxed);
+
+        let pb = self.multi_progress.add(ProgressBar::new(bytes));
+
+        pb.set_style(
+            ProgressStyle::with_template(
+                "  [{elapsed_precise}] [{bar:40.green/white}] {bytes}/{total_bytes} {wide_msg}",
+            )
+            .unwrap()
+            .progress_chars("#>-"),
+        );
+        pb.set_message(format!("0 of {downloads} files"));
+
+        let _ = self.total.set(pb);
+    }
+
+    fn on_file_start(&self, file: &str, size: u64, downloaded: u64) {
+        let mut files = self.files.lock().unwrap();
+        let pb = files
+            .entry(file.to_string())
+            .or_insert_with(|| self.create_file_bar(file, size));
+
+        // a download that had to start over counts from scratch
+        pb.set_position(downloaded);
+    }
+
+    fn on_bytes(&self, file: &str, delta: u64) {
+        if let Some(pb) = self.files.lock().unwrap().get(file) {
+            pb.inc(delta);
+        }
+    }
+
+    fn on_file_done(&self, file: &str, size: u64, error: Option<&Error>) {
+        if let Some(pb) = self.files.lock().unwrap().remove(file) {
+            pb.finish_and_clear();
+        }
+
+        match error {
+            None => {
+                let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+                let downloads = self.downloads.load(Ordering::Relaxed);
+
+                if let Some(total) = self.total.get() {
+                    total.inc(size);
+                    total.set_message(format!("{done} of {downloads} files"));
+                }
+
+                self.multi_progress
+                    .suspend(|| info!("downloaded {done} of {downloads} - {file}"));
+            }
+            Some(Error::Interrupted) => {}
+            Some(_) if self.format == output::Format::Json => {}
+            Some(e) => self
+                .multi_progress
+                .suspend(|| eprintln!("failed to download {file}: {e}")),
+        }
+    }
+
+    fn on_downloads_finished(&self) {
+        if let Some(total) = self.total.get() {
+            total.finish_and_clear();
+        }
+    }
+
+    fn suspend(&self, log: &mut dyn FnMut()) {
+        self.multi_progress.suspend(log)
+    }
+
+    fn should_cancel(&self) -> bool {
+        interrupt::is_interrupted()
+    }
+}
@@ -0,0 +1,92 @@
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+// a tiny http server for tests, serving fixed bodies by path. it understands just enough of
+// Range and If-Range to test resuming and patching, using the md5 of each body as its ETag. returns the base
+// url, with a trailing slash like repository urls usually have
+pub fn serve(files: HashMap<String, Vec<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            // a broken connection only fails the request it carried, the test will notice that
+            let _ = stream.map(|stream| respond(stream, &files));
+        }
+    });
+
+    url
+}
+
+fn respond(mut stream: TcpStream, files: &HashMap<String, Vec<u8>>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut headers = HashMap::new();
+
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+
+        match line.trim_end().split_once(": ") {
+            Some((name, value)) => headers.insert(name.to_lowercase(), value.to_string()),
+            None => break,
+        };
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    // callers aren't consistent about joining with slashes, so neither are we
+    let path = parts.next().unwrap_or_default().trim_start_matches('/');
+
+    let Some(body) = files.get(path) else {
+        write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )?;
+        return stream.flush();
+    };
+
+    let etag = format!("\"{}\"", hex::encode(Md5::digest(body)));
+
+    // bytes=<start>- or bytes=<start>-<last>
+    let range = headers
+        .get("range")
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(start, last)| {
+            let start = start.parse::<usize>().ok()?;
+            let end = match last {
+                "" => body.len(),
+                last => last.parse::<usize>().ok()? + 1,
+            };
+
+            Some(start.min(body.len())..end.min(body.len()))
+        })
+        .filter(|_| {
+            headers
+                .get("if-range")
+                .is_none_or(|validator| *validator == etag)
+        });
+
+    let (status, body) = match range {
+        Some(range) => ("206 Partial Content", &body[range]),
+        None => ("200 OK", body.as_slice()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nETag: {etag}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+
+    if method != "HEAD" {
+        stream.write_all(body)?;
+    }
+
+    stream.flush()
+}